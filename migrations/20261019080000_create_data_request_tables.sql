@@ -0,0 +1,21 @@
+-- Add migration script here
+-- Single-use links emailed to subscribers asking for an export or an erasure of their data.
+CREATE TABLE data_request_tokens (
+    data_request_token TEXT NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (data_request_token)
+);
+
+-- Minimal record of every export or erasure we performed.
+-- It deliberately keeps no contact details of the subscriber.
+CREATE TABLE data_request_audit_log (
+    id uuid NOT NULL,
+    subscriber_id uuid NOT NULL,
+    kind TEXT NOT NULL,
+    -- NULL when the subscriber made the request themselves.
+    requested_by uuid NULL REFERENCES users (user_id),
+    performed_at timestamptz NOT NULL,
+    PRIMARY KEY (id)
+);
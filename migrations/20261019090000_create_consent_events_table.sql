@@ -0,0 +1,27 @@
+-- Add migration script here
+-- Every time someone gives (signup) or confirms (confirmation) their consent.
+CREATE TABLE consent_events (
+    id uuid NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    client_ip TEXT NULL,
+    user_agent TEXT NULL,
+    -- The form the subscriber used, when it told us.
+    source TEXT NULL,
+    consent_text_version TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+
+-- The trail is append-only, a recorded event can never be edited.
+-- Rows are only deleted together with their subscriber, when honouring an erasure request.
+CREATE FUNCTION reject_consent_event_update() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'consent_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER consent_events_append_only
+    BEFORE UPDATE ON consent_events
+    FOR EACH ROW EXECUTE FUNCTION reject_consent_event_update();
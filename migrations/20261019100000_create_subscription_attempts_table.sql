@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Recent attempts to subscribe, used to rate limit `POST /subscriptions`
+-- per client IP and per target email.
+CREATE TABLE subscription_attempts (
+    email TEXT NOT NULL,
+    client_ip TEXT NULL,
+    attempted_at timestamptz NOT NULL
+);
+CREATE INDEX subscription_attempts_attempted_at_idx ON subscription_attempts (attempted_at);
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Where to warn the account owner, e.g. about a lockout.
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+-- Consecutive failures since the last successful login, and the lockouts they triggered.
+ALTER TABLE users ADD COLUMN failed_login_attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN lockouts INT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until timestamptz NULL;
+
+CREATE TABLE login_attempts (
+    id uuid PRIMARY KEY,
+    -- NULL when the username doesn't belong to any user.
+    user_id uuid NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    client_ip TEXT NULL,
+    attempted_at timestamptz NOT NULL,
+    outcome TEXT NOT NULL
+);
+CREATE INDEX login_attempts_attempted_at_idx ON login_attempts (attempted_at);
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Deactivated users keep their account but can't log in.
+ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;
+
+-- Single-use links emailed to people invited to become admins.
+CREATE TABLE user_invitations (
+    invitation_token TEXT NOT NULL,
+    email TEXT NOT NULL,
+    invited_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (invitation_token)
+);
+
+-- Deleting a user drops their saved responses, while audit entries outlive them.
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_user_id_fkey;
+ALTER TABLE idempotency ADD CONSTRAINT idempotency_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE;
+ALTER TABLE data_request_audit_log DROP CONSTRAINT data_request_audit_log_requested_by_fkey;
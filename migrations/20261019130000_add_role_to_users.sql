@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Existing users could do everything so far, they keep doing so as owners.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+-- The role the invited user gets once they accept.
+ALTER TABLE user_invitations ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
+ALTER TABLE user_invitations ALTER COLUMN role DROP DEFAULT;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Only a hash of the token is kept, so a leaked table can't be used to reset passwords.
+CREATE TABLE password_reset_tokens (
+    token_hash TEXT NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (token_hash)
+);
+
+-- Sessions started before this point in time are no longer valid.
+ALTER TABLE users ADD COLUMN sessions_revoked_at timestamptz NULL;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- TOTP secrets are encrypted by the application, the database never sees them in clear.
+ALTER TABLE users ADD COLUMN totp_secret BYTEA NULL;
+-- The secret is only used at login once the user proved they can generate codes.
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+-- Time step of the last accepted code, so a code can't be replayed.
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+-- Single-use codes to log in without the authenticator, stored hashed.
+CREATE TABLE totp_recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
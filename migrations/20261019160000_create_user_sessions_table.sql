@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Every logged in session, so users can see where they are logged in and end sessions
+-- remotely. A session whose row is gone is no longer valid.
+-- `users.sessions_revoked_at` keeps recording when all of them were last revoked.
+CREATE TABLE user_sessions (
+    session_id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    client_ip TEXT NULL,
+    user_agent TEXT NULL,
+    PRIMARY KEY (session_id)
+);
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
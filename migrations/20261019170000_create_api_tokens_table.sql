@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Personal tokens for programmatic access, stored hashed like reset tokens.
+CREATE TABLE api_tokens (
+    token_id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    -- What the token may be used for, see `authorization::Scope`.
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL,
+    PRIMARY KEY (token_id)
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Issues without a publication date are drafts.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+-- How many deliveries were queued when the issue was published.
+ALTER TABLE newsletter_issues ADD COLUMN recipients_count INTEGER NULL;
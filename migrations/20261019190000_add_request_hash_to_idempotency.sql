@@ -0,0 +1,8 @@
+-- Keys reused for another request are rejected instead of replaying the first response.
+-- Keys saved before this have no hash and are never rejected.
+ALTER TABLE idempotency ADD COLUMN request_hash TEXT NULL;
+-- Expired keys are deleted by age.
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
+-- Names the request holding the key: one whose claim was taken over can't save its
+-- response over the new holder's.
+ALTER TABLE idempotency ADD COLUMN claim_id uuid NULL;
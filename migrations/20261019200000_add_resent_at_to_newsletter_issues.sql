@@ -0,0 +1,2 @@
+-- Resending an issue reaches the subscribers who joined since it was last sent.
+ALTER TABLE newsletter_issues ADD COLUMN resent_at timestamptz NULL;
@@ -0,0 +1,5 @@
+-- The worker only delivers issues whose delivery is active.
+ALTER TABLE newsletter_issues ADD COLUMN delivery_state TEXT NOT NULL DEFAULT 'active'
+    CHECK (delivery_state IN ('active', 'paused', 'cancelled'));
+-- How many deliveries had been made when the issue was cancelled.
+ALTER TABLE newsletter_issues ADD COLUMN sent_count INTEGER NULL;
@@ -0,0 +1,136 @@
+use super::hash_token;
+use crate::authorization::Scope;
+use crate::routes::subscriptions::generate_subscription_token;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Makes tokens easy to spot, e.g. by secret scanners.
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// An API token, as listed to its owner.
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The owner of a valid API token and what the token may do.
+pub struct ApiTokenOwner {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<Scope>,
+}
+
+/// Create a token for `user_id`, returning it in clear.
+///
+/// Only a hash is kept, so this is the one time the token can be shown.
+#[tracing::instrument(name = "Create an API token", skip(pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scopes: &[Scope],
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let token = format!("{TOKEN_PREFIX}{}", generate_subscription_token());
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an API token.")?;
+    Ok(token)
+}
+
+/// The tokens of `user_id`, most recently created first.
+#[tracing::instrument(name = "Get the API tokens of a user", skip(pool))]
+pub async fn get_api_tokens(user_id: Uuid, pool: &PgPool) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT token_id, name, scopes, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the API tokens of a user.")?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(ApiToken {
+                token_id: r.token_id,
+                name: r.name,
+                scopes: parse_scopes(r.scopes)?,
+                created_at: r.created_at,
+                last_used_at: r.last_used_at,
+            })
+        })
+        .collect()
+}
+
+/// Revoke a token of `user_id`, returning `false` if there was no such token.
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM api_tokens WHERE user_id = $1 AND token_id = $2"#,
+        user_id,
+        token_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up the owner of `token`, noting that the token was used.
+#[tracing::instrument(name = "Authenticate an API token", skip(token, pool))]
+pub async fn authenticate_api_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<Option<ApiTokenOwner>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1
+        RETURNING token_id, user_id, scopes
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an API token.")?;
+    row.map(|r| {
+        Ok(ApiTokenOwner {
+            token_id: r.token_id,
+            user_id: r.user_id,
+            scopes: parse_scopes(r.scopes)?,
+        })
+    })
+    .transpose()
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Result<Vec<Scope>, anyhow::Error> {
+    scopes
+        .into_iter()
+        .map(|s| Scope::try_from(s).map_err(anyhow::Error::msg))
+        .collect()
+}
@@ -0,0 +1,344 @@
+use super::{validate_credentials, AuthError, Credentials};
+use crate::configuration::{LockoutSettings, PasswordHashingSettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LoginOutcome {
+    Success,
+    InvalidCredentials,
+    LockedOut,
+    Deactivated,
+}
+
+impl LoginOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Success => "success",
+            LoginOutcome::InvalidCredentials => "invalid_credentials",
+            LoginOutcome::LockedOut => "locked_out",
+            LoginOutcome::Deactivated => "deactivated",
+        }
+    }
+}
+
+pub struct LoginAttempt {
+    pub username: String,
+    pub client_ip: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+    pub outcome: String,
+}
+
+/// Validate `credentials` for a login, keeping track of failures.
+///
+/// Every attempt is recorded in `login_attempts`. After
+/// `max_failed_attempts` consecutive failures the account is locked for a cooldown
+/// that doubles with every lockout, and its owner gets an email about it. Logins to a
+/// locked account fail like any other, only `login_attempts` records why.
+///
+/// A correct password doesn't clear the failures: call [`reset_failed_logins`] once the
+/// session is established, i.e. after the second factor if there is one.
+#[tracing::instrument(
+    name = "Attempt login",
+    skip(
+        credentials,
+        pool,
+        email_client,
+        settings,
+        hashing,
+        dummy_password_hash
+    )
+)]
+pub async fn attempt_login(
+    credentials: Credentials,
+    client_ip: Option<String>,
+    settings: &LockoutSettings,
+    hashing: &PasswordHashingSettings,
+    dummy_password_hash: &Secret<String>,
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<Uuid, AuthError> {
+    let username = credentials.username.clone();
+    let user = get_login_state(&username, pool).await?;
+    let user_id = user.as_ref().map(|u| u.user_id);
+    let active = user.as_ref().map(|u| u.active).unwrap_or_default();
+    let locked = user
+        .as_ref()
+        .and_then(|u| u.locked_until)
+        .is_some_and(|locked_until| locked_until > Utc::now());
+
+    // The password is checked even for locked accounts: neither the response nor its
+    // timing should tell a locked account apart from a wrong password.
+    let validation = validate_credentials(credentials, hashing, dummy_password_hash, pool).await;
+    if locked {
+        if let Err(AuthError::UnexpectedError(e)) = validation {
+            return Err(AuthError::UnexpectedError(e));
+        }
+        record_login_attempt(
+            user_id,
+            &username,
+            client_ip.as_deref(),
+            LoginOutcome::LockedOut,
+            pool,
+        )
+        .await?;
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The account is locked."
+        )));
+    }
+
+    match validation {
+        // Deactivated users are told the same as anyone getting their password wrong.
+        Ok(user_id) if !active => {
+            record_login_attempt(
+                Some(user_id),
+                &username,
+                client_ip.as_deref(),
+                LoginOutcome::Deactivated,
+                pool,
+            )
+            .await?;
+            Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                "The user is deactivated."
+            )))
+        }
+        Ok(user_id) => {
+            record_login_attempt(
+                Some(user_id),
+                &username,
+                client_ip.as_deref(),
+                LoginOutcome::Success,
+                pool,
+            )
+            .await?;
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            record_login_attempt(
+                user_id,
+                &username,
+                client_ip.as_deref(),
+                LoginOutcome::InvalidCredentials,
+                pool,
+            )
+            .await?;
+            if let Some(user_id) = user_id {
+                if let Some(locked_until) = register_failed_login(user_id, settings, pool).await? {
+                    async_std::task::spawn(notify_lockout(
+                        user_id,
+                        locked_until,
+                        pool.clone(),
+                        email_client.clone(),
+                    ));
+                }
+            }
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+struct LoginState {
+    user_id: Uuid,
+    active: bool,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get login state", skip(username, pool))]
+async fn get_login_state(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<LoginState>, anyhow::Error> {
+    let state = sqlx::query_as!(
+        LoginState,
+        r#"SELECT user_id, active, locked_until FROM users WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the login state of a user.")?;
+    Ok(state)
+}
+
+#[tracing::instrument(name = "Record login attempt", skip(username, pool))]
+async fn record_login_attempt(
+    user_id: Option<Uuid>,
+    username: &str,
+    client_ip: Option<&str>,
+    outcome: LoginOutcome,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (id, user_id, username, client_ip, attempted_at, outcome)
+        VALUES ($1, $2, $3, $4, now(), $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        username,
+        client_ip,
+        outcome.as_str()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a login attempt.")?;
+    Ok(())
+}
+
+/// Clear the failed logins of `user_id`, and any lock they caused.
+#[tracing::instrument(name = "Reset failed logins", skip(pool))]
+pub async fn reset_failed_logins(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_login_attempts = 0, lockouts = 0, locked_until = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reset failed logins.")?;
+    Ok(())
+}
+
+/// Count a failed login, locking the account once there were too many in a row.
+///
+/// Returns when the lock ends if this failure triggered one.
+#[tracing::instrument(name = "Register failed login", skip(settings, pool))]
+async fn register_failed_login(
+    user_id: Uuid,
+    settings: &LockoutSettings,
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_login_attempts = failed_login_attempts + 1
+        WHERE user_id = $1
+        RETURNING failed_login_attempts, lockouts
+        "#,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to count a failed login.")?;
+    let mut locked_until = None;
+    if row.failed_login_attempts >= settings.max_failed_attempts {
+        let cooldown = chrono::Duration::from_std(settings.cooldown(row.lockouts))
+            .context("Lockout cooldown out of range.")?;
+        let until = Utc::now() + cooldown;
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET failed_login_attempts = 0, lockouts = lockouts + 1, locked_until = $2
+            WHERE user_id = $1
+            "#,
+            user_id,
+            until
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to lock a user out.")?;
+        locked_until = Some(until);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to register a failed login.")?;
+    Ok(locked_until)
+}
+
+/// Let the account owner know it was locked. Failures are logged, not surfaced.
+#[tracing::instrument(name = "Notify lockout", skip(pool, email_client))]
+async fn notify_lockout(
+    user_id: Uuid,
+    locked_until: DateTime<Utc>,
+    pool: PgPool,
+    email_client: EmailClient,
+) {
+    if let Err(e) = try_notify_lockout(user_id, locked_until, &pool, &email_client).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to notify a user about a lockout."
+        );
+    }
+}
+
+async fn try_notify_lockout(
+    user_id: Uuid,
+    locked_until: DateTime<Utc>,
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<(), anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT username, email FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the email of a user.")?;
+    let email = match row.email {
+        Some(email) => email,
+        None => {
+            tracing::warn!("The locked out user has no email address.");
+            return Ok(());
+        }
+    };
+    let recipient = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+    let locked_until = locked_until.to_rfc2822();
+    let html_body = format!(
+        "Your account {} was locked after too many failed login attempts.<br />\
+        You can log in again after {locked_until}.<br />\
+        If these attempts weren't yours, change your password once you can log in.",
+        row.username
+    );
+    let text_body = format!(
+        "Your account {} was locked after too many failed login attempts.\n\
+        You can log in again after {locked_until}.\n\
+        If these attempts weren't yours, change your password once you can log in.",
+        row.username
+    );
+    email_client
+        .send_email(
+            &recipient,
+            "Your account was locked",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .map_err(|e| e.into_inner())
+        .context("Failed to send the lockout notification.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get recent login attempts", skip(pool))]
+pub async fn get_recent_login_attempts(
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<LoginAttempt>, anyhow::Error> {
+    let attempts = sqlx::query_as!(
+        LoginAttempt,
+        r#"
+        SELECT username, client_ip, attempted_at, outcome
+        FROM login_attempts
+        ORDER BY attempted_at DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch recent login attempts.")?;
+    Ok(attempts)
+}
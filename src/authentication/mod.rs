@@ -0,0 +1,325 @@
+mod api_tokens;
+mod lockout;
+mod password_reset;
+mod sessions;
+mod two_factor;
+
+use crate::authorization::Role;
+use crate::configuration::PasswordHashingSettings;
+use crate::domain::NewPassword;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Digest;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub use api_tokens::{
+    authenticate_api_token, create_api_token, get_api_tokens, revoke_api_token, ApiToken,
+    ApiTokenOwner,
+};
+pub use lockout::{
+    attempt_login, get_recent_login_attempts, reset_failed_logins, LoginAttempt, LoginOutcome,
+};
+pub use password_reset::{
+    finish_password_reset, get_password_reset_user, start_password_reset, PasswordReset,
+};
+pub use sessions::{
+    delete_expired_sessions, get_user_sessions, revoke_other_sessions, revoke_session,
+    start_session, touch_session, UserSession,
+};
+pub use two_factor::{
+    confirm_two_factor, disable_two_factor, get_two_factor_status, is_two_factor_enabled,
+    start_two_factor_enrollment, totp_code, verify_second_factor, TwoFactorStatus,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Check `credentials`, upgrading the stored hash in the background if it is weaker
+/// than what `hashing` asks for.
+///
+/// Unknown usernames are checked against `dummy_password_hash`, made with `hashing`
+/// too, so that they take as long to turn away as wrong passwords.
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, hashing, dummy_password_hash, pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    hashing: &PasswordHashingSettings,
+    dummy_password_hash: &Secret<String>,
+    pool: &PgPool,
+) -> std::result::Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = dummy_password_hash.clone();
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(AuthError::UnexpectedError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await?;
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+    if needs_rehash(stored_password_hash.expose_secret(), hashing) {
+        async_std::task::spawn(rehash_password(
+            user_id,
+            stored_password_hash,
+            password,
+            *hashing,
+            pool.clone(),
+        ));
+    }
+    Ok(user_id)
+}
+
+/// Whether `password_hash` is cheaper to crack than hashes made with `hashing`.
+fn needs_rehash(password_hash: &str, hashing: &PasswordHashingSettings) -> bool {
+    let password_hash = match PasswordHash::new(password_hash) {
+        Ok(password_hash) => password_hash,
+        // Only hashes we managed to verify make it here.
+        Err(_) => return false,
+    };
+    let params = match Params::try_from(&password_hash) {
+        Ok(params) => params,
+        Err(_) => return true,
+    };
+    password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+        || params.m_cost() < hashing.memory_cost
+        || params.t_cost() < hashing.iterations
+        || params.p_cost() < hashing.parallelism
+}
+
+/// Replace the stored hash of `user_id`, unless the password changed in the meantime.
+#[tracing::instrument(
+    name = "Rehash password",
+    skip(previous_password_hash, password, hashing, pool)
+)]
+async fn rehash_password(
+    user_id: Uuid,
+    previous_password_hash: Secret<String>,
+    password: Secret<String>,
+    hashing: PasswordHashingSettings,
+    pool: PgPool,
+) {
+    let result: Result<(), anyhow::Error> = async {
+        let password_hash =
+            spawn_blocking_with_tracing(move || compute_password_hash(password, hashing))
+                .await
+                .context("Failed to hash password")?;
+        sqlx::query!(
+            r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+            password_hash.expose_secret(),
+            user_id,
+            previous_password_hash.expose_secret()
+        )
+        .execute(&pool)
+        .await
+        .context("Failed to store the upgraded password hash.")?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to upgrade a password hash."
+        );
+    }
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+pub fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> std::result::Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")
+        .map_err(AuthError::UnexpectedError)?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, executor))]
+pub async fn change_password<'a, E>(
+    user_id: Uuid,
+    password: NewPassword,
+    hashing: &PasswordHashingSettings,
+    executor: E,
+) -> Result<(), anyhow::Error>
+where
+    E: sqlx::Executor<'a, Database = Postgres>,
+{
+    let password = password.into_secret();
+    let hashing = *hashing;
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, hashing))
+            .await
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+/// Create a user who can log in with `username` and `password`.
+#[tracing::instrument(name = "Create user", skip(transaction, password, hashing))]
+pub async fn create_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    email: &str,
+    role: Role,
+    password: NewPassword,
+    hashing: &PasswordHashingSettings,
+) -> Result<Uuid, anyhow::Error> {
+    let password = password.into_secret();
+    let hashing = *hashing;
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, hashing))
+            .await
+            .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email, role)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        email,
+        role.as_str()
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store a new user in the database.")?;
+    Ok(user_id)
+}
+
+/// Tokens we hand out are random enough that a plain hash keeps them safe at rest.
+fn hash_token(token: &str) -> String {
+    hex::encode(sha2::Sha256::digest(token.as_bytes()))
+}
+
+/// A hash of a random password, for unknown usernames to be checked against.
+pub fn dummy_password_hash(
+    hashing: PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
+    compute_password_hash(Secret::new(Uuid::new_v4().to_string()), hashing)
+}
+
+fn compute_password_hash(
+    password: Secret<String>,
+    hashing: PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+pub async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> std::result::Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row: Option<_> = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dummy_password_hash, needs_rehash};
+    use crate::configuration::PasswordHashingSettings;
+    use secrecy::ExposeSecret;
+
+    const HASHING: PasswordHashingSettings = PasswordHashingSettings {
+        memory_cost: 15000,
+        iterations: 2,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn hashes_with_the_configured_parameters_are_kept() {
+        let password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
+            gZiV/M1gPc22ElAH/Jh1Hw$\
+            CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+        assert!(!needs_rehash(password_hash, &HASHING));
+    }
+
+    #[test]
+    fn hashes_with_a_weaker_algorithm_or_parameters_are_upgraded() {
+        for password_hash in [
+            "$argon2id$v=19$m=4096,t=3,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=15000,t=1,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2i$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=16$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        ] {
+            assert!(needs_rehash(password_hash, &HASHING), "{password_hash}");
+        }
+    }
+
+    #[test]
+    fn the_dummy_hash_follows_the_configured_parameters() {
+        let stronger = PasswordHashingSettings {
+            memory_cost: 19456,
+            ..HASHING
+        };
+        let password_hash = dummy_password_hash(stronger).unwrap();
+        assert!(password_hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert!(!needs_rehash(password_hash.expose_secret(), &stronger));
+    }
+}
@@ -0,0 +1,95 @@
+use super::hash_token;
+use super::sessions::revoke_all_sessions;
+use crate::routes::subscriptions::generate_subscription_token;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// A reset token ready to be emailed to the owner of an account.
+pub struct PasswordReset {
+    pub token: String,
+    pub email: String,
+}
+
+/// Issue a reset token for `username`.
+///
+/// Returns `None` if there is no active user with an email address by that name,
+/// callers must not let the difference show.
+#[tracing::instrument(name = "Start password reset", skip(pool))]
+pub async fn start_password_reset(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<PasswordReset>, anyhow::Error> {
+    let user = sqlx::query!(
+        r#"SELECT user_id, email FROM users WHERE username = $1 AND active"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the user asking for a password reset.")?;
+    let (user_id, email) = match user {
+        Some(row) => match row.email {
+            Some(email) => (row.user_id, email),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    let token = generate_subscription_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at)
+        VALUES ($1, $2, now())
+        "#,
+        hash_token(&token),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(Some(PasswordReset { token, email }))
+}
+
+/// The user a reset token was issued for, if it is still valid.
+///
+/// The token stays locked until the end of the transaction `executor` belongs to, so
+/// it can't be used twice.
+#[tracing::instrument(name = "Get password reset user", skip(executor, token))]
+pub async fn get_password_reset_user<'a, E>(
+    executor: E,
+    token: &str,
+) -> Result<Option<Uuid>, anyhow::Error>
+where
+    E: sqlx::Executor<'a, Database = Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens
+        WHERE
+            token_hash = $1 AND
+            created_at > now() - interval '1 hour'
+        FOR UPDATE
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve a password reset token.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Drop every outstanding reset token of `user_id` and log them out everywhere.
+#[tracing::instrument(name = "Finish password reset", skip(transaction))]
+pub async fn finish_password_reset(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete password reset tokens.")?;
+    revoke_all_sessions(transaction, user_id).await
+}
@@ -0,0 +1,167 @@
+use crate::configuration::SessionSettings;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// A logged in session, as listed to its owner.
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Record a new session for `user_id`, returning its id.
+#[tracing::instrument(name = "Start a user session", skip(pool))]
+pub async fn start_session(
+    user_id: Uuid,
+    client_ip: Option<&str>,
+    user_agent: Option<&str>,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, created_at, last_seen_at, client_ip, user_agent)
+        VALUES ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        client_ip,
+        user_agent
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a user session.")?;
+    Ok(session_id)
+}
+
+/// Note activity on a session, returning `false` if it has been revoked.
+#[tracing::instrument(name = "Touch a user session", skip(pool))]
+pub async fn touch_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET last_seen_at = now()
+        WHERE session_id = $1 AND user_id = $2
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the last activity of a user session.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The sessions of `user_id` that are still valid, most recently used first.
+#[tracing::instrument(name = "Get the sessions of a user", skip(settings, pool))]
+pub async fn get_user_sessions(
+    user_id: Uuid,
+    settings: &SessionSettings,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+        SELECT session_id, created_at, last_seen_at, client_ip, user_agent
+        FROM user_sessions
+        WHERE
+            user_id = $1 AND
+            last_seen_at > now() - make_interval(secs => $2) AND
+            created_at > now() - make_interval(secs => $3)
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+        settings.idle_timeout().as_secs_f64(),
+        settings.absolute_timeout().as_secs_f64()
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the sessions of a user.")?;
+    Ok(sessions)
+}
+
+/// Forget the sessions past their idle or absolute timeout, returning how many there were.
+#[tracing::instrument(name = "Delete expired user sessions", skip(settings, pool))]
+pub async fn delete_expired_sessions(
+    settings: &SessionSettings,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE
+            last_seen_at <= now() - make_interval(secs => $1) OR
+            created_at <= now() - make_interval(secs => $2)
+        "#,
+        settings.idle_timeout().as_secs_f64(),
+        settings.absolute_timeout().as_secs_f64()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired user sessions.")?;
+    Ok(result.rows_affected())
+}
+
+/// Revoke one session of `user_id`, returning `false` if there was no such session.
+#[tracing::instrument(name = "Revoke a user session", skip(pool))]
+pub async fn revoke_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE user_id = $1 AND session_id = $2"#,
+        user_id,
+        session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a user session.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke every session of `user_id` but `current_session_id`.
+#[tracing::instrument(name = "Revoke the other sessions of a user", skip(pool))]
+pub async fn revoke_other_sessions(
+    user_id: Uuid,
+    current_session_id: Uuid,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE user_id = $1 AND session_id <> $2"#,
+        user_id,
+        current_session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the other sessions of a user.")?;
+    Ok(result.rows_affected())
+}
+
+/// Revoke every session of `user_id`.
+#[tracing::instrument(name = "Revoke all the sessions of a user", skip(transaction))]
+pub async fn revoke_all_sessions(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM user_sessions WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to revoke the sessions of a user.")?;
+    sqlx::query!(
+        r#"UPDATE users SET sessions_revoked_at = now() WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the revocation of the sessions of a user.")?;
+    Ok(())
+}
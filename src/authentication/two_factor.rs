@@ -0,0 +1,409 @@
+use super::hash_token;
+use crate::configuration::TwoFactorSettings;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{Rng, RngCore};
+use secrecy::ExposeSecret;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tide::http::Url;
+use uuid::Uuid;
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+const NONCE_LEN: usize = 12;
+const RECOVERY_CODES: usize = 10;
+
+pub enum TwoFactorStatus {
+    Disabled,
+    /// Enrollment started, the user still has to enter a first code.
+    Pending {
+        otpauth_uri: String,
+        secret: String,
+    },
+    Enabled {
+        recovery_codes_left: i64,
+    },
+}
+
+/// The RFC 6238 code for `secret` at `unix_time`: HMAC-SHA1, 30 seconds steps, 6 digits.
+pub fn totp_code(secret: &[u8], unix_time: u64) -> String {
+    hotp(secret, unix_time / STEP_SECONDS)
+}
+
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac =
+        <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// The time step `code` was generated in, allowing one step of clock drift either way.
+fn matching_step(secret: &[u8], code: &str, unix_time: u64) -> Option<u64> {
+    let current = unix_time / STEP_SECONDS;
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .find(|step| hotp(secret, *step) == code)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the unix epoch.")
+        .as_secs()
+}
+
+fn otpauth_uri(issuer: &str, username: &str, secret: &[u8]) -> String {
+    let mut uri = Url::parse("otpauth://totp").expect("Failed to parse the otpauth base uri.");
+    uri.path_segments_mut()
+        .expect("otpauth uris have a path")
+        .push(&format!("{issuer}:{username}"));
+    uri.query_pairs_mut()
+        .append_pair("secret", &encode_secret(secret))
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECONDS.to_string());
+    uri.to_string()
+}
+
+fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+fn cipher(settings: &TwoFactorSettings) -> Aes256Gcm {
+    let key = Sha256::digest(settings.encryption_key.expose_secret().as_bytes());
+    Aes256Gcm::new(&key)
+}
+
+/// Encrypt a TOTP secret, the random nonce is stored in front of the ciphertext.
+fn encrypt_secret(secret: &[u8], settings: &TwoFactorSettings) -> Result<Vec<u8>, anyhow::Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(settings)
+        .encrypt(Nonce::from_slice(&nonce), secret)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt a TOTP secret."))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt_secret(stored: &[u8], settings: &TwoFactorSettings) -> Result<Vec<u8>, anyhow::Error> {
+    if stored.len() < NONCE_LEN {
+        anyhow::bail!("The stored TOTP secret is too short.");
+    }
+    let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+    cipher(settings)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt a TOTP secret."))
+}
+
+fn generate_recovery_code() -> String {
+    let chars: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+#[tracing::instrument(name = "Get two-factor status", skip(settings, pool))]
+pub async fn get_two_factor_status(
+    user_id: Uuid,
+    settings: &TwoFactorSettings,
+    pool: &PgPool,
+) -> Result<TwoFactorStatus, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            username,
+            totp_secret,
+            totp_enabled,
+            (SELECT count(*) FROM totp_recovery_codes c WHERE c.user_id = u.user_id)
+                AS "recovery_codes_left!"
+        FROM users u
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the two-factor status of a user.")?;
+    let status = match (row.totp_enabled, row.totp_secret) {
+        (true, _) => TwoFactorStatus::Enabled {
+            recovery_codes_left: row.recovery_codes_left,
+        },
+        (false, Some(stored)) => {
+            let secret = decrypt_secret(&stored, settings)?;
+            TwoFactorStatus::Pending {
+                otpauth_uri: otpauth_uri(&settings.issuer, &row.username, &secret),
+                secret: encode_secret(&secret),
+            }
+        }
+        (false, None) => TwoFactorStatus::Disabled,
+    };
+    Ok(status)
+}
+
+/// Whether logging in as `user_id` takes a second factor.
+#[tracing::instrument(name = "Is two-factor enabled", skip(pool))]
+pub async fn is_two_factor_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_enabled FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch whether a user enabled two-factor authentication.")?;
+    Ok(row.map(|r| r.totp_enabled).unwrap_or_default())
+}
+
+/// Give `user_id` a fresh TOTP secret, which only gets used once confirmed.
+#[tracing::instrument(name = "Start two-factor enrollment", skip(settings, pool))]
+pub async fn start_two_factor_enrollment(
+    user_id: Uuid,
+    settings: &TwoFactorSettings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let mut secret = [0u8; SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_used_step = NULL
+        WHERE user_id = $1 AND NOT totp_enabled
+        "#,
+        user_id,
+        encrypt_secret(&secret, settings)?
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a TOTP secret.")?;
+    Ok(())
+}
+
+/// Turn two-factor authentication on if `code` matches the pending secret.
+///
+/// Returns the recovery codes to show to the user, `None` if the code is wrong.
+#[tracing::instrument(name = "Confirm two-factor", skip(code, settings, pool))]
+pub async fn confirm_two_factor(
+    user_id: Uuid,
+    code: &str,
+    settings: &TwoFactorSettings,
+    pool: &PgPool,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let stored = sqlx::query!(
+        r#"SELECT totp_secret FROM users WHERE user_id = $1 AND NOT totp_enabled FOR UPDATE"#,
+        user_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch a pending TOTP secret.")?
+    .and_then(|r| r.totp_secret);
+    let secret = match stored {
+        Some(stored) => decrypt_secret(&stored, settings)?,
+        None => return Ok(None),
+    };
+    let step = match matching_step(&secret, code.trim(), now()) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled = true, totp_last_used_step = $2 WHERE user_id = $1"#,
+        user_id,
+        step as i64
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication.")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete old recovery codes.")?;
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    for code in &codes {
+        sqlx::query!(
+            r#"INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+            user_id,
+            hash_token(code)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store a recovery code.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(Some(codes))
+}
+
+/// Check the second step of a login: a TOTP code, or else a recovery code.
+///
+/// A TOTP code is accepted once, a recovery code is used up.
+#[tracing::instrument(name = "Verify second factor", skip(code, settings, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    settings: &TwoFactorSettings,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let code = code.trim().to_lowercase();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_used_step
+        FROM users
+        WHERE user_id = $1 AND totp_enabled
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch a TOTP secret.")?;
+    let (stored, last_used_step) = match row {
+        Some(row) => (row.totp_secret, row.totp_last_used_step),
+        None => return Ok(false),
+    };
+    let stored = stored.context("Two-factor authentication is enabled without a secret.")?;
+    let secret = decrypt_secret(&stored, settings)?;
+    if let Some(step) = matching_step(&secret, &code, now()) {
+        if last_used_step.is_none_or(|last| step as i64 > last) {
+            sqlx::query!(
+                r#"UPDATE users SET totp_last_used_step = $2 WHERE user_id = $1"#,
+                user_id,
+                step as i64
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to record a used TOTP code.")?;
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to use a TOTP code.")?;
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+    let used = sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1 AND code_hash = $2"#,
+        user_id,
+        hash_token(&code)
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to use a recovery code.")?
+    .rows_affected()
+        == 1;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to use a recovery code.")?;
+    Ok(used)
+}
+
+#[tracing::instrument(name = "Disable two-factor", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled = false, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication.")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt_secret, encrypt_secret, matching_step, totp_code};
+    use crate::configuration::TwoFactorSettings;
+    use secrecy::Secret;
+
+    fn settings(key: &str) -> TwoFactorSettings {
+        TwoFactorSettings {
+            issuer: "zero2prod".into(),
+            encryption_key: Secret::new(key.into()),
+        }
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        // The SHA1 vectors of RFC 6238 appendix B, truncated to 6 digits.
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(totp_code(secret, time), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_the_neighbouring_steps_are_accepted() {
+        let secret = b"12345678901234567890";
+        let code = totp_code(secret, 1111111109);
+        assert!(matching_step(secret, &code, 1111111109 + 30).is_some());
+        assert!(matching_step(secret, &code, 1111111109 + 90).is_none());
+    }
+
+    #[test]
+    fn secrets_can_only_be_decrypted_with_the_same_key() {
+        let secret = b"12345678901234567890";
+        let stored = encrypt_secret(secret, &settings("a-key")).unwrap();
+        assert_ne!(&stored[..], &secret[..]);
+        assert_eq!(decrypt_secret(&stored, &settings("a-key")).unwrap(), secret);
+        assert!(decrypt_secret(&stored, &settings("another-key")).is_err());
+    }
+}
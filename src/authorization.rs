@@ -0,0 +1,178 @@
+use http_types::Method;
+
+/// What a user is allowed to do in the admin area.
+///
+/// Roles are ordered: every role can do what the roles below it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Sees the dashboards only.
+    Viewer,
+    /// Publishes newsletter issues too.
+    Editor,
+    /// Manages users and subscribers too.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "viewer" => Ok(Self::Viewer),
+            "editor" => Ok(Self::Editor),
+            "owner" => Ok(Self::Owner),
+            other => Err(format!(
+                "{other} is not a supported role. Use either `viewer`, `editor` or `owner`."
+            )),
+        }
+    }
+}
+
+/// The least role allowed to reach an admin `path`.
+///
+/// Pages that aren't listed need the owner role, so a new page is never exposed by
+/// mistake.
+pub fn required_role(path: &str) -> Role {
+    match path {
+        "/admin/dashboard" | "/admin/password" | "/admin/logout" => Role::Viewer,
+        // Everyone secures their own account.
+        "/admin/two_factor"
+        | "/admin/two_factor/enroll"
+        | "/admin/two_factor/confirm"
+        | "/admin/two_factor/disable"
+        | "/admin/sessions"
+        | "/admin/sessions/revoke"
+        | "/admin/sessions/revoke_others"
+        | "/admin/api_tokens"
+        | "/admin/api_tokens/create"
+        | "/admin/api_tokens/revoke" => Role::Viewer,
+        "/admin/newsletters"
+        | "/admin/issues"
+        | "/admin/issues/view"
+        | "/admin/issues/clone"
+        | "/admin/issues/publish"
+        | "/admin/issues/resend"
+        | "/admin/issues/pause"
+        | "/admin/issues/resume"
+        | "/admin/issues/cancel" => Role::Editor,
+        path if path == "/api/v1/issues" || path.starts_with("/api/v1/issues/") => Role::Editor,
+        _ => Role::Owner,
+    }
+}
+
+/// What an API token may be used for, on top of the role of its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    ReadNewsletters,
+    PublishNewsletters,
+    ReadSubscribers,
+    WriteSubscribers,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::ReadNewsletters,
+        Scope::PublishNewsletters,
+        Scope::ReadSubscribers,
+        Scope::WriteSubscribers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadNewsletters => "newsletters:read",
+            Scope::PublishNewsletters => "newsletters:publish",
+            Scope::ReadSubscribers => "subscribers:read",
+            Scope::WriteSubscribers => "subscribers:write",
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == value)
+            .ok_or_else(|| format!("{value} is not a supported scope."))
+    }
+}
+
+/// The scope an API token needs to make a `method` request to `path`.
+///
+/// Anything that isn't listed can't be reached with a token at all.
+pub fn required_scope(method: Method, path: &str) -> Option<Scope> {
+    let under = |prefix: &str| path == prefix || path.starts_with(&format!("{prefix}/"));
+    match method {
+        Method::Post if path == "/admin/newsletters" => Some(Scope::PublishNewsletters),
+        Method::Get if under("/api/v1/issues") => Some(Scope::ReadNewsletters),
+        Method::Post if under("/api/v1/issues") => Some(Scope::PublishNewsletters),
+        Method::Get if under("/api/v1/subscribers") => Some(Scope::ReadSubscribers),
+        Method::Post | Method::Patch | Method::Delete if under("/api/v1/subscribers") => {
+            Some(Scope::WriteSubscribers)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{required_role, required_scope, Role, Scope};
+    use http_types::Method;
+
+    #[test]
+    fn roles_include_the_roles_below_them() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+
+    #[test]
+    fn unlisted_admin_pages_need_the_owner_role() {
+        assert_eq!(required_role("/admin/dashboard"), Role::Viewer);
+        assert_eq!(required_role("/admin/newsletters"), Role::Editor);
+        assert_eq!(required_role("/admin/some-new-page"), Role::Owner);
+        assert_eq!(required_role("/api/v1/issues/some-id"), Role::Editor);
+        assert_eq!(required_role("/api/v1/subscribers"), Role::Owner);
+    }
+
+    #[test]
+    fn api_tokens_only_reach_the_paths_of_their_scopes() {
+        assert_eq!(
+            required_scope(Method::Post, "/admin/newsletters"),
+            Some(Scope::PublishNewsletters)
+        );
+        assert_eq!(required_scope(Method::Get, "/admin/newsletters"), None);
+        assert_eq!(required_scope(Method::Post, "/admin/password"), None);
+        assert_eq!(
+            required_scope(Method::Get, "/api/v1/issues/some-id"),
+            Some(Scope::ReadNewsletters)
+        );
+        assert_eq!(
+            required_scope(Method::Delete, "/api/v1/subscribers/some-id"),
+            Some(Scope::WriteSubscribers)
+        );
+        assert_eq!(
+            required_scope(Method::Delete, "/api/v1/issues/some-id"),
+            None
+        );
+        assert_eq!(required_scope(Method::Get, "/api/v1/issuesx"), None);
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_name() {
+        for scope in Scope::ALL {
+            assert_eq!(Scope::try_from(scope.as_str().to_string()), Ok(scope));
+        }
+        assert!(Scope::try_from("everything".to_string()).is_err());
+    }
+}
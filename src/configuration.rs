@@ -13,6 +13,237 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub subscriptions: SubscriptionSettings,
+    pub rate_limits: RateLimitSettings,
+    pub login_lockout: LockoutSettings,
+    pub two_factor: TwoFactorSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub session: SessionSettings,
+    pub idempotency: IdempotencySettings,
+    pub delivery: DeliverySettings,
+}
+
+/// Limits protecting `POST /subscriptions` from bots.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SubscriptionSettings {
+    // Shortest time a human takes to fill the subscribe form in.
+    pub min_form_fill_milliseconds: u64,
+    pub max_form_age_seconds: u64,
+    // Attempts allowed within `attempts_window_seconds`.
+    pub max_attempts_per_ip: i64,
+    pub max_attempts_per_email: i64,
+    pub attempts_window_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn min_form_fill_time(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.min_form_fill_milliseconds)
+    }
+
+    pub fn max_form_age(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_form_age_seconds)
+    }
+
+    pub fn attempts_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.attempts_window_seconds)
+    }
+}
+
+/// Token buckets guarding the login and public subscription endpoints.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct RateLimitSettings {
+    // Prepended to every bucket key stored in Redis.
+    pub key_prefix: String,
+    pub login_per_ip: TokenBucketSettings,
+    pub login_per_username: TokenBucketSettings,
+    pub subscribe_per_ip: TokenBucketSettings,
+    pub confirm_per_ip: TokenBucketSettings,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct TokenBucketSettings {
+    // Requests allowed in a burst.
+    pub capacity: u32,
+    // A token is added back to the bucket every interval.
+    pub refill_interval_milliseconds: u64,
+}
+
+/// What it takes for a new admin password to be accepted.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize,
+    pub max_length: usize,
+    // Estimated entropy, see `domain::NewPassword`.
+    pub min_strength_bits: f64,
+}
+
+/// Cost of the Argon2id hashes of new passwords.
+///
+/// Stored hashes that are cheaper get upgraded the next time their owner logs in.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct PasswordHashingSettings {
+    // In KiB.
+    pub memory_cost: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_cost, self.iterations, self.parallelism, None)
+    }
+}
+
+/// How long admin sessions last and how their cookie is set.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SessionSettings {
+    // Inactivity after which a session has to log in again.
+    pub idle_timeout_seconds: u64,
+    // Time since login after which a session has to log in again, active or not.
+    pub absolute_timeout_seconds: u64,
+    pub cookie_name: String,
+    pub cookie_domain: Option<String>,
+    // Mark the cookie `Secure` even when requests reach us over plain http, e.g. behind
+    // a TLS-terminating proxy.
+    pub cookie_secure: bool,
+    pub cookie_same_site: SameSitePolicy,
+}
+
+impl SessionSettings {
+    pub fn idle_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idle_timeout_seconds)
+    }
+
+    pub fn absolute_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.absolute_timeout_seconds)
+    }
+}
+
+/// How long idempotency keys are remembered.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct IdempotencySettings {
+    // A retry after this long is processed as a new request.
+    pub ttl_seconds: u64,
+    // How often the worker deletes expired keys.
+    pub cleanup_interval_seconds: u64,
+    // How long a duplicate waits for the original request before getting a 409.
+    pub in_flight_wait_milliseconds: u64,
+    // Requests still in progress after this long are assumed lost, e.g. in a crash, and
+    // can be retried.
+    pub abandoned_after_seconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+
+    pub fn in_flight_wait(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.in_flight_wait_milliseconds)
+    }
+
+    pub fn abandoned_after(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.abandoned_after_seconds)
+    }
+}
+
+/// When the delivery worker looks at the queue.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct DeliverySettings {
+    // Publishing wakes the worker up right away, polling only catches missed notifications.
+    pub fallback_poll_interval_seconds: u64,
+    // How long the worker backs off after failing to deliver.
+    pub retry_interval_milliseconds: u64,
+    // Deliveries made at the same time. Each one holds a database connection.
+    pub concurrency: usize,
+    // Shared by all deliveries, `None` sends as fast as possible.
+    pub max_sends_per_second: Option<u32>,
+    // Emails sent in a single request, up to `email_client::MAX_BATCH_SIZE` and to
+    // `max_sends_per_second`.
+    pub batch_size: usize,
+    // A delivery still failing after this many attempts is moved to the dead letters.
+    pub max_attempts: i32,
+    // How long a failed delivery waits before its next attempt.
+    pub redelivery_delay_seconds: u64,
+}
+
+impl DeliverySettings {
+    pub fn fallback_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.fallback_poll_interval_seconds)
+    }
+
+    pub fn retry_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.retry_interval_milliseconds)
+    }
+
+    pub fn batch_size(&self) -> usize {
+        let batch_size = self
+            .batch_size
+            .clamp(1, crate::email_client::MAX_BATCH_SIZE);
+        // A batch goes out at once, so it can't be more than a second worth of sends.
+        match self.max_sends_per_second {
+            Some(rate) => batch_size.min(rate.max(1) as usize),
+            None => batch_size,
+        }
+    }
+
+    pub fn redelivery_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.redelivery_delay_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSitePolicy> for tide::http::cookies::SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => Self::Strict,
+            SameSitePolicy::Lax => Self::Lax,
+            SameSitePolicy::None => Self::None,
+        }
+    }
+}
+
+/// TOTP second factor for admin logins.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TwoFactorSettings {
+    // Shown next to the account in authenticator apps.
+    pub issuer: String,
+    // TOTP secrets are encrypted at rest with a key derived from this.
+    pub encryption_key: Secret<String>,
+}
+
+/// When to lock an account after failed logins, and for how long.
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub struct LockoutSettings {
+    // Consecutive failures that trigger a lockout.
+    pub max_failed_attempts: i32,
+    // The first lockout lasts this long, every following one twice as long as the previous.
+    pub base_cooldown_seconds: u64,
+    pub max_cooldown_seconds: u64,
+}
+
+impl LockoutSettings {
+    /// How long the account is locked for, given how many lockouts preceded this one.
+    pub fn cooldown(&self, previous_lockouts: i32) -> std::time::Duration {
+        let factor = 2u64.saturating_pow(previous_lockouts.max(0) as u32);
+        std::time::Duration::from_secs(
+            self.base_cooldown_seconds
+                .saturating_mul(factor)
+                .min(self.max_cooldown_seconds),
+        )
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -51,6 +282,10 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // Version of the consent text subscribers agree to, recorded with every consent event.
+    pub consent_text_version: String,
+    // Whether a reverse proxy appends the client address to `X-Forwarded-For`.
+    pub behind_proxy: bool,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -148,3 +383,47 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DeliverySettings, LockoutSettings};
+    use std::time::Duration;
+
+    const SETTINGS: LockoutSettings = LockoutSettings {
+        max_failed_attempts: 5,
+        base_cooldown_seconds: 60,
+        max_cooldown_seconds: 3600,
+    };
+
+    #[test]
+    fn lockout_cooldown_doubles_with_every_lockout() {
+        assert_eq!(SETTINGS.cooldown(0), Duration::from_secs(60));
+        assert_eq!(SETTINGS.cooldown(1), Duration::from_secs(120));
+        assert_eq!(SETTINGS.cooldown(3), Duration::from_secs(480));
+    }
+
+    #[test]
+    fn lockout_cooldown_is_capped() {
+        assert_eq!(SETTINGS.cooldown(6), Duration::from_secs(3600));
+        assert_eq!(SETTINGS.cooldown(i32::MAX), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn batches_stay_within_a_second_worth_of_sends() {
+        let mut settings = DeliverySettings {
+            fallback_poll_interval_seconds: 60,
+            retry_interval_milliseconds: 1000,
+            concurrency: 1,
+            max_sends_per_second: None,
+            batch_size: 100,
+            max_attempts: 3,
+            redelivery_delay_seconds: 60,
+        };
+        assert_eq!(settings.batch_size(), 100);
+        settings.max_sends_per_second = Some(20);
+        assert_eq!(settings.batch_size(), 20);
+        settings.batch_size = 10_000;
+        settings.max_sends_per_second = Some(1_000);
+        assert_eq!(settings.batch_size(), crate::email_client::MAX_BATCH_SIZE);
+    }
+}
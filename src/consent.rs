@@ -0,0 +1,137 @@
+use crate::routes::utils::client_ip;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConsentEventKind {
+    Signup,
+    Confirmation,
+}
+
+impl ConsentEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentEventKind::Signup => "signup",
+            ConsentEventKind::Confirmation => "confirmation",
+        }
+    }
+}
+
+/// How a consent was given, captured from the request that carried it.
+#[derive(Debug, Clone)]
+pub struct ConsentContext {
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+    pub consent_text_version: String,
+}
+
+impl ConsentContext {
+    pub fn from_request(
+        req: &crate::Request,
+        source: Option<String>,
+        consent_text_version: String,
+    ) -> Self {
+        Self {
+            client_ip: client_ip(req, req.state().behind_proxy),
+            user_agent: req.header("User-Agent").map(|ua| ua.as_str().to_string()),
+            source,
+            consent_text_version,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsentEvent {
+    pub kind: String,
+    pub occurred_at: DateTime<Utc>,
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+    pub consent_text_version: String,
+}
+
+#[tracing::instrument(name = "Record a consent event", skip(transaction))]
+pub async fn record_consent_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: ConsentEventKind,
+    context: &ConsentContext,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            id,
+            subscriber_id,
+            kind,
+            occurred_at,
+            client_ip,
+            user_agent,
+            source,
+            consent_text_version
+        )
+        VALUES ($1, $2, $3, now(), $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        kind.as_str(),
+        context.client_ip,
+        context.user_agent,
+        context.source,
+        context.consent_text_version
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// The consent given at signup, which a confirmation agrees to.
+#[tracing::instrument(name = "Get signup consent", skip(executor))]
+pub async fn get_signup_consent<'a, E>(
+    executor: E,
+    subscriber_id: Uuid,
+) -> Result<Option<(Option<String>, String)>, anyhow::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+        SELECT source, consent_text_version
+        FROM consent_events
+        WHERE subscriber_id = $1 AND kind = 'signup'
+        ORDER BY occurred_at DESC
+        LIMIT 1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch the signup consent.")?;
+    Ok(row.map(|r| (r.source, r.consent_text_version)))
+}
+
+#[tracing::instrument(name = "Get consent events", skip(executor))]
+pub async fn get_consent_events<'a, E>(
+    executor: E,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentEvent>, anyhow::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let events = sqlx::query_as!(
+        ConsentEvent,
+        r#"
+        SELECT kind, occurred_at, client_ip, user_agent, source, consent_text_version
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to fetch consent events.")?;
+    Ok(events)
+}
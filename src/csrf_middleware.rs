@@ -0,0 +1,156 @@
+use crate::login_middleware::ApiTokenId;
+use crate::routes::subscriptions::generate_subscription_token;
+use crate::session_state::TypedSession;
+use crate::State;
+use http_types::{headers, Method};
+use tide::http::Url;
+use tide::{Body, Middleware, Next, Response, Result, StatusCode};
+
+const TOKEN_FIELD: &str = "csrf_token";
+// Lets scripts send the token without a form.
+const TOKEN_HEADER: &str = "X-CSRF-Token";
+
+/// Synchronizer tokens for the forms of the login flow and the admin area.
+///
+/// Every session gets a random token, which the forms posting to a protected path
+/// carry in the hidden field rendered by [`csrf_field`]. State-changing requests to
+/// those paths are rejected with a 403 unless they carry the token of their session,
+/// and, if the browser says where they come from, unless they come from this application.
+#[derive(Default)]
+pub struct CsrfMiddleware;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for CsrfMiddleware {
+    async fn handle(&self, mut req: tide::Request<State>, next: Next<'_, State>) -> Result {
+        let session = TypedSession::from_req(&req);
+        let changes_state = !matches!(req.method(), Method::Get | Method::Head | Method::Options);
+        // Bearer tokens aren't sent by browsers on their own.
+        let uses_api_token = req.ext::<ApiTokenId>().is_some();
+        if changes_state && !uses_api_token && is_protected(req.url().path()) {
+            let from_elsewhere = !is_same_origin(&req);
+            let token = submitted_token(&mut req).await?;
+            let expected = session.get_csrf_token();
+            let valid = matches!(
+                (token, expected),
+                (Some(token), Some(expected)) if constant_time_eq(token.as_bytes(), expected.as_bytes())
+            );
+            if from_elsewhere || !valid {
+                return Ok(forbidden());
+            }
+        }
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// The hidden field with the token of the session, for forms posting to a protected path.
+pub fn csrf_field<S: Clone + Send + Sync + 'static>(req: &tide::Request<S>) -> Result<String> {
+    let token =
+        TypedSession::from_req(req).get_or_insert_csrf_token(generate_subscription_token)?;
+    Ok(format!(
+        r#"<input type="hidden" name="{TOKEN_FIELD}" value="{token}">"#
+    ))
+}
+
+/// Whether a state-changing request to `path` needs a token.
+fn is_protected(path: &str) -> bool {
+    path == "/login"
+        || path.starts_with("/login/")
+        || path == "/admin"
+        || path.starts_with("/admin/")
+        || path.starts_with("/invitations/")
+}
+
+/// Whether `Origin`, or `Referer` if it is missing, points at this application.
+///
+/// Requests saying nothing about where they come from only have the token checked.
+fn is_same_origin(req: &tide::Request<State>) -> bool {
+    let source = req
+        .header(headers::ORIGIN)
+        .or_else(|| req.header(headers::REFERER));
+    let source = match source {
+        Some(source) => source.as_str(),
+        None => return true,
+    };
+    let source = match Url::parse(source) {
+        Ok(source) => source,
+        // Including the opaque `null` origin.
+        Err(_) => return false,
+    };
+    let matches_base_url = Url::parse(&req.state().base_url)
+        .map(|base_url| base_url.origin() == source.origin())
+        .unwrap_or(false);
+    let matches_host = match (source.host_str(), req.header(headers::HOST)) {
+        (Some(host), Some(expected)) => {
+            let authority = match source.port() {
+                Some(port) => format!("{host}:{port}"),
+                None => host.to_string(),
+            };
+            authority == expected.as_str()
+        }
+        _ => false,
+    };
+    matches_base_url || matches_host
+}
+
+/// The token sent in the header or, failing that, in the form of `req`.
+///
+/// The body is put back for the handler to read.
+async fn submitted_token(req: &mut tide::Request<State>) -> Result<Option<String>> {
+    #[derive(serde::Deserialize)]
+    struct TokenForm {
+        csrf_token: String,
+    }
+
+    if let Some(token) = req.header(TOKEN_HEADER) {
+        return Ok(Some(token.as_str().to_string()));
+    }
+    let bytes = req.take_body().into_bytes().await?;
+    let form = Body::from_bytes(bytes.clone())
+        .into_form::<TokenForm>()
+        .await
+        .ok();
+    let mut body = Body::from_bytes(bytes);
+    if let Some(mime) = req.content_type() {
+        body.set_mime(mime);
+    }
+    req.set_body(body);
+    Ok(form.map(|f| f.csrf_token))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn forbidden() -> Response {
+    let mut resp = Response::new(StatusCode::Forbidden);
+    resp.set_body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>The form has expired or didn't come from this site, please go back and try again.</p>
+</body>
+</html>"#,
+    );
+    resp.set_content_type("text/html; charset=utf-8");
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_protected;
+
+    #[test]
+    fn the_login_flow_and_the_admin_area_are_protected() {
+        assert!(is_protected("/login"));
+        assert!(is_protected("/login/two_factor"));
+        assert!(is_protected("/admin/password"));
+        assert!(is_protected("/invitations/accept"));
+        assert!(!is_protected("/subscriptions"));
+        assert!(!is_protected("/loginx"));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::configuration::PasswordPolicySettings;
+use secrecy::{ExposeSecret, Secret};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+const BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+/// A password that follows the password policy, ready to be hashed and stored.
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    /// Check `password` against `policy`, explaining what is wrong with it if anything.
+    pub fn parse(
+        password: Secret<String>,
+        username: &str,
+        policy: &PasswordPolicySettings,
+    ) -> Result<NewPassword, String> {
+        let candidate = password.expose_secret();
+        let length = candidate.chars().count();
+        if length < policy.min_length {
+            return Err(format!(
+                "The password must be at least {} characters long.",
+                policy.min_length
+            ));
+        }
+        if length > policy.max_length {
+            return Err(format!(
+                "The password must be at most {} characters long.",
+                policy.max_length
+            ));
+        }
+        let username = username.trim().to_lowercase();
+        if !username.is_empty() && candidate.to_lowercase().contains(&username) {
+            return Err("The password must not contain your username.".into());
+        }
+        if is_breached(candidate) {
+            return Err("This password appeared in a data breach, please pick another one.".into());
+        }
+        if estimate_strength_bits(candidate) < policy.min_strength_bits {
+            return Err("This password is too easy to guess, make it longer or mix in other kinds of characters.".into());
+        }
+        Ok(Self(password))
+    }
+
+    pub fn into_secret(self) -> Secret<String> {
+        self.0
+    }
+}
+
+fn is_breached(password: &str) -> bool {
+    static BREACHED: OnceLock<HashSet<String>> = OnceLock::new();
+    BREACHED
+        .get_or_init(|| {
+            BREACHED_PASSWORDS
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect()
+        })
+        .contains(&password.to_lowercase())
+}
+
+/// A rough estimate of how many bits of entropy `password` has.
+///
+/// Every character is worth the size of the alphabet the password draws from, except
+/// those repeating or continuing a sequence from the previous one (`aaa`, `abc`, `321`).
+fn estimate_strength_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let mut alphabet = 0;
+    if chars.iter().any(char::is_ascii_lowercase) {
+        alphabet += 26;
+    }
+    if chars.iter().any(char::is_ascii_uppercase) {
+        alphabet += 26;
+    }
+    if chars.iter().any(char::is_ascii_digit) {
+        alphabet += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        alphabet += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        alphabet += 100;
+    }
+    if alphabet == 0 {
+        return 0.0;
+    }
+    let predictable = chars
+        .windows(2)
+        .filter(|pair| (pair[1] as i64 - pair[0] as i64).abs() <= 1)
+        .count();
+    (chars.len() - predictable) as f64 * (alphabet as f64).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewPassword;
+    use crate::configuration::PasswordPolicySettings;
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn policy() -> PasswordPolicySettings {
+        PasswordPolicySettings {
+            min_length: 12,
+            max_length: 128,
+            min_strength_bits: 50.0,
+        }
+    }
+
+    fn parse(password: &str, username: &str) -> Result<NewPassword, String> {
+        NewPassword::parse(Secret::new(password.into()), username, &policy())
+    }
+
+    #[test]
+    fn a_long_random_password_is_accepted() {
+        assert_ok!(parse("wobbly-kettle-ferns-92", "admin"));
+    }
+
+    #[test]
+    fn passwords_outside_the_length_bounds_are_rejected() {
+        assert_err!(parse("Sh0rt!", "admin"));
+        assert_err!(parse(&"xY7-".repeat(33), "admin"));
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert_err!(parse("my-name-is-Alice-42", "alice"));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_whatever_their_case() {
+        assert_err!(parse("Password123456", "admin"));
+    }
+
+    #[test]
+    fn repeated_and_sequential_characters_do_not_count_as_strength() {
+        assert_err!(parse("aaaaaaaaaaaaaaaa", "admin"));
+        assert_err!(parse("abcdefghijklmnop", "admin"));
+    }
+}
@@ -2,6 +2,10 @@ use crate::domain::SubscriberEmail;
 use secrecy::{ExposeSecret, Secret};
 use surf::Client;
 use surf::Config;
+use surf::StatusCode;
+
+/// Postmark refuses batches with more emails.
+pub const MAX_BATCH_SIZE: usize = 500;
 
 #[derive(Clone)]
 pub struct EmailClient {
@@ -40,7 +44,6 @@ impl EmailClient {
         html_content: &str,
         text_content: &str,
     ) -> Result<(), surf::Error> {
-        let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
@@ -48,6 +51,60 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
+        self.post("email", &request_body).await?;
+        Ok(())
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails in a single request.
+    ///
+    /// Postmark answers for every message, in order: some can be refused while the
+    /// others go out.
+    pub async fn send_email_batch(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), MessageRejected>>, surf::Error> {
+        if emails.len() > MAX_BATCH_SIZE {
+            return Err(surf::Error::from_str(
+                StatusCode::PayloadTooLarge,
+                format!("Postmark takes at most {MAX_BATCH_SIZE} emails per batch"),
+            ));
+        }
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: email.recipient.as_ref(),
+                subject: email.subject,
+                html_body: email.html_content,
+                text_body: email.text_content,
+            })
+            .collect();
+        let mut response = self.post("email/batch", &request_body).await?;
+        let results: Vec<BatchMessageResponse> = response.body_json().await?;
+        if results.len() != emails.len() {
+            return Err(surf::Error::from_str(
+                StatusCode::BadGateway,
+                "Got a result per message for a different number of messages",
+            ));
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(()),
+                error_code => Err(MessageRejected {
+                    error_code,
+                    message: result.message,
+                }),
+            })
+            .collect())
+    }
+
+    async fn post<Body: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> Result<surf::Response, surf::Error> {
+        let url = format!("{}/{}", self.base_url, path);
         let req_builder = self
             .http_client
             .post(&url)
@@ -55,7 +112,7 @@ impl EmailClient {
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
-            .body_json(&request_body)?;
+            .body_json(body)?;
         let mut response = req_builder.await?;
         let resp_status = response.status();
         if resp_status.is_client_error() || resp_status.is_server_error() {
@@ -69,7 +126,31 @@ impl EmailClient {
                 "Get error status code from server",
             ));
         }
-        Ok(())
+        Ok(response)
+    }
+}
+
+/// An email of a batch.
+pub struct Email<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+}
+
+/// Why Postmark refused to send one of the emails of a batch.
+#[derive(thiserror::Error, Debug)]
+#[error("Postmark refused the email ({error_code}): {message}")]
+pub struct MessageRejected {
+    pub error_code: i64,
+    pub message: String,
+}
+
+impl MessageRejected {
+    /// Whether sending the same email again is bound to be refused too.
+    pub fn is_permanent(&self) -> bool {
+        // Invalid email request and inactive recipient.
+        matches!(self.error_code, 300 | 406)
     }
 }
 
@@ -83,10 +164,17 @@ struct SendEmailRequest<'a> {
     text_body: &'a str,
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResponse {
+    error_code: i64,
+    message: String,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{Email, EmailClient};
     use claim::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -163,6 +251,68 @@ mod tests {
         // Assert
     }
 
+    #[async_std::test]
+    async fn send_email_batch_returns_a_result_for_every_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| Email {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+            })
+            .collect();
+
+        // Act
+        let outcome = email_client.send_email_batch(&emails).await.unwrap();
+
+        // Assert
+        assert_ok!(&outcome[0]);
+        assert_eq!(outcome[1].as_ref().unwrap_err().error_code, 406);
+    }
+
+    #[async_std::test]
+    async fn send_email_batch_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let (recipient, subject, content) = (email(), subject(), content());
+        let emails = [Email {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        }];
+
+        // Act
+        let outcome = email_client.send_email_batch(&emails).await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
     #[async_std::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         // Arrange
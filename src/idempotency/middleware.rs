@@ -0,0 +1,310 @@
+use super::{
+    get_saved_response, release_key, save_response, try_processing, IdempotencyKey, NextAction,
+    SaveOutcome,
+};
+use crate::login_middleware::UserId;
+use crate::State;
+use anyhow::Context;
+use async_std::sync::{Arc, Mutex};
+use http_types::{headers, Method};
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+use std::time::{Duration, Instant};
+use tide::{Body, Middleware, Next, Response, Result, StatusCode};
+
+const KEY_HEADER: &str = "Idempotency-Key";
+// How often a duplicate checks whether the original request is done.
+const IN_FLIGHT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Suggested to duplicates that gave up waiting.
+const RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Adjusts a saved response before it is sent back again.
+pub type Replay = fn(&tide::Request<State>, &mut Response);
+
+/// Make the POST requests of a route idempotent.
+///
+/// Clients name every attempt at a request with an `Idempotency-Key` header or, for
+/// forms, an `idempotency_key` field. The first request with a key is processed and its
+/// response saved; later requests of the same user with the same key get the saved
+/// response back without being processed again. Failures aren't saved, so a request can
+/// be fixed and retried with its key. Duplicates arriving while the first request is
+/// still processed wait for its response, for a while, then get a 409.
+///
+/// Handlers do their work in the transaction from [`begin_transaction`], committed
+/// together with the saved response. Add the middleware to the routes that need it,
+/// after `RequiredLoginMiddleware` has run: keys belong to users.
+pub struct IdempotencyMiddleware {
+    required: bool,
+    replay: Option<Replay>,
+}
+
+impl IdempotencyMiddleware {
+    /// Requests without a key are processed as usual.
+    pub fn optional() -> Self {
+        Self {
+            required: false,
+            replay: None,
+        }
+    }
+
+    /// Requests without a key are rejected with a 400.
+    pub fn required() -> Self {
+        Self {
+            required: true,
+            replay: None,
+        }
+    }
+
+    /// Call `replay` on saved responses before sending them back.
+    ///
+    /// Cookies aren't saved, e.g. flash messages have to be attached again.
+    pub fn on_replay(mut self, replay: Replay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+}
+
+/// The transaction holding the idempotency key of the request being processed.
+#[derive(Clone)]
+struct IdempotentTransaction(Arc<Mutex<Option<Transaction<'static, Postgres>>>>);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for IdempotencyMiddleware {
+    async fn handle(&self, mut req: tide::Request<State>, next: Next<'_, State>) -> Result {
+        if req.method() != Method::Post {
+            return Ok(next.run(req).await);
+        }
+        let user_id = match req.ext::<UserId>() {
+            Some(user_id) => user_id.0,
+            None => return Ok(next.run(req).await),
+        };
+        let (key, body) = submitted_key(&mut req).await?;
+        let idempotency_key: IdempotencyKey = match key {
+            Some(key) => match key.try_into() {
+                Ok(key) => key,
+                Err(e) => {
+                    let mut resp = Response::new(StatusCode::BadRequest);
+                    resp.set_error(e);
+                    return Ok(resp);
+                }
+            },
+            None if self.required => {
+                let _ = req.body_bytes().await;
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    "Missing idempotency key.",
+                ));
+            }
+            None => return Ok(next.run(req).await),
+        };
+        let request_hash = fingerprint(req.method(), req.url().path(), &body);
+        let pool = req.state().connection.clone();
+        let settings = req.state().idempotency_settings;
+        let deadline = Instant::now() + settings.in_flight_wait();
+        let (transaction, claim_id) = loop {
+            match try_processing(
+                &pool,
+                &idempotency_key,
+                user_id,
+                &request_hash,
+                settings.abandoned_after(),
+            )
+            .await?
+            {
+                NextAction::StartProcessing(t, claim_id) => break (t, claim_id),
+                NextAction::RejectReusedKey => {
+                    let _ = req.body_bytes().await;
+                    return Err(tide::Error::from_str(
+                        StatusCode::UnprocessableEntity,
+                        "The idempotency key was already used for another request.",
+                    ));
+                }
+                NextAction::ReturnSavedResponse(mut saved_response) => {
+                    let _ = req.body_bytes().await;
+                    if let Some(replay) = self.replay {
+                        replay(&req, &mut saved_response);
+                    }
+                    return Ok(saved_response);
+                }
+                NextAction::WaitInProgress if Instant::now() < deadline => {
+                    async_std::task::sleep(IN_FLIGHT_POLL_INTERVAL).await;
+                }
+                NextAction::WaitInProgress => {
+                    let _ = req.body_bytes().await;
+                    return Ok(in_progress());
+                }
+            }
+        };
+        let slot = IdempotentTransaction(Arc::new(Mutex::new(Some(transaction))));
+        req.set_ext(slot.clone());
+        let res = next.run(req).await;
+        let transaction = slot.0.lock().await.take();
+        match transaction {
+            Some(transaction)
+                if !res.status().is_client_error() && !res.status().is_server_error() =>
+            {
+                match save_response(transaction, &idempotency_key, user_id, claim_id, res).await? {
+                    SaveOutcome::Saved(res) => Ok(res),
+                    // Answered like a duplicate of the request that took the key over.
+                    SaveOutcome::TakenOver(mut res) => {
+                        let deadline = Instant::now() + settings.in_flight_wait();
+                        loop {
+                            if let Some(saved) =
+                                get_saved_response(&pool, &idempotency_key, user_id).await?
+                            {
+                                answer_with(&mut res, saved);
+                                return Ok(res);
+                            }
+                            if Instant::now() >= deadline {
+                                return Ok(in_progress());
+                            }
+                            async_std::task::sleep(IN_FLIGHT_POLL_INTERVAL).await;
+                        }
+                    }
+                }
+            }
+            // The work is rolled back with the transaction, let the request be retried.
+            _ => {
+                if let Err(e) = release_key(&pool, &idempotency_key, user_id, claim_id).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to release an idempotency key."
+                    );
+                }
+                Ok(res)
+            }
+        }
+    }
+}
+
+/// The answer to a duplicate that gave up waiting for the original request.
+fn in_progress() -> Response {
+    let mut resp = Response::new(StatusCode::Conflict);
+    resp.insert_header(headers::RETRY_AFTER, RETRY_AFTER_SECONDS.to_string());
+    resp.set_error(anyhow::anyhow!(
+        "A request with the same idempotency key is in progress."
+    ));
+    resp
+}
+
+/// Turn `res` into the `saved` response, keeping the cookies the handler attached to it.
+fn answer_with(res: &mut Response, mut saved: Response) {
+    let names: Vec<_> = res.header_names().cloned().collect();
+    for name in names {
+        res.remove_header(name);
+    }
+    res.set_status(saved.status());
+    for (name, values) in saved.iter() {
+        for value in values {
+            res.append_header(name, value.clone());
+        }
+    }
+    res.set_body(saved.take_body());
+}
+
+/// The transaction to process `req` in: the one of its idempotency key if it has one,
+/// a new one otherwise.
+pub async fn begin_transaction(
+    req: &tide::Request<State>,
+) -> std::result::Result<Transaction<'static, Postgres>, anyhow::Error> {
+    if let Some(slot) = req.ext::<IdempotentTransaction>() {
+        if let Some(transaction) = slot.0.lock().await.take() {
+            return Ok(transaction);
+        }
+    }
+    req.state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+}
+
+/// Commit the work done for `req`, or hand it back to be committed with the saved
+/// response.
+pub async fn commit_transaction(
+    req: &tide::Request<State>,
+    transaction: Transaction<'static, Postgres>,
+) -> std::result::Result<(), anyhow::Error> {
+    match req.ext::<IdempotentTransaction>() {
+        Some(slot) => {
+            *slot.0.lock().await = Some(transaction);
+            Ok(())
+        }
+        None => transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction."),
+    }
+}
+
+/// The key in the header or, failing that, in the form or JSON body of `req`, and the
+/// body.
+///
+/// The body is put back for the handler to read.
+async fn submitted_key(req: &mut tide::Request<State>) -> Result<(Option<String>, Vec<u8>)> {
+    #[derive(serde::Deserialize)]
+    struct KeyField {
+        idempotency_key: String,
+    }
+
+    let header = req.header(KEY_HEADER).map(|key| key.as_str().to_string());
+    let is_json = req
+        .content_type()
+        .is_some_and(|mime| mime.essence() == "application/json");
+    let bytes = req.take_body().into_bytes().await?;
+    let field = if header.is_some() {
+        None
+    } else if is_json {
+        serde_json::from_slice::<KeyField>(&bytes).ok()
+    } else {
+        Body::from_bytes(bytes.clone())
+            .into_form::<KeyField>()
+            .await
+            .ok()
+    };
+    let mut body = Body::from_bytes(bytes.clone());
+    if let Some(mime) = req.content_type() {
+        body.set_mime(mime);
+    }
+    req.set_body(body);
+    Ok((header.or(field.map(|f| f.idempotency_key)), bytes))
+}
+
+/// Tells requests apart, so a key can't be reused for another request.
+fn fingerprint(method: Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_ref());
+    hasher.update([0]);
+    hasher.update(path);
+    hasher.update([0]);
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fingerprint;
+    use http_types::Method;
+
+    #[test]
+    fn fingerprints_change_with_the_method_the_path_and_the_body() {
+        let original = fingerprint(Method::Post, "/api/v1/issues", b"{}");
+
+        assert_eq!(original, fingerprint(Method::Post, "/api/v1/issues", b"{}"));
+        assert_ne!(original, fingerprint(Method::Put, "/api/v1/issues", b"{}"));
+        assert_ne!(
+            original,
+            fingerprint(Method::Post, "/api/v1/subscribers", b"{}")
+        );
+        assert_ne!(
+            original,
+            fingerprint(Method::Post, "/api/v1/issues", b"{\"a\":1}")
+        );
+        // The separators keep the parts from running into each other.
+        assert_ne!(
+            fingerprint(Method::Post, "/a", b"b"),
+            fingerprint(Method::Post, "/ab", b"")
+        );
+    }
+}
@@ -1,4 +1,9 @@
 mod key;
+mod middleware;
 mod persistence;
 pub use key::IdempotencyKey;
-pub use persistence::{get_saved_response, save_response, try_processing, NextAction};
+pub use middleware::{begin_transaction, commit_transaction, IdempotencyMiddleware, Replay};
+pub use persistence::{
+    delete_expired_keys, get_saved_response, release_key, save_response, try_processing,
+    NextAction, SaveOutcome,
+};
@@ -16,48 +16,21 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
-pub async fn get_saved_response(
-    pool: &PgPool,
-    idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
-) -> Result<Option<Response>, anyhow::Error> {
-    let saved_response = sqlx::query!(
-        r#"
-        SELECT
-            response_status_code as "response_status_code!",
-            response_headers as "response_headers!: Vec<HeaderPairRecord>",
-            response_body as "response_body!"
-        FROM idempotency
-        WHERE
-            user_id = $1 AND
-            idempotency_key = $2
-        "#,
-        user_id,
-        idempotency_key.as_ref()
-    )
-    .fetch_optional(pool)
-    .await?;
-
-    if let Some(r) = saved_response {
-        let status_code = StatusCode::try_from(r.response_status_code as u16)
-            .map_err(|_| anyhow::anyhow!("invalid status code saved in database."))?;
-        let mut response = Response::new(status_code);
-        for HeaderPairRecord { name, value } in r.response_headers {
-            response.append_header(name.as_str(), String::from_utf8_lossy(&value));
-        }
-        response.set_body(r.response_body);
-        Ok(Some(response))
-    } else {
-        Ok(None)
-    }
+pub enum SaveOutcome {
+    Saved(Response),
+    // The claim was taken over: the work was rolled back and the response not saved.
+    TakenOver(Response),
 }
 
+/// Save `http_response` for `idempotency_key` and commit `transaction` with it, provided
+/// the key is still held by `claim_id`.
 pub async fn save_response(
     mut transaction: Transaction<'static, Postgres>,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    claim_id: Uuid,
     mut http_response: Response,
-) -> Result<Response, anyhow::Error> {
+) -> Result<SaveOutcome, anyhow::Error> {
     let status_code = http_response.status() as i16;
     let headers = {
         let mut h = Vec::new();
@@ -84,7 +57,7 @@ pub async fn save_response(
         .await
         .expect("the given response body should always be able to convert to bytes.");
 
-    sqlx::query_unchecked!(
+    let saved = sqlx::query_unchecked!(
         r#"
             UPDATE idempotency
             SET
@@ -92,54 +65,228 @@ pub async fn save_response(
                 response_headers = $4,
                 response_body = $5
             WHERE
-                user_id = $1 AND idempotency_key = $2
+                user_id = $1 AND
+                idempotency_key = $2 AND
+                claim_id = $6 AND
+                response_status_code IS NULL
         "#,
         user_id,
         idempotency_key.as_ref(),
         status_code,
         headers,
-        body
+        body,
+        claim_id
     )
     .execute(&mut transaction)
     .await?;
-    transaction.commit().await?;
     http_response.set_body(body);
-    Ok(http_response)
+    if saved.rows_affected() == 0 {
+        return Ok(SaveOutcome::TakenOver(http_response));
+    }
+    transaction.commit().await?;
+    Ok(SaveOutcome::Saved(http_response))
+}
+
+/// The response saved for `idempotency_key`, if its request is done.
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<Response>, anyhow::Error> {
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers: Vec<HeaderPairRecord>",
+            response_body
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2 AND
+            response_status_code IS NOT NULL
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+    saved
+        .map(|saved| {
+            into_response(
+                saved.response_status_code,
+                saved.response_headers,
+                saved.response_body,
+            )
+        })
+        .transpose()
+}
+
+fn into_response(
+    status_code: i16,
+    headers: Option<Vec<HeaderPairRecord>>,
+    body: Option<Vec<u8>>,
+) -> Result<Response, anyhow::Error> {
+    let status_code = StatusCode::try_from(status_code as u16)
+        .map_err(|_| anyhow::anyhow!("invalid status code saved in database."))?;
+    let mut response = Response::new(status_code);
+    for HeaderPairRecord { name, value } in headers.unwrap_or_default() {
+        response.append_header(name.as_str(), String::from_utf8_lossy(&value));
+    }
+    response.set_body(body.unwrap_or_default());
+    Ok(response)
 }
 
 pub enum NextAction {
-    StartProcessing(Transaction<'static, Postgres>),
+    // The key is claimed with the given id.
+    StartProcessing(Transaction<'static, Postgres>, Uuid),
     ReturnSavedResponse(Response),
+    // The key was used for a request with another fingerprint.
+    RejectReusedKey,
+    // Another request with the key is being processed.
+    WaitInProgress,
 }
 
+/// Claim `idempotency_key` for a request with `request_hash` as its fingerprint.
+///
+/// The claim is committed right away, so duplicates see that the request is in progress
+/// instead of waiting on a lock. It is completed by [`save_response`] or given up with
+/// [`release_key`]; claims left in progress for longer than `abandoned_after`, e.g. by a
+/// crash, can be taken over, after which the previous holder can do neither.
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
     user_id: Uuid,
+    request_hash: &str,
+    abandoned_after: std::time::Duration,
 ) -> Result<NextAction, anyhow::Error> {
-    let mut transaction = pool.begin().await?;
-    let n_inserted_rows = sqlx::query!(
-        r#"
-        INSERT INTO idempotency (
+    let claim_id = Uuid::new_v4();
+    loop {
+        let n_inserted_rows = sqlx::query!(
+            r#"
+            INSERT INTO idempotency (
+                user_id,
+                idempotency_key,
+                request_hash,
+                claim_id,
+                created_at
+            )
+            VALUES ($1, $2, $3, $4, now())
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            request_hash,
+            claim_id
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        if n_inserted_rows > 0 {
+            return Ok(NextAction::StartProcessing(pool.begin().await?, claim_id));
+        }
+        let saved = sqlx::query!(
+            r#"
+            SELECT
+                request_hash,
+                response_status_code,
+                response_headers as "response_headers: Vec<HeaderPairRecord>",
+                response_body,
+                created_at < now() - make_interval(secs => $3) as "abandoned!"
+            FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            abandoned_after.as_secs_f64()
+        )
+        .fetch_optional(pool)
+        .await?;
+        let saved = match saved {
+            Some(saved) => saved,
+            // Released or expired in the meantime.
+            None => continue,
+        };
+        if saved
+            .request_hash
+            .is_some_and(|saved_hash| saved_hash != request_hash)
+        {
+            return Ok(NextAction::RejectReusedKey);
+        }
+        if let Some(status_code) = saved.response_status_code {
+            return Ok(NextAction::ReturnSavedResponse(into_response(
+                status_code,
+                saved.response_headers,
+                saved.response_body,
+            )?));
+        }
+        if !saved.abandoned {
+            return Ok(NextAction::WaitInProgress);
+        }
+        let taken_over = sqlx::query!(
+            r#"
+            UPDATE idempotency
+            SET created_at = now(), request_hash = $3, claim_id = $5
+            WHERE
+                user_id = $1 AND
+                idempotency_key = $2 AND
+                response_status_code IS NULL AND
+                created_at < now() - make_interval(secs => $4)
+            "#,
             user_id,
-            idempotency_key,
-            created_at
+            idempotency_key.as_ref(),
+            request_hash,
+            abandoned_after.as_secs_f64(),
+            claim_id
         )
-        VALUES ($1, $2, now())
-        ON CONFLICT DO NOTHING
+        .execute(pool)
+        .await?
+        .rows_affected();
+        if taken_over > 0 {
+            return Ok(NextAction::StartProcessing(pool.begin().await?, claim_id));
+        }
+        // Someone else took it over first, look again.
+    }
+}
+
+/// Give up the claim `claim_id` on `idempotency_key`, so that the request can be retried.
+///
+/// Does nothing if the claim was taken over in the meantime.
+#[tracing::instrument(name = "Release an idempotency key", skip(pool))]
+pub async fn release_key(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    claim_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2 AND
+            claim_id = $3 AND
+            response_status_code IS NULL
         "#,
         user_id,
-        idempotency_key.as_ref()
+        idempotency_key.as_ref(),
+        claim_id
     )
-    .execute(&mut transaction)
-    .await?
-    .rows_affected();
-    if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(transaction))
-    } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expectred a saved response, we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
-    }
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Forget the keys claimed more than `ttl` ago, returning how many there were.
+#[tracing::instrument(name = "Delete expired idempotency keys", skip(pool))]
+pub async fn delete_expired_keys(
+    pool: &PgPool,
+    ttl: std::time::Duration,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at < now() - make_interval(secs => $1)"#,
+        ttl.as_secs_f64()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
 }
@@ -1,9 +1,19 @@
+use crate::authentication::delete_expired_sessions;
+use crate::configuration::{DeliverySettings, IdempotencySettings, SessionSettings};
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{Email, EmailClient};
+use crate::idempotency::delete_expired_keys;
+use crate::newsletter_issues::DELIVERY_CHANNEL;
 use crate::{configuration::Settings, startup::get_connection_pool};
+use async_std::channel::{Receiver, Sender};
+use async_std::prelude::FutureExt;
+use sqlx::postgres::PgListener;
 use sqlx::{PgPool, Postgres, Transaction};
-use std::time::Duration;
-use tracing::{field::display, Span};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::Span;
 use uuid::Uuid;
 
 pub enum ExecutionOutcome {
@@ -11,91 +21,247 @@ pub enum ExecutionOutcome {
     EmptyQueue,
 }
 
-#[tracing::instrument(
-    skip_all,
-    fields(newsletter_issue_id=tracing::field::Empty,subscriber_email=tracing::field::Empty), err
-)]
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    settings: &DeliverySettings,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = deque_task(pool).await?;
-    if task.is_none() {
+    execute_task(pool, email_client, settings, None).await
+}
+
+/// Send a batch of queued deliveries.
+///
+/// Every delivery is settled on its own: sent ones leave the queue, failed ones are
+/// retried later or, after `max_attempts` or a permanent refusal, moved to the dead
+/// letters. The rate limit is waited for before any row gets locked.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+async fn execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+    rate_limit: Option<&SendRateLimit>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let batch_size = settings.batch_size();
+    if let Some(rate_limit) = rate_limit {
+        rate_limit.acquire(batch_size as u32).await;
+    }
+    let (mut transaction, tasks) = deque_tasks(pool, batch_size).await?;
+    if tasks.is_empty() {
+        if let Some(rate_limit) = rate_limit {
+            rate_limit.release(batch_size as u32);
+        }
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-    // send out email.
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
+    Span::current().record("n_tasks", tasks.len());
+    let mut issues = HashMap::new();
+    for task in &tasks {
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(&mut transaction, task.newsletter_issue_id).await?);
+        }
+    }
+    let mut recipients = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => recipients.push((task, email)),
+            Err(e) => {
                 tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. \
-                    Skipping."
+                    error.cause_chain = ?e, error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Giving up on a confirmed subscriber. \
+                    Their stored contact details are invalid"
                 );
+                give_up_task(&mut transaction, task, &e.to_string()).await?;
             }
         }
+    }
+    if let Some(rate_limit) = rate_limit {
+        rate_limit.release((batch_size - recipients.len()) as u32);
+    }
+    if recipients.is_empty() {
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    // send out emails.
+    let emails: Vec<_> = recipients
+        .iter()
+        .map(|(task, email)| {
+            let issue = &issues[&task.newsletter_issue_id];
+            Email {
+                recipient: email,
+                subject: &issue.title,
+                html_content: &issue.html_content,
+                text_content: &issue.text_content,
+            }
+        })
+        .collect();
+    let results: Vec<Result<(), SendFailure>> = match email_client.send_email_batch(&emails).await {
+        Ok(results) => results
+            .into_iter()
+            .map(|result| {
+                result.map_err(|e| SendFailure {
+                    permanent: e.is_permanent(),
+                    error: e.to_string(),
+                })
+            })
+            .collect(),
         Err(e) => {
             tracing::error!(
-                error.cause_chain = ?e, error.message = %e,
-                "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid"
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver a batch of issues. Retrying every email later."
             );
+            let failure = SendFailure {
+                error: e.to_string(),
+                permanent: false,
+            };
+            vec![Err(failure); emails.len()]
         }
-    }
+    };
 
-    delete_task(transaction, issue_id, &email).await?;
+    let mut delivered = Vec::new();
+    for ((task, _), result) in recipients.into_iter().zip(results) {
+        match result {
+            Ok(()) => delivered.push(task),
+            Err(e) if e.permanent || task.n_retries + 1 >= settings.max_attempts => {
+                tracing::error!(
+                    error.message = %e.error,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Giving up on delivering an issue to a confirmed subscriber."
+                );
+                give_up_task(&mut transaction, task, &e.error).await?;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.message = %e.error,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Failed to deliver an issue to a confirmed subscriber. Retrying later."
+                );
+                retry_task(&mut transaction, task, settings.redelivery_delay()).await?;
+            }
+        }
+    }
+    delete_tasks(&mut transaction, &delivered).await?;
+    transaction.commit().await?;
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+/// Why an email of a batch didn't go out.
+#[derive(Clone)]
+struct SendFailure {
+    error: String,
+    // Retrying would be refused the same way.
+    permanent: bool,
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+}
+
 #[tracing::instrument(skip_all)]
-async fn deque_task(pool: &PgPool) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+async fn deque_tasks(
+    pool: &PgPool,
+    batch_size: usize,
+) -> Result<(PgTransaction, Vec<Task>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        Task,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
-        FROM issue_delivery_queue
-        FOR UPDATE
+        SELECT q.newsletter_issue_id, q.subscriber_email, q.n_retries
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE i.delivery_state = 'active' AND q.execute_after <= now()
+        FOR UPDATE OF q
         SKIP LOCKED
-        LIMIT 1
-        "#
+        LIMIT $1
+        "#,
+        batch_size as i64
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
     .await?;
+    Ok((transaction, tasks))
+}
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+#[tracing::instrument(skip_all)]
+async fn delete_tasks(
+    transaction: &mut PgTransaction,
+    tasks: &[&Task],
+) -> Result<(), anyhow::Error> {
+    let issue_ids: Vec<Uuid> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    let emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE (newsletter_issue_id, subscriber_email) IN (
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        )
+        "#,
+        &issue_ids,
+        &emails
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
-async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
 ) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Move a delivery from the queue to the dead letters.
+#[tracing::instrument(skip_all)]
+async fn give_up_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email)
+        DO UPDATE SET error = EXCLUDED.error, failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        error
+    )
+    .execute(&mut *transaction)
+    .await?;
     sqlx::query!(
         r#"
         DELETE FROM issue_delivery_queue
@@ -103,12 +269,11 @@ async fn delete_task(
             newsletter_issue_id = $1 AND
             subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
     )
-    .execute(&mut transaction)
+    .execute(&mut *transaction)
     .await?;
-    transaction.commit().await?;
     Ok(())
 }
 
@@ -119,7 +284,10 @@ struct NewsletterIssue {
 }
 
 #[tracing::instrument(skip_all)]
-async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+async fn get_issue(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
@@ -130,27 +298,194 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
         "#,
         issue_id
     )
-    .fetch_one(pool)
+    .fetch_one(transaction)
     .await?;
     Ok(issue)
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+/// Spaces sends out evenly, across all the deliveries made at the same time.
+struct SendRateLimit {
+    interval: Duration,
+    next_send: Mutex<Instant>,
+}
+
+impl SendRateLimit {
+    fn new(sends_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / sends_per_second.max(1),
+            next_send: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `sends` emails can go out.
+    async fn acquire(&self, sends: u32) {
+        let send_at = {
+            let mut next_send = self.next_send.lock().unwrap();
+            let send_at = (*next_send).max(Instant::now());
+            *next_send = send_at + self.interval * sends;
+            send_at
+        };
+        async_std::task::sleep(send_at.saturating_duration_since(Instant::now())).await;
+    }
+
+    /// Hand back sends that were acquired but didn't go out.
+    fn release(&self, sends: u32) {
+        let mut next_send = self.next_send.lock().unwrap();
+        let now = Instant::now();
+        *next_send = next_send
+            .checked_sub(self.interval * sends)
+            .map_or(now, |released| released.max(now));
+    }
+}
+
+async fn worker_loop(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limit: Option<&SendRateLimit>,
+    wakeups: Receiver<()>,
+    settings: &DeliverySettings,
+) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        match execute_task(pool, email_client, settings, rate_limit).await {
             Ok(ExecutionOutcome::EmptyQueue) => {
-                async_std::task::sleep(Duration::from_secs(10)).await;
+                // Sleep until deliveries are queued, or until the next fallback poll.
+                let _ =
+                    async_std::future::timeout(settings.fallback_poll_interval(), wakeups.recv())
+                        .await;
             }
             Err(_) => {
-                async_std::task::sleep(Duration::from_secs(1)).await;
+                async_std::task::sleep(settings.retry_interval()).await;
             }
             Ok(ExecutionOutcome::TaskCompleted) => {}
         }
     }
 }
 
+/// Wake every idle worker up when deliveries are queued.
+async fn notification_loop(
+    mut listener: PgListener,
+    wakers: Vec<Sender<()>>,
+    settings: DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match listener.recv().await {
+            // A worker with a wakeup pending will look at the queue anyway.
+            Ok(_) => wakers.iter().for_each(|waker| {
+                let _ = waker.try_send(());
+            }),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to wait for delivery notifications."
+                );
+                async_std::task::sleep(settings.retry_interval()).await;
+            }
+        }
+    }
+}
+
+/// Forget expired idempotency keys every now and then.
+async fn idempotency_cleanup_loop(
+    pool: PgPool,
+    settings: IdempotencySettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match delete_expired_keys(&pool, settings.ttl()).await {
+            Ok(deleted) => tracing::info!(deleted, "Deleted expired idempotency keys."),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired idempotency keys."
+            ),
+        }
+        async_std::task::sleep(settings.cleanup_interval()).await;
+    }
+}
+
+/// Forget expired user sessions every now and then.
+///
+/// Sessions are checked for expiry when they are used, this catches the ones that
+/// never come back.
+async fn session_cleanup_loop(
+    pool: PgPool,
+    settings: SessionSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match delete_expired_sessions(&settings, &pool).await {
+            Ok(deleted) => tracing::info!(deleted, "Deleted expired user sessions."),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired user sessions."
+            ),
+        }
+        async_std::task::sleep(settings.idle_timeout()).await;
+    }
+}
+
 pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
     let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let settings = configuration.delivery;
+    let rate_limit = settings.max_sends_per_second.map(SendRateLimit::new);
+    // Listen before looking at the queue, so work published in between isn't missed.
+    let mut listener = PgListener::connect_with(&connection_pool).await?;
+    listener.listen(DELIVERY_CHANNEL).await?;
+    let (wakers, workers): (Vec<_>, Vec<_>) = (0..settings.concurrency.max(1))
+        .map(|_| {
+            let (waker, wakeups) = async_std::channel::bounded(1);
+            let worker = worker_loop(
+                &connection_pool,
+                &email_client,
+                rate_limit.as_ref(),
+                wakeups,
+                &settings,
+            );
+            (waker, worker)
+        })
+        .unzip();
+    let workers = async {
+        futures::future::try_join_all(workers).await?;
+        Ok(())
+    };
+    workers
+        .race(notification_loop(listener, wakers, settings))
+        .race(idempotency_cleanup_loop(
+            connection_pool.clone(),
+            configuration.idempotency,
+        ))
+        .race(session_cleanup_loop(
+            connection_pool.clone(),
+            configuration.session,
+        ))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendRateLimit;
+    use std::time::{Duration, Instant};
+
+    #[async_std::test]
+    async fn sends_are_spaced_out_by_the_rate_limit() {
+        let rate_limit = SendRateLimit::new(20);
+        let started = Instant::now();
+        for _ in 0..3 {
+            rate_limit.acquire(2).await;
+        }
+        // The first batch goes right away, the next ones 100ms apart.
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[async_std::test]
+    async fn released_sends_do_not_hold_up_the_next_ones() {
+        let rate_limit = SendRateLimit::new(10);
+        rate_limit.acquire(10).await;
+        rate_limit.release(10);
+        let started = Instant::now();
+        rate_limit.acquire(1).await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
 }
@@ -1,15 +1,25 @@
 pub mod authentication;
+pub mod authorization;
 pub mod configuration;
+pub mod consent;
+pub mod csrf_middleware;
 pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_worker;
 pub mod login_middleware;
+pub mod newsletter_issues;
+pub mod rate_limit_middleware;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod subscriber_data;
 pub mod telemetry;
 
+use configuration::{
+    IdempotencySettings, LockoutSettings, PasswordHashingSettings, PasswordPolicySettings,
+    SessionSettings, Settings, SubscriptionSettings, TwoFactorSettings,
+};
 use email_client::EmailClient;
 use secrecy::Secret;
 use sqlx::PgPool;
@@ -21,20 +31,38 @@ pub struct State {
     email_client: EmailClient,
     base_url: String,
     hmac_secret: Secret<String>,
+    consent_text_version: String,
+    behind_proxy: bool,
+    subscription_settings: SubscriptionSettings,
+    lockout_settings: LockoutSettings,
+    two_factor_settings: TwoFactorSettings,
+    password_policy: PasswordPolicySettings,
+    password_hashing: PasswordHashingSettings,
+    dummy_password_hash: Secret<String>,
+    session_settings: SessionSettings,
+    idempotency_settings: IdempotencySettings,
 }
 
 impl State {
-    pub fn new(
-        pg_pool: PgPool,
-        email_client: EmailClient,
-        base_url: String,
-        hmac_secret: Secret<String>,
-    ) -> Self {
+    pub fn new(pg_pool: PgPool, email_client: EmailClient, configuration: &Settings) -> Self {
         State {
             connection: pg_pool,
             email_client,
-            base_url,
-            hmac_secret,
+            base_url: configuration.application.base_url.clone(),
+            hmac_secret: configuration.application.hmac_secret.clone(),
+            consent_text_version: configuration.application.consent_text_version.clone(),
+            behind_proxy: configuration.application.behind_proxy,
+            subscription_settings: configuration.subscriptions.clone(),
+            lockout_settings: configuration.login_lockout,
+            two_factor_settings: configuration.two_factor.clone(),
+            password_policy: configuration.password_policy,
+            password_hashing: configuration.password_hashing,
+            dummy_password_hash: authentication::dummy_password_hash(
+                configuration.password_hashing,
+            )
+            .expect("Failed to hash the dummy password."),
+            session_settings: configuration.session.clone(),
+            idempotency_settings: configuration.idempotency,
         }
     }
 }
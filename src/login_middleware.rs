@@ -1,30 +1,171 @@
+use crate::authentication::{authenticate_api_token, revoke_session, touch_session, ApiTokenOwner};
+use crate::authorization::{required_role, required_scope, Role};
+use crate::routes::api::OPENAPI_PATH;
+use crate::routes::utils::attach_flashed_message;
+use crate::routes::PublishError;
 use crate::session_state::TypedSession;
-use tide::{Middleware, Next, Redirect, Result};
+use crate::State;
+use anyhow::Context;
+use http_types::headers;
+use sqlx::PgPool;
+use tide::{Middleware, Next, Redirect, Response, Result, StatusCode};
 #[derive(Default)]
 pub struct RequiredLoginMiddleware;
 
 pub struct UserId(pub uuid::Uuid);
 
+/// The `user_sessions` row of the logged in session.
+pub struct SessionId(pub uuid::Uuid);
+
+/// The API token a request was authenticated with, instead of a session.
+pub struct ApiTokenId(pub uuid::Uuid);
+
 #[tide::utils::async_trait]
-impl<S: Clone + Send + Sync + 'static> Middleware<S> for RequiredLoginMiddleware {
-    async fn handle(&self, mut req: tide::Request<S>, next: Next<'_, S>) -> Result {
+impl Middleware<State> for RequiredLoginMiddleware {
+    async fn handle(&self, mut req: tide::Request<State>, next: Next<'_, State>) -> Result {
         let req_path = req.url().path();
-        if [
-            "/admin/dashboard",
-            "/admin/password",
-            "/admin/logout",
-            "/admin/newsletters",
-        ]
-        .contains(&req_path)
-        {
-            let session = TypedSession::from_req(&req);
-            let user_id = match session.get_user_id() {
-                None => return Ok(Redirect::see_other("/login").into()),
-                Some(user_id) => user_id,
+        let is_admin = req_path == "/admin" || req_path.starts_with("/admin/");
+        if is_token_only(req_path) || (is_admin && req.header(headers::AUTHORIZATION).is_some()) {
+            let (owner, role) = match authenticate_bearer(&req).await {
+                Ok(authenticated) => authenticated,
+                Err(e) => {
+                    // Read the unused body so the connection can be kept alive.
+                    let _ = req.body_bytes().await;
+                    return Err(e);
+                }
+            };
+            let req_path = req.url().path();
+            let in_scope = required_scope(req.method(), req_path)
+                .is_some_and(|scope| owner.scopes.contains(&scope));
+            if !in_scope || role < required_role(req_path) {
+                let _ = req.body_bytes().await;
+                return Ok(forbidden());
+            }
+            req.set_ext(UserId(owner.user_id));
+            req.set_ext(ApiTokenId(owner.token_id));
+            req.set_ext(role);
+        } else if is_admin {
+            let mut session = TypedSession::from_req(&req);
+            let (user_id, session_id) = match (session.get_user_id(), session.get_session_id()) {
+                (Some(user_id), Some(session_id)) => (user_id, session_id),
+                (None, _) => return Ok(Redirect::see_other("/login").into()),
+                // Logged in before sessions were tracked.
+                (Some(_), None) => {
+                    session.log_out();
+                    return Ok(Redirect::see_other("/login").into());
+                }
             };
+            let state = req.state();
+            let pool = &state.connection;
+            let idle_timeout = chrono::Duration::from_std(state.session_settings.idle_timeout())
+                .context("The idle session timeout is out of range.")?;
+            let absolute_timeout =
+                chrono::Duration::from_std(state.session_settings.absolute_timeout())
+                    .context("The absolute session timeout is out of range.")?;
+            if session.is_expired(idle_timeout, absolute_timeout) {
+                revoke_session(user_id, session_id, pool).await?;
+                session.log_out();
+                let mut resp: Response = Redirect::see_other("/login").into();
+                attach_flashed_message(
+                    &mut resp,
+                    &state.hmac_secret,
+                    "Your session has expired, please log in again.".into(),
+                );
+                return Ok(resp);
+            }
+            // The session may have been revoked, and users may have been deactivated or
+            // deleted since they logged in.
+            if !touch_session(session_id, user_id, pool).await? {
+                session.log_out();
+                return Ok(Redirect::see_other("/login").into());
+            }
+            let role = match get_active_role(user_id, pool).await? {
+                Some(role) => role,
+                None => {
+                    session.log_out();
+                    return Ok(Redirect::see_other("/login").into());
+                }
+            };
+            if role < required_role(req_path) {
+                // Read the unused form so the connection can be kept alive.
+                let _ = req.body_bytes().await;
+                return Ok(forbidden());
+            }
+            session.touch()?;
             req.set_ext(UserId(user_id));
+            req.set_ext(SessionId(session_id));
+            req.set_ext(role);
         }
         let res = next.run(req).await;
         Ok(res)
     }
 }
+
+/// Whether `path` can only be reached with an API token.
+///
+/// The JSON API doesn't take sessions, but its description is public.
+fn is_token_only(path: &str) -> bool {
+    path.starts_with("/api/") && path != OPENAPI_PATH
+}
+
+/// The owner of the bearer token of `req` and their role.
+///
+/// Failures are `PublishError::AuthError`s, answered with a 401 and a challenge.
+async fn authenticate_bearer(req: &tide::Request<State>) -> Result<(ApiTokenOwner, Role)> {
+    let unauthorized = |msg: &'static str| {
+        tide::Error::new(
+            StatusCode::Unauthorized,
+            PublishError::AuthError(anyhow::anyhow!(msg)),
+        )
+    };
+    let token = req
+        .header(headers::AUTHORIZATION)
+        .ok_or_else(|| unauthorized("Missing API token."))?
+        .as_str()
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| unauthorized("The authorization scheme is not 'Bearer'."))?;
+    let pool = &req.state().connection;
+    let owner = authenticate_api_token(token.trim(), pool)
+        .await?
+        .ok_or_else(|| unauthorized("Invalid API token."))?;
+    let role = get_active_role(owner.user_id, pool)
+        .await?
+        .ok_or_else(|| unauthorized("The owner of the API token is not active."))?;
+    Ok((owner, role))
+}
+
+/// The role of a user, unless they are deactivated or gone.
+#[tracing::instrument(name = "Get the role of an active user", skip(pool))]
+async fn get_active_role(
+    user_id: uuid::Uuid,
+    pool: &PgPool,
+) -> std::result::Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT role FROM users WHERE user_id = $1 AND active"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the role of a user.")?;
+    row.map(|r| Role::try_from(r.role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+fn forbidden() -> Response {
+    let mut resp = Response::new(StatusCode::Forbidden);
+    resp.set_body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forbidden</title>
+</head>
+<body>
+    <p>You don't have permission to access this page.</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+    );
+    resp.set_content_type("text/html; charset=utf-8");
+    resp
+}
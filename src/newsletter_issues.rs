@@ -0,0 +1,528 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Channel notified, once committed, whenever deliveries become ready to be made.
+pub const DELIVERY_CHANNEL: &str = "issue_delivery";
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    // `None` for drafts.
+    pub published_at: Option<DateTime<Utc>>,
+    pub delivery: Option<DeliveryStatus>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Issue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub created_at: DateTime<Utc>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub delivery: Option<DeliveryStatus>,
+}
+
+/// How far the delivery of a published issue went.
+#[derive(serde::Serialize)]
+pub struct DeliveryStatus {
+    pub state: DeliveryState,
+    // Confirmed subscribers at the time of publication.
+    pub recipients: i32,
+    // Deliveries still waiting in the queue.
+    pub pending: i64,
+    // Deliveries already made, frozen when the issue is cancelled.
+    pub sent: i64,
+    // Deliveries given up on after too many attempts.
+    pub failed: i64,
+}
+
+/// Whether the worker delivers a published issue.
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    Active,
+    Paused,
+    /// The remaining deliveries were dropped, there is no way back.
+    Cancelled,
+}
+
+impl DeliveryState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryState::Active => "active",
+            DeliveryState::Paused => "paused",
+            DeliveryState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl TryFrom<String> for DeliveryState {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "active" => Ok(Self::Active),
+            "paused" => Ok(Self::Paused),
+            "cancelled" => Ok(Self::Cancelled),
+            other => Err(format!("{other} is not a supported delivery state.")),
+        }
+    }
+}
+
+pub enum PublishOutcome {
+    Published,
+    AlreadyPublished,
+    NotFound,
+}
+
+pub enum ResendOutcome {
+    // How many subscribers joined since the issue was last sent.
+    Resent(u64),
+    NotPublished,
+    Cancelled,
+    NotFound,
+}
+
+pub enum DeliveryChangeOutcome {
+    Changed,
+    // The delivery was already in the requested state.
+    Unchanged,
+    AlreadyCancelled,
+    NotPublished,
+    NotFound,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+    INSERT INTO newsletter_issues (
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        created_at
+    )
+    VALUES ($1, $2, $3, $4, now())
+    "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Publish a draft, queueing a delivery to every confirmed subscriber.
+#[tracing::instrument(skip(transaction))]
+pub async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<PublishOutcome, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch a newsletter issue.")?;
+    match issue {
+        None => return Ok(PublishOutcome::NotFound),
+        Some(issue) if issue.published_at.is_some() => return Ok(PublishOutcome::AlreadyPublished),
+        Some(_) => {}
+    }
+    let recipients = enqueue_delivery_tasks(transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now(), recipients_count = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        recipients as i32
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark a newsletter issue as published.")?;
+    Ok(PublishOutcome::Published)
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue(
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    notify_delivery_worker(transaction, newsletter_issue_id).await?;
+    Ok(result.rows_affected())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn notify_delivery_worker(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_notify($1, $2)",
+        DELIVERY_CHANNEL,
+        newsletter_issue_id.to_string()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Issues, drafts included, most recently created first.
+#[tracing::instrument(name = "Get newsletter issues", skip(pool))]
+pub async fn get_issues(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            created_at,
+            published_at::timestamptz AS "published_at: DateTime<Utc>",
+            recipients_count,
+            delivery_state,
+            sent_count,
+            (
+                SELECT count(*)
+                FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "pending!",
+            (
+                SELECT count(*)
+                FROM issue_delivery_dead_letters d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "failed!"
+        FROM newsletter_issues i
+        ORDER BY created_at DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch newsletter issues.")?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(IssueSummary {
+                newsletter_issue_id: r.newsletter_issue_id,
+                title: r.title,
+                created_at: r.created_at,
+                delivery: delivery_status(
+                    r.published_at,
+                    r.delivery_state,
+                    r.recipients_count,
+                    r.pending,
+                    r.failed,
+                    r.sent_count,
+                )?,
+                published_at: r.published_at,
+            })
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(executor))]
+pub async fn get_issue<'a, E>(
+    executor: E,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Issue>, anyhow::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            title,
+            text_content,
+            html_content,
+            created_at,
+            published_at::timestamptz AS "published_at: DateTime<Utc>",
+            recipients_count,
+            delivery_state,
+            sent_count,
+            (
+                SELECT count(*)
+                FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "pending!",
+            (
+                SELECT count(*)
+                FROM issue_delivery_dead_letters d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "failed!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch a newsletter issue.")?;
+    row.map(|r| {
+        Ok(Issue {
+            newsletter_issue_id,
+            title: r.title,
+            text_content: r.text_content,
+            html_content: r.html_content,
+            created_at: r.created_at,
+            delivery: delivery_status(
+                r.published_at,
+                r.delivery_state,
+                r.recipients_count,
+                r.pending,
+                r.failed,
+                r.sent_count,
+            )?,
+            published_at: r.published_at,
+        })
+    })
+    .transpose()
+}
+
+fn delivery_status(
+    published_at: Option<DateTime<Utc>>,
+    state: String,
+    recipients: Option<i32>,
+    pending: i64,
+    failed: i64,
+    sent: Option<i32>,
+) -> Result<Option<DeliveryStatus>, anyhow::Error> {
+    if published_at.is_none() {
+        return Ok(None);
+    }
+    let recipients = recipients.unwrap_or_default();
+    Ok(Some(DeliveryStatus {
+        state: DeliveryState::try_from(state).map_err(anyhow::Error::msg)?,
+        recipients,
+        pending,
+        sent: sent.map_or(i64::from(recipients) - pending - failed, i64::from),
+        failed,
+    }))
+}
+
+/// Copy an issue into a new draft, returning its id.
+#[tracing::instrument(skip(transaction))]
+pub async fn clone_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let draft_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            created_at
+        )
+        SELECT $2, title, text_content, html_content, now()
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        draft_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to copy a newsletter issue.")?;
+    Ok((result.rows_affected() > 0).then_some(draft_id))
+}
+
+/// Send a published issue to the subscribers who confirmed since it was last sent.
+#[tracing::instrument(skip(transaction))]
+pub async fn resend_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<ResendOutcome, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            published_at::timestamptz AS "published_at: DateTime<Utc>",
+            resent_at,
+            delivery_state
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch a newsletter issue.")?;
+    let last_sent_at = match issue {
+        None => return Ok(ResendOutcome::NotFound),
+        Some(issue) if issue.delivery_state == DeliveryState::Cancelled.as_str() => {
+            return Ok(ResendOutcome::Cancelled)
+        }
+        Some(issue) => match issue.resent_at.or(issue.published_at) {
+            Some(last_sent_at) => last_sent_at,
+            None => return Ok(ResendOutcome::NotPublished),
+        },
+    };
+    // Subscribers confirmed before consent events were recorded fall back to when they
+    // signed up.
+    let queued = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, s.email
+        FROM subscriptions s
+        WHERE
+            s.status = 'confirmed' AND
+            COALESCE(
+                (
+                    SELECT max(c.occurred_at)
+                    FROM consent_events c
+                    WHERE c.subscriber_id = s.id AND c.kind = 'confirmation'
+                ),
+                s.subscribed_at
+            ) > $2
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        last_sent_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enqueue delivery tasks")?
+    .rows_affected();
+    if queued > 0 {
+        notify_delivery_worker(transaction, newsletter_issue_id)
+            .await
+            .context("Failed to notify the delivery worker.")?;
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET resent_at = now(), recipients_count = COALESCE(recipients_count, 0) + $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        queued as i32
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark a newsletter issue as resent.")?;
+    Ok(ResendOutcome::Resent(queued))
+}
+
+/// Pause, resume or cancel the delivery of a published issue.
+///
+/// Cancelling drops the deliveries still in the queue and records how many were made.
+#[tracing::instrument(skip(transaction))]
+pub async fn change_delivery_state(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    state: DeliveryState,
+) -> Result<DeliveryChangeOutcome, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT published_at, delivery_state
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch a newsletter issue.")?;
+    let current = match issue {
+        None => return Ok(DeliveryChangeOutcome::NotFound),
+        Some(issue) if issue.published_at.is_none() => {
+            return Ok(DeliveryChangeOutcome::NotPublished)
+        }
+        Some(issue) => DeliveryState::try_from(issue.delivery_state).map_err(anyhow::Error::msg)?,
+    };
+    if current == DeliveryState::Cancelled {
+        return Ok(DeliveryChangeOutcome::AlreadyCancelled);
+    }
+    if current == state {
+        return Ok(DeliveryChangeOutcome::Unchanged);
+    }
+    // Deliveries the worker is making right now are waited for, so they count as sent.
+    let dropped = if state == DeliveryState::Cancelled {
+        sqlx::query!(
+            r#"
+            DELETE FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
+            "#,
+            newsletter_issue_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to drop queued deliveries.")?
+        .rows_affected()
+    } else {
+        0
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            delivery_state = $2,
+            sent_count = CASE
+                WHEN $2 = 'cancelled' THEN COALESCE(recipients_count, 0) - $3 - (
+                    SELECT count(*)::int
+                    FROM issue_delivery_dead_letters d
+                    WHERE d.newsletter_issue_id = $1
+                )
+                ELSE NULL
+            END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        state.as_str(),
+        dropped as i32
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the delivery state of a newsletter issue.")?;
+    if state == DeliveryState::Active {
+        notify_delivery_worker(transaction, newsletter_issue_id)
+            .await
+            .context("Failed to notify the delivery worker.")?;
+    }
+    Ok(DeliveryChangeOutcome::Changed)
+}
@@ -0,0 +1,408 @@
+use crate::configuration::{RateLimitSettings, TokenBucketSettings};
+use crate::routes::utils::client_ip;
+use async_std::sync::Mutex;
+use http_types::{headers, Method};
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tide::{Body, Middleware, Next, Response, Result, StatusCode};
+
+// How long to wait for redis, to connect or to answer, before limiting in memory.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Refills the bucket stored at KEYS[1] and tries to take a token from it.
+// Mirrors `TokenBucket::take`, so both backends agree on the outcome.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_interval = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+local refilled = math.floor((now - updated_at) / refill_interval)
+if tokens + refilled >= capacity then
+    tokens = capacity
+    updated_at = now
+elseif refilled > 0 then
+    tokens = tokens + refilled
+    updated_at = updated_at + refilled * refill_interval
+end
+local retry_after = 0
+if tokens > 0 then
+    tokens = tokens - 1
+else
+    retry_after = refill_interval - (now - updated_at)
+end
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'updated_at', updated_at)
+redis.call('PEXPIRE', KEYS[1], capacity * refill_interval)
+return retry_after
+"#;
+
+/// Token-bucket limits on `POST /login`, `POST /login/two_factor`, `POST /login/forgot`,
+/// `POST /subscriptions` and `GET /subscriptions/confirm`.
+///
+/// Buckets live in Redis so that every instance shares them. If Redis can't be
+/// reached the middleware keeps limiting with buckets held in memory instead.
+pub struct RateLimitMiddleware {
+    redis_client: Option<redis::Client>,
+    // Opened on first use and shared by every request, reset when it fails.
+    connection: Mutex<Option<MultiplexedConnection>>,
+    script: redis::Script,
+    settings: RateLimitSettings,
+    behind_proxy: bool,
+    fallback: Arc<Mutex<MemoryBuckets>>,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(redis_uri: &str, settings: RateLimitSettings, behind_proxy: bool) -> Self {
+        let redis_client = redis::Client::open(redis_uri)
+            .map_err(|e| {
+                tracing::warn!(
+                    error.message = %e,
+                    "Invalid redis uri, rate limits are kept in memory."
+                )
+            })
+            .ok();
+        Self {
+            redis_client,
+            connection: Mutex::new(None),
+            script: redis::Script::new(TAKE_TOKEN_SCRIPT),
+            settings,
+            behind_proxy,
+            fallback: Arc::new(Mutex::new(MemoryBuckets::default())),
+        }
+    }
+
+    /// The buckets a request has to take a token from, with their keys.
+    async fn buckets<S: Clone + Send + Sync + 'static>(
+        &self,
+        req: &mut tide::Request<S>,
+    ) -> Result<Vec<(String, TokenBucketSettings)>> {
+        let ip = client_ip(req, self.behind_proxy).unwrap_or_else(|| "unknown".into());
+        let prefix = &self.settings.key_prefix;
+        let buckets = match (req.method(), req.url().path()) {
+            // Second factors and reset links are limited like logins, but in buckets of
+            // their own.
+            (Method::Post, path @ ("/login" | "/login/two_factor" | "/login/forgot")) => {
+                let action = match path {
+                    "/login" => "login",
+                    "/login/two_factor" => "two_factor",
+                    _ => "forgot",
+                };
+                let mut buckets = vec![(
+                    format!("{prefix}:{action}:ip:{ip}"),
+                    self.settings.login_per_ip,
+                )];
+                if let Some(username) = peek_username(req).await? {
+                    buckets.push((
+                        format!("{prefix}:{action}:username:{username}"),
+                        self.settings.login_per_username,
+                    ));
+                }
+                buckets
+            }
+            (Method::Post, "/subscriptions") => vec![(
+                format!("{prefix}:subscribe:ip:{ip}"),
+                self.settings.subscribe_per_ip,
+            )],
+            (Method::Get, "/subscriptions/confirm") => vec![(
+                format!("{prefix}:confirm:ip:{ip}"),
+                self.settings.confirm_per_ip,
+            )],
+            _ => vec![],
+        };
+        Ok(buckets)
+    }
+
+    /// Take a token from the bucket at `key`.
+    ///
+    /// Returns how long to wait before retrying if the bucket is empty.
+    async fn take_token(&self, key: &str, limit: TokenBucketSettings) -> Option<u64> {
+        let now = now_in_milliseconds();
+        match self.take_token_from_redis(key, limit, now).await {
+            Ok(retry_after) => retry_after,
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to reach redis, falling back to in-memory rate limits."
+                );
+                self.take_token_from_memory(key, limit, now).await
+            }
+        }
+    }
+
+    async fn take_token_from_redis(
+        &self,
+        key: &str,
+        limit: TokenBucketSettings,
+        now: u64,
+    ) -> std::result::Result<Option<u64>, anyhow::Error> {
+        let mut connection = self.redis_connection().await?;
+        let retry_after = async_std::future::timeout(
+            REDIS_TIMEOUT,
+            self.script
+                .key(key)
+                .arg(limit.capacity)
+                .arg(limit.refill_interval_milliseconds)
+                .arg(now)
+                .invoke_async::<_, u64>(&mut connection),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out waiting for redis."))
+        .and_then(|retry_after| retry_after.map_err(Into::into));
+        match retry_after {
+            Ok(retry_after) => Ok((retry_after > 0).then_some(retry_after)),
+            Err(e) => {
+                // Open a fresh connection on the next request.
+                *self.connection.lock().await = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// A handle on the shared redis connection, opening it if needed.
+    ///
+    /// The lock isn't held while connecting: requests racing to open the connection
+    /// each give up after the timeout, rather than queueing behind one another.
+    async fn redis_connection(&self) -> std::result::Result<MultiplexedConnection, anyhow::Error> {
+        if let Some(connection) = self.connection.lock().await.as_ref() {
+            return Ok(connection.clone());
+        }
+        let client = self
+            .redis_client
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No redis client configured."))?;
+        let opened = async_std::future::timeout(
+            REDIS_TIMEOUT,
+            client.get_multiplexed_async_std_connection(),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out connecting to redis."))??;
+        Ok(self.connection.lock().await.get_or_insert(opened).clone())
+    }
+
+    async fn take_token_from_memory(
+        &self,
+        key: &str,
+        limit: TokenBucketSettings,
+        now: u64,
+    ) -> Option<u64> {
+        let mut fallback = self.fallback.lock().await;
+        fallback.sweep(now);
+        fallback
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .take(limit, now)
+    }
+}
+
+#[tide::utils::async_trait]
+impl<S: Clone + Send + Sync + 'static> Middleware<S> for RateLimitMiddleware {
+    async fn handle(&self, mut req: tide::Request<S>, next: Next<'_, S>) -> Result {
+        for (key, limit) in self.buckets(&mut req).await? {
+            if let Some(retry_after) = self.take_token(&key, limit).await {
+                tracing::warn!(rate_limit.key = %key, "Request rejected by rate limit.");
+                return Ok(too_many_requests(retry_after));
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+/// Read the username out of a login form, leaving the body in place for the handler.
+async fn peek_username<S: Clone + Send + Sync + 'static>(
+    req: &mut tide::Request<S>,
+) -> Result<Option<String>> {
+    #[derive(serde::Deserialize)]
+    struct LoginForm {
+        username: String,
+    }
+
+    let bytes = req.take_body().into_bytes().await?;
+    let form = Body::from_bytes(bytes.clone())
+        .into_form::<LoginForm>()
+        .await
+        .ok();
+    let mut body = Body::from_bytes(bytes);
+    if let Some(mime) = req.content_type() {
+        body.set_mime(mime);
+    }
+    req.set_body(body);
+    Ok(form.map(|f| f.username))
+}
+
+fn too_many_requests(retry_after_milliseconds: u64) -> Response {
+    let mut response = Response::new(StatusCode::TooManyRequests);
+    response.insert_header(
+        headers::RETRY_AFTER,
+        retry_after_milliseconds.div_ceil(1000).to_string(),
+    );
+    response.set_body("Too many requests, please try again later.");
+    response
+}
+
+fn now_in_milliseconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the unix epoch.")
+        .as_millis() as u64
+}
+
+/// How often the in-memory buckets are swept.
+const SWEEP_INTERVAL_MILLISECONDS: u64 = 60_000;
+
+#[derive(Default)]
+struct MemoryBuckets {
+    buckets: HashMap<String, TokenBucket>,
+    swept_at: u64,
+}
+
+impl MemoryBuckets {
+    /// Drop the buckets that are full again, at most once per sweep interval.
+    ///
+    /// Full buckets behave exactly like missing ones, so this only bounds memory usage.
+    fn sweep(&mut self, now: u64) {
+        if now.saturating_sub(self.swept_at) < SWEEP_INTERVAL_MILLISECONDS {
+            return;
+        }
+        self.buckets.retain(|_, bucket| bucket.full_at > now);
+        self.swept_at = now;
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: u32,
+    // When the bucket was last refilled.
+    updated_at: u64,
+    // When the bucket will be back to its capacity.
+    full_at: u64,
+}
+
+impl TokenBucket {
+    fn new(limit: TokenBucketSettings, now: u64) -> Self {
+        Self {
+            tokens: limit.capacity,
+            updated_at: now,
+            full_at: now,
+        }
+    }
+
+    fn refilled(&self, limit: TokenBucketSettings, now: u64) -> u64 {
+        now.saturating_sub(self.updated_at) / limit.refill_interval_milliseconds
+    }
+
+    fn is_full(&self, limit: TokenBucketSettings, now: u64) -> bool {
+        self.tokens as u64 + self.refilled(limit, now) >= limit.capacity as u64
+    }
+
+    /// Take a token, returning how many milliseconds until one is available if the
+    /// bucket is empty.
+    fn take(&mut self, limit: TokenBucketSettings, now: u64) -> Option<u64> {
+        let refilled = self.refilled(limit, now);
+        if self.is_full(limit, now) {
+            self.tokens = limit.capacity;
+            self.updated_at = now;
+        } else if refilled > 0 {
+            self.tokens += refilled as u32;
+            self.updated_at += refilled * limit.refill_interval_milliseconds;
+        }
+        let retry_after = if self.tokens > 0 {
+            self.tokens -= 1;
+            None
+        } else {
+            Some(limit.refill_interval_milliseconds - (now - self.updated_at))
+        };
+        self.full_at = self.updated_at
+            + (limit.capacity - self.tokens) as u64 * limit.refill_interval_milliseconds;
+        retry_after
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MemoryBuckets, RateLimitMiddleware, TokenBucket, REDIS_TIMEOUT, SWEEP_INTERVAL_MILLISECONDS,
+    };
+    use crate::configuration::{RateLimitSettings, TokenBucketSettings};
+
+    const LIMIT: TokenBucketSettings = TokenBucketSettings {
+        capacity: 2,
+        refill_interval_milliseconds: 1000,
+    };
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_of_its_capacity() {
+        let mut bucket = TokenBucket::new(LIMIT, 0);
+        assert_eq!(bucket.take(LIMIT, 0), None);
+        assert_eq!(bucket.take(LIMIT, 0), None);
+        assert_eq!(bucket.take(LIMIT, 0), Some(1000));
+    }
+
+    #[test]
+    fn an_empty_bucket_tells_how_long_until_the_next_token() {
+        let mut bucket = TokenBucket::new(LIMIT, 0);
+        bucket.take(LIMIT, 0);
+        bucket.take(LIMIT, 0);
+        assert_eq!(bucket.take(LIMIT, 400), Some(600));
+        assert_eq!(bucket.take(LIMIT, 1000), None);
+        assert_eq!(bucket.take(LIMIT, 1500), Some(500));
+    }
+
+    #[test]
+    fn refills_never_exceed_the_capacity() {
+        let mut bucket = TokenBucket::new(LIMIT, 0);
+        bucket.take(LIMIT, 0);
+        assert_eq!(bucket.take(LIMIT, 60_000), None);
+        assert_eq!(bucket.take(LIMIT, 60_000), None);
+        assert_eq!(bucket.take(LIMIT, 60_000), Some(1000));
+    }
+
+    #[test]
+    fn sweeps_drop_full_buckets_once_per_interval() {
+        let mut fallback = MemoryBuckets::default();
+        let mut bucket = TokenBucket::new(LIMIT, 0);
+        bucket.take(LIMIT, 0);
+        fallback.buckets.insert("key".into(), bucket);
+
+        fallback.sweep(SWEEP_INTERVAL_MILLISECONDS - 1);
+        assert_eq!(fallback.buckets.len(), 1);
+        fallback.sweep(SWEEP_INTERVAL_MILLISECONDS);
+        assert!(fallback.buckets.is_empty());
+    }
+
+    #[async_std::test]
+    async fn a_silent_redis_falls_back_to_memory_after_the_timeout() {
+        // Connections are accepted, but nothing is ever read or written.
+        let limit = TokenBucketSettings {
+            capacity: 2,
+            refill_interval_milliseconds: 60_000,
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let settings = RateLimitSettings {
+            key_prefix: "test".into(),
+            login_per_ip: limit,
+            login_per_username: limit,
+            subscribe_per_ip: limit,
+            confirm_per_ip: limit,
+        };
+        let middleware =
+            RateLimitMiddleware::new(&format!("redis://127.0.0.1:{port}"), settings, false);
+
+        let taken = async_std::future::timeout(REDIS_TIMEOUT * 4, async {
+            for _ in 0..limit.capacity {
+                assert_eq!(middleware.take_token("key", limit).await, None);
+            }
+            middleware.take_token("key", limit).await
+        })
+        .await
+        .expect("The rate limit waited on redis past its timeout.");
+
+        assert!(taken.is_some());
+        drop(listener);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::authentication::get_api_tokens;
+use crate::authorization::Scope;
+use crate::csrf_middleware::csrf_field;
+use crate::login_middleware::UserId;
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use tide::http::Cookie;
+use tide::{Response, Result};
+
+pub async fn api_tokens_page(req: Request) -> Result {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let csrf_field = csrf_field(&req)?;
+    let tokens = get_api_tokens(user_id, &req.state().connection).await?;
+    let rows: String = tokens
+        .iter()
+        .map(|token| {
+            let scopes: Vec<_> = token.scopes.iter().map(|s| s.as_str()).collect();
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>
+                    <form action="/admin/api_tokens/revoke" method="post">
+                        {csrf_field}
+                        <input type="hidden" name="token_id" value="{}">
+                        <button type="submit">Revoke</button>
+                    </form>
+                </td></tr>"#,
+                escape_html(&token.name),
+                scopes.join(", "),
+                token.created_at.to_rfc3339(),
+                token
+                    .last_used_at
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_else(|| "Never".into()),
+                token.token_id,
+            )
+        })
+        .collect();
+    let scope_fields: String = Scope::ALL
+        .iter()
+        .map(|scope| {
+            format!(
+                r#"
+        <label><input type="checkbox" name="scopes" value="{0}"> {0}</label>"#,
+                scope.as_str()
+            )
+        })
+        .collect();
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <p>API tokens let programs act on your behalf, within your role and the scopes of the token.</p>
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Scopes</th>
+            <th>Created</th>
+            <th>Last used</th>
+            <th></th>
+        </tr>
+        {rows}
+    </table>
+    <form action="/admin/api_tokens/create" method="post">
+        {csrf_field}
+        <label>Name
+            <input type="text" placeholder="What the token is for" name="name">
+        </label>{scope_fields}
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,99 @@
+use crate::authorization::Scope;
+use crate::login_middleware::UserId;
+use crate::routes::utils::{attach_flashed_message, escape_html};
+use crate::Request;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct RevokeFormData {
+    token_id: Uuid,
+}
+
+fn user_id(req: &Request) -> Uuid {
+    req.ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0
+}
+
+fn back_to_tokens(req: &Request, msg: &str) -> Response {
+    let mut resp: Response = Redirect::see_other("/admin/api_tokens").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg.to_string());
+    resp
+}
+
+/// Create a token and show it, only this once.
+#[tracing::instrument(name = "Create an API token", skip(req))]
+pub async fn create_api_token(mut req: Request) -> Result {
+    // A pair per field, since every checked scope is sent under the same name.
+    let fields: Vec<(String, String)> = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let mut name = String::new();
+    let mut scopes = vec![];
+    for (field, value) in fields {
+        match field.as_str() {
+            "name" => name = value.trim().to_string(),
+            "scopes" => match Scope::try_from(value) {
+                Ok(scope) if !scopes.contains(&scope) => scopes.push(scope),
+                Ok(_) => {}
+                Err(e) => return Ok(back_to_tokens(&req, &e)),
+            },
+            _ => {}
+        }
+    }
+    if name.is_empty() {
+        return Ok(back_to_tokens(&req, "Give the token a name."));
+    }
+    if scopes.is_empty() {
+        return Ok(back_to_tokens(&req, "Pick at least one scope."));
+    }
+    let token = crate::authentication::create_api_token(
+        user_id(&req),
+        &name,
+        &scopes,
+        &req.state().connection,
+    )
+    .await?;
+    let name = escape_html(&name);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New API token</title>
+</head>
+<body>
+    <p>The token {name} has been created.</p>
+    <p>Copy it now, it won't be shown again. Send it as <code>Authorization: Bearer &lt;token&gt;</code>.</p>
+    <p><code>{token}</code></p>
+    <p><a href="/admin/api_tokens">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(req))]
+pub async fn revoke_api_token(mut req: Request) -> Result {
+    let form_data: RevokeFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let revoked = crate::authentication::revoke_api_token(
+        user_id(&req),
+        form_data.token_id,
+        &req.state().connection,
+    )
+    .await?;
+    let msg = if revoked {
+        "The token has been revoked."
+    } else {
+        "The token no longer exists."
+    };
+    Ok(back_to_tokens(&req, msg))
+}
@@ -1,3 +1,5 @@
+use crate::authorization::{required_role, Role};
+use crate::csrf_middleware::csrf_field;
 use crate::login_middleware::UserId;
 use crate::Request;
 use anyhow::Context;
@@ -10,7 +12,34 @@ pub async fn admin_dashboard(req: Request) -> Result {
     let user_id: &UserId = req
         .ext::<UserId>()
         .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?");
+    let role = *req
+        .ext::<Role>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?");
     let username = get_username(user_id.0, pool).await?;
+    // Only offer what the user's role lets them reach.
+    let actions: String = [
+        ("/admin/newsletters", "Send a newsletter issue"),
+        ("/admin/issues", "Past newsletter issues"),
+        (
+            "/admin/subscribers/export?format=csv",
+            "Export subscribers (CSV)",
+        ),
+        (
+            "/admin/subscribers/export?format=json",
+            "Export subscribers (JSON lines)",
+        ),
+        (
+            "/admin/subscribers/data",
+            "Export or erase a subscriber's data",
+        ),
+        ("/admin/login_activity", "Recent login activity"),
+        ("/admin/users", "Manage users"),
+    ]
+    .into_iter()
+    .filter(|(href, _)| required_role(href.split('?').next().unwrap_or(href)) <= role)
+    .map(|(href, label)| format!("\n        <li><a href=\"{href}\">{label}</a></li>"))
+    .collect();
+    let csrf_field = csrf_field(&req)?;
     let body = format!(
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -19,19 +48,23 @@ pub async fn admin_dashboard(req: Request) -> Result {
     <title>Admin dashboard</title>
 </head>
 <body>
-    <p>Welcome {username}!</p>
+    <p>Welcome {username}! You are signed in as {role}.</p>
     <p> Available actions: </p>
     <ol>
         <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/two_factor">Two-factor authentication</a></li>
+        <li><a href="/admin/sessions">Sessions</a></li>
+        <li><a href="/admin/api_tokens">API tokens</a></li>
         <li>
             <form name="logoutForm" action="/admin/logout" method="post">
+                {csrf_field}
                 <input type="submit" value="Logout">
             </form>
-        </li>
-        <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+        </li>{actions}
     </ol>
 </body>
-</html>"#
+</html>"#,
+        role = role.as_str()
     );
     let mut resp: Response = body.into();
     resp.set_content_type("text/html; charset=utf-8");
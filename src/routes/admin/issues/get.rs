@@ -0,0 +1,191 @@
+use crate::csrf_middleware::csrf_field;
+use crate::newsletter_issues::{get_issue, get_issues, DeliveryState, DeliveryStatus};
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use serde::Deserialize;
+use tide::http::Cookie;
+use tide::{Response, Result, StatusCode};
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize)]
+struct ListParameters {
+    page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct IssueParameters {
+    issue_id: Uuid,
+}
+
+/// Past issues and drafts, most recent first.
+pub async fn issues_page(req: Request) -> Result {
+    let parameters: ListParameters = req.query().map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let page = parameters.page.unwrap_or(1).max(1);
+    let issues = get_issues(&req.state().connection, PAGE_SIZE, (page - 1) * PAGE_SIZE).await?;
+    let rows: String = issues
+        .iter()
+        .map(|issue| {
+            format!(
+                r#"<tr><td><a href="/admin/issues/view?issue_id={}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+                issue.newsletter_issue_id,
+                escape_html(&issue.title),
+                issue
+                    .published_at
+                    .map(|at| at.to_rfc3339())
+                    .unwrap_or_default(),
+                delivery_state(issue.delivery.as_ref()),
+            )
+        })
+        .collect();
+    let older = if issues.len() as i64 == PAGE_SIZE {
+        format!(
+            r#"<p><a href="/admin/issues?page={}">Older issues -&gt;</a></p>"#,
+            page + 1
+        )
+    } else {
+        String::new()
+    };
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issues</title>
+</head>
+<body>
+    {msg_html}
+    <p>Newsletter issues</p>
+    <table>
+        <tr>
+            <th>Title</th>
+            <th>Published</th>
+            <th>Delivery</th>
+        </tr>
+        {rows}
+    </table>
+    {older}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+/// An issue as it was sent, with what can be done with it.
+pub async fn issue_page(req: Request) -> Result {
+    let parameters: IssueParameters = req.query().map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let issue = match get_issue(&req.state().connection, parameters.issue_id).await? {
+        Some(issue) => issue,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let csrf_field = csrf_field(&req)?;
+    let issue_id = issue.newsletter_issue_id;
+    let action = |action: &str, label: &str| {
+        format!(
+            r#"<form action="/admin/issues/{action}" method="post">
+        {csrf_field}
+        <input type="hidden" name="issue_id" value="{issue_id}">
+        <button type="submit">{label}</button>
+    </form>"#
+        )
+    };
+    let mut actions = vec![action("clone", "Copy into a new draft")];
+    match &issue.delivery {
+        None => actions.push(action("publish", "Publish")),
+        Some(delivery) if delivery.state == DeliveryState::Cancelled => {}
+        Some(delivery) => {
+            actions.push(action(
+                "resend",
+                "Send to subscribers who joined since it was sent",
+            ));
+            match delivery.state {
+                DeliveryState::Active if delivery.pending > 0 => {
+                    actions.push(action("pause", "Pause the delivery"))
+                }
+                DeliveryState::Paused => actions.push(action("resume", "Resume the delivery")),
+                _ => {}
+            }
+            if delivery.pending > 0 {
+                actions.push(action("cancel", "Cancel the remaining deliveries"));
+            }
+        }
+    }
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    <p>Created: {created_at}</p>
+    <p>Published: {published_at}</p>
+    <p>Delivery: {delivery}</p>
+    <p>Plain text content:</p>
+    <pre>{text_content}</pre>
+    <p>HTML content:</p>
+    <pre>{html_content}</pre>
+    {actions}
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+        title = escape_html(&issue.title),
+        created_at = issue.created_at.to_rfc3339(),
+        published_at = issue
+            .published_at
+            .map(|at| at.to_rfc3339())
+            .unwrap_or_else(|| "not yet".to_string()),
+        delivery = delivery_state(issue.delivery.as_ref()),
+        text_content = escape_html(&issue.text_content),
+        html_content = escape_html(&issue.html_content),
+        actions = actions.join("\n    "),
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+fn delivery_state(delivery: Option<&DeliveryStatus>) -> String {
+    let delivery = match delivery {
+        None => return "Draft".to_string(),
+        Some(delivery) => delivery,
+    };
+    let state = match delivery.state {
+        DeliveryState::Cancelled => format!(
+            "Cancelled after {} of {} sent",
+            delivery.sent, delivery.recipients
+        ),
+        DeliveryState::Paused => format!(
+            "Paused, {} of {} left",
+            delivery.pending, delivery.recipients
+        ),
+        DeliveryState::Active if delivery.pending > 0 => format!(
+            "Delivering, {} of {} left",
+            delivery.pending, delivery.recipients
+        ),
+        DeliveryState::Active => format!("Delivered to {}", delivery.sent),
+    };
+    if delivery.failed > 0 {
+        format!("{state}, {} failed", delivery.failed)
+    } else {
+        state
+    }
+}
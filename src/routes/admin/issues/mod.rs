@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use get::{issue_page, issues_page};
+pub use post::{
+    cancel_delivery, clone_issue, pause_delivery, publish_draft, resend_issue, resume_delivery,
+};
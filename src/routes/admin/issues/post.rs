@@ -0,0 +1,150 @@
+use crate::newsletter_issues::{
+    self, DeliveryChangeOutcome, DeliveryState, PublishOutcome, ResendOutcome,
+};
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use anyhow::Context;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct IssueFormData {
+    issue_id: Uuid,
+}
+
+async fn issue_id(req: &mut Request) -> Result<Uuid> {
+    let form_data: IssueFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    Ok(form_data.issue_id)
+}
+
+fn back_to_issue(req: &Request, issue_id: Uuid, msg: &str) -> Response {
+    let mut resp: Response =
+        Redirect::see_other(format!("/admin/issues/view?issue_id={issue_id}")).into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg.to_string());
+    resp
+}
+
+fn back_to_issues(req: &Request, msg: &str) -> Response {
+    let mut resp: Response = Redirect::see_other("/admin/issues").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg.to_string());
+    resp
+}
+
+#[tracing::instrument(name = "Copy an issue into a draft", skip(req))]
+pub async fn clone_issue(mut req: Request) -> Result {
+    let issue_id = issue_id(&mut req).await?;
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let draft_id = match newsletter_issues::clone_issue(&mut transaction, issue_id).await? {
+        Some(draft_id) => draft_id,
+        None => return Ok(back_to_issues(&req, "The issue no longer exists.")),
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to copy an issue.")?;
+    Ok(back_to_issue(
+        &req,
+        draft_id,
+        "This is a new draft, copied from the issue.",
+    ))
+}
+
+#[tracing::instrument(name = "Publish a draft", skip(req))]
+pub async fn publish_draft(mut req: Request) -> Result {
+    let issue_id = issue_id(&mut req).await?;
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let msg = match newsletter_issues::publish_issue(&mut transaction, issue_id).await? {
+        PublishOutcome::Published => "The newsletter issue has been published!",
+        PublishOutcome::AlreadyPublished => "The issue has already been published.",
+        PublishOutcome::NotFound => return Ok(back_to_issues(&req, "The issue no longer exists.")),
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish an issue.")?;
+    Ok(back_to_issue(&req, issue_id, msg))
+}
+
+#[tracing::instrument(name = "Resend an issue", skip(req))]
+pub async fn resend_issue(mut req: Request) -> Result {
+    let issue_id = issue_id(&mut req).await?;
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let msg = match newsletter_issues::resend_issue(&mut transaction, issue_id).await? {
+        ResendOutcome::Resent(0) => "Nobody joined since the issue was last sent.".to_string(),
+        ResendOutcome::Resent(queued) => {
+            format!("The issue is being sent to {queued} more subscribers.")
+        }
+        ResendOutcome::NotPublished => "Publish the draft first.".to_string(),
+        ResendOutcome::Cancelled => "The delivery of the issue was cancelled.".to_string(),
+        ResendOutcome::NotFound => return Ok(back_to_issues(&req, "The issue no longer exists.")),
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to resend an issue.")?;
+    Ok(back_to_issue(&req, issue_id, &msg))
+}
+
+#[tracing::instrument(name = "Pause the delivery of an issue", skip(req))]
+pub async fn pause_delivery(req: Request) -> Result {
+    change_delivery_state(req, DeliveryState::Paused, "The delivery has been paused.").await
+}
+
+#[tracing::instrument(name = "Resume the delivery of an issue", skip(req))]
+pub async fn resume_delivery(req: Request) -> Result {
+    change_delivery_state(req, DeliveryState::Active, "The delivery has been resumed.").await
+}
+
+#[tracing::instrument(name = "Cancel the delivery of an issue", skip(req))]
+pub async fn cancel_delivery(req: Request) -> Result {
+    change_delivery_state(
+        req,
+        DeliveryState::Cancelled,
+        "The delivery has been cancelled.",
+    )
+    .await
+}
+
+async fn change_delivery_state(mut req: Request, state: DeliveryState, msg: &str) -> Result {
+    let issue_id = issue_id(&mut req).await?;
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let msg =
+        match newsletter_issues::change_delivery_state(&mut transaction, issue_id, state).await? {
+            DeliveryChangeOutcome::Changed => msg,
+            DeliveryChangeOutcome::Unchanged => "Nothing to change.",
+            DeliveryChangeOutcome::AlreadyCancelled => "The delivery was already cancelled.",
+            DeliveryChangeOutcome::NotPublished => "Publish the draft first.",
+            DeliveryChangeOutcome::NotFound => {
+                return Ok(back_to_issues(&req, "The issue no longer exists."))
+            }
+        };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a delivery.")?;
+    Ok(back_to_issue(&req, issue_id, msg))
+}
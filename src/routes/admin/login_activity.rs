@@ -0,0 +1,48 @@
+use crate::authentication::get_recent_login_attempts;
+use crate::routes::utils::escape_html;
+use crate::Request;
+use tide::{Response, Result};
+
+// How many attempts the page lists, most recent first.
+const RECENT_ATTEMPTS: i64 = 100;
+
+pub async fn login_activity(req: Request) -> Result {
+    let attempts = get_recent_login_attempts(RECENT_ATTEMPTS, &req.state().connection).await?;
+    let rows: String = attempts
+        .iter()
+        .map(|attempt| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                attempt.attempted_at.to_rfc3339(),
+                escape_html(&attempt.username),
+                escape_html(attempt.client_ip.as_deref().unwrap_or_default()),
+                escape_html(&attempt.outcome),
+            )
+        })
+        .collect();
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Login activity</title>
+        </head>
+        <body>
+            <p>Recent login attempts</p>
+            <table>
+                <tr>
+                    <th>When</th>
+                    <th>Username</th>
+                    <th>IP</th>
+                    <th>Outcome</th>
+                </tr>
+                {rows}
+            </table>
+            <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
@@ -1,3 +1,4 @@
+use crate::authentication::revoke_session;
 use crate::routes::utils::attach_flashed_message;
 use crate::session_state::TypedSession;
 use crate::Request;
@@ -5,6 +6,9 @@ use tide::{Redirect, Response, Result};
 
 pub async fn log_out(req: Request) -> Result {
     let session = TypedSession::from_req(&req);
+    if let (Some(user_id), Some(session_id)) = (session.get_user_id(), session.get_session_id()) {
+        revoke_session(user_id, session_id, &req.state().connection).await?;
+    }
     if session.get_user_id().is_none() {
         Ok(Redirect::see_other("/login").into())
     } else {
@@ -1,9 +1,23 @@
-mod dashboard;
+mod api_tokens;
+pub(crate) mod dashboard;
+mod issues;
+mod login_activity;
 mod logout;
 mod newsletters;
 mod password;
+mod sessions;
+mod subscribers;
+mod two_factor;
+mod users;
 
+pub use api_tokens::*;
 pub use dashboard::admin_dashboard;
+pub use issues::*;
+pub use login_activity::login_activity;
 pub use logout::log_out;
 pub use newsletters::*;
 pub use password::*;
+pub use sessions::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
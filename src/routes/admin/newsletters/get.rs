@@ -1,10 +1,13 @@
+use crate::csrf_middleware::csrf_field;
 use crate::routes::utils::get_flashed_message;
 use crate::Request;
 use tide::http::Cookie;
 use tide::{Response, Result};
 
 pub async fn newsletter_form(req: Request) -> Result {
+    let csrf_field = csrf_field(&req)?;
     let message = get_flashed_message(&req);
+    let idempotency_key = uuid::Uuid::new_v4();
     let body = format!(
         r#"<!DOCTYPE html>
         <html lang="en">
@@ -15,6 +18,7 @@ pub async fn newsletter_form(req: Request) -> Result {
         <body>
             {message}
             <form action="/admin/newsletters" method="post">
+                {csrf_field}
                 <label>Title:<br>
                     <input
                         type="text"
@@ -41,6 +45,7 @@ pub async fn newsletter_form(req: Request) -> Result {
                     ></textarea>
                 </label>
                 <br>
+                <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
                 <button type="submit">Publish</button>
             </form>
             <p><a href="/admin/dashboard">&lt;- Back</a></p>
@@ -1,9 +1,11 @@
+use crate::csrf_middleware::csrf_field;
 use crate::routes::utils::get_flashed_message;
 use crate::Request;
 use tide::http::Cookie;
 use tide::{Response, Result};
 
 pub async fn change_password_form(req: Request) -> Result {
+    let csrf_field = csrf_field(&req)?;
     let msg_html = get_flashed_message(&req);
     let body = format!(
         r#"
@@ -18,6 +20,7 @@ pub async fn change_password_form(req: Request) -> Result {
     <body>
         {msg_html}
         <form action="/admin/password" method="post">
+            {csrf_field}
             <label>Current password
                 <input type="password" placeholder="Enter current password" name="current_password">
             </label>
@@ -1,5 +1,6 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::login_middleware::UserId;
+use crate::authentication::{revoke_other_sessions, validate_credentials, AuthError, Credentials};
+use crate::domain::NewPassword;
+use crate::login_middleware::{SessionId, UserId};
 use crate::routes::admin::dashboard::get_username;
 use crate::routes::utils::attach_flashed_message;
 use crate::Request;
@@ -19,6 +20,10 @@ pub async fn change_password(mut req: Request) -> Result {
         .ext::<UserId>()
         .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware")
         .0;
+    let session_id = req
+        .ext::<SessionId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware")
+        .0;
 
     let data: FormData = req.body_form().await.map_err(|mut e| {
         e.set_status(StatusCode::BadRequest);
@@ -35,11 +40,27 @@ pub async fn change_password(mut req: Request) -> Result {
 
     let pool = &req.state().connection;
     let username = get_username(user_id, pool).await?;
+    let new_password =
+        match NewPassword::parse(data.new_password, &username, &req.state().password_policy) {
+            Ok(new_password) => new_password,
+            Err(e) => {
+                let mut response: Response = Redirect::see_other("/admin/password").into();
+                attach_flashed_message(&mut response, hmac_key, e);
+                return Ok(response);
+            }
+        };
     let credentials = Credentials {
         username,
         password: data.current_password.clone(),
     };
-    if let Err(e) = validate_credentials(credentials, pool).await {
+    if let Err(e) = validate_credentials(
+        credentials,
+        &req.state().password_hashing,
+        &req.state().dummy_password_hash,
+        pool,
+    )
+    .await
+    {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 let mut response: Response = Redirect::see_other("/admin/password").into();
@@ -54,7 +75,15 @@ pub async fn change_password(mut req: Request) -> Result {
         };
     }
 
-    crate::authentication::change_password(user_id, data.new_password, pool).await?;
+    crate::authentication::change_password(
+        user_id,
+        new_password,
+        &req.state().password_hashing,
+        pool,
+    )
+    .await?;
+    // Whoever may have known the old password gets logged out.
+    revoke_other_sessions(user_id, session_id, pool).await?;
     let mut resp: Response = Redirect::see_other("/admin/password").into();
     attach_flashed_message(
         &mut resp,
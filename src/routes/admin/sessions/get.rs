@@ -0,0 +1,80 @@
+use crate::authentication::get_user_sessions;
+use crate::csrf_middleware::csrf_field;
+use crate::login_middleware::{SessionId, UserId};
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use tide::http::Cookie;
+use tide::{Response, Result};
+
+pub async fn sessions_page(req: Request) -> Result {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let current_session_id = req
+        .ext::<SessionId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let csrf_field = csrf_field(&req)?;
+    let state = req.state();
+    let sessions = get_user_sessions(user_id, &state.session_settings, &state.connection).await?;
+    let rows: String = sessions
+        .iter()
+        .map(|session| {
+            let action = if session.session_id == current_session_id {
+                "This session".to_string()
+            } else {
+                format!(
+                    r#"<form action="/admin/sessions/revoke" method="post">
+                        {csrf_field}
+                        <input type="hidden" name="session_id" value="{}">
+                        <button type="submit">Revoke</button>
+                    </form>"#,
+                    session.session_id
+                )
+            };
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                session.created_at.to_rfc3339(),
+                session.last_seen_at.to_rfc3339(),
+                escape_html(session.client_ip.as_deref().unwrap_or_default()),
+                escape_html(session.user_agent.as_deref().unwrap_or_default()),
+                action,
+            )
+        })
+        .collect();
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Sessions</title>
+</head>
+<body>
+    {msg_html}
+    <p>Where you are logged in</p>
+    <table>
+        <tr>
+            <th>Started</th>
+            <th>Last active</th>
+            <th>IP</th>
+            <th>Browser</th>
+            <th></th>
+        </tr>
+        {rows}
+    </table>
+    <form action="/admin/sessions/revoke_others" method="post">
+        {csrf_field}
+        <button type="submit">Log out everywhere else</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,71 @@
+use crate::login_middleware::{SessionId, UserId};
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct RevokeFormData {
+    session_id: Uuid,
+}
+
+fn ids(req: &Request) -> (Uuid, Uuid) {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let session_id = req
+        .ext::<SessionId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    (user_id, session_id)
+}
+
+fn back_to_sessions(req: &Request, msg: &str) -> Response {
+    let mut resp: Response = Redirect::see_other("/admin/sessions").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg.to_string());
+    resp
+}
+
+#[tracing::instrument(name = "Revoke a session", skip(req))]
+pub async fn revoke_session(mut req: Request) -> Result {
+    let form_data: RevokeFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let (user_id, current_session_id) = ids(&req);
+    if form_data.session_id == current_session_id {
+        return Ok(back_to_sessions(
+            &req,
+            "Log out to end the session you are using.",
+        ));
+    }
+    let revoked = crate::authentication::revoke_session(
+        user_id,
+        form_data.session_id,
+        &req.state().connection,
+    )
+    .await?;
+    let msg = if revoked {
+        "The session has been revoked."
+    } else {
+        "The session no longer exists."
+    };
+    Ok(back_to_sessions(&req, msg))
+}
+
+#[tracing::instrument(name = "Revoke all other sessions", skip(req))]
+pub async fn revoke_other_sessions(req: Request) -> Result {
+    let (user_id, current_session_id) = ids(&req);
+    let revoked = crate::authentication::revoke_other_sessions(
+        user_id,
+        current_session_id,
+        &req.state().connection,
+    )
+    .await?;
+    Ok(back_to_sessions(
+        &req,
+        &format!("{revoked} other sessions have been revoked."),
+    ))
+}
@@ -0,0 +1,71 @@
+use crate::consent::get_consent_events;
+use crate::routes::utils::{attach_flashed_message, escape_html};
+use crate::subscriber_data::get_subscriber_id_from_email;
+use crate::Request;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result};
+
+#[derive(Deserialize)]
+struct Parameters {
+    email: String,
+}
+
+pub async fn subscriber_consent(req: Request) -> Result {
+    let parameters: Parameters = req.query()?;
+    let pool = &req.state().connection;
+    let subscriber_id = match get_subscriber_id_from_email(pool, &parameters.email).await? {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let mut resp: Response = Redirect::see_other("/admin/subscribers/data").into();
+            attach_flashed_message(
+                &mut resp,
+                &req.state().hmac_secret,
+                format!("No subscriber found for {}.", parameters.email),
+            );
+            return Ok(resp);
+        }
+    };
+    let events = get_consent_events(pool, subscriber_id).await?;
+    let rows: String = events
+        .iter()
+        .map(|event| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&event.kind),
+                event.occurred_at.to_rfc3339(),
+                escape_html(event.client_ip.as_deref().unwrap_or_default()),
+                escape_html(event.user_agent.as_deref().unwrap_or_default()),
+                escape_html(event.source.as_deref().unwrap_or_default()),
+                escape_html(&event.consent_text_version),
+            )
+        })
+        .collect();
+    let email = escape_html(&parameters.email);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Consent history</title>
+        </head>
+        <body>
+            <p>Consent history of {email}</p>
+            <table>
+                <tr>
+                    <th>Event</th>
+                    <th>When</th>
+                    <th>IP</th>
+                    <th>User agent</th>
+                    <th>Source</th>
+                    <th>Consent text version</th>
+                </tr>
+                {rows}
+            </table>
+            <p><a href="/admin/subscribers/data">&lt;- Back</a></p>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
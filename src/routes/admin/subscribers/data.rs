@@ -0,0 +1,112 @@
+use crate::csrf_middleware::csrf_field;
+use crate::login_middleware::UserId;
+use crate::routes::utils::{attach_flashed_message, get_flashed_message};
+use crate::subscriber_data::{
+    erase_subscriber_data, export_subscriber_data, get_subscriber_id_from_email, Requester,
+};
+use crate::Request;
+use serde::Deserialize;
+use tide::http::Cookie;
+use tide::{Body, Redirect, Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+struct Parameters {
+    email: String,
+}
+
+pub async fn subscriber_data_form(req: Request) -> Result {
+    let csrf_field = csrf_field(&req)?;
+    let message = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Subscriber data</title>
+        </head>
+        <body>
+            {message}
+            <form action="/admin/subscribers/data/export" method="get">
+                <label>Email <input type="email" placeholder="Enter the subscriber email" name="email"></label>
+                <button type="submit">Export data</button>
+            </form>
+            <form action="/admin/subscribers/consent" method="get">
+                <label>Email <input type="email" placeholder="Enter the subscriber email" name="email"></label>
+                <button type="submit">View consent history</button>
+            </form>
+            <form action="/admin/subscribers/data/erase" method="post">
+                {csrf_field}
+                <label>Email <input type="email" placeholder="Enter the subscriber email" name="email"></label>
+                <button type="submit">Erase data</button>
+            </form>
+            <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+pub async fn export_subscriber(req: Request) -> Result {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let parameters: Parameters = req.query()?;
+    let pool = &req.state().connection;
+    let export = match get_subscriber_id_from_email(pool, &parameters.email).await? {
+        Some(subscriber_id) => {
+            export_subscriber_data(pool, subscriber_id, Requester::Admin(user_id)).await?
+        }
+        None => None,
+    };
+    match export {
+        Some(export) => {
+            let mut resp = Response::new(StatusCode::Ok);
+            resp.set_body(Body::from_json(&export)?);
+            resp.insert_header(
+                "Content-Disposition",
+                r#"attachment; filename="subscriber-data.json""#,
+            );
+            Ok(resp)
+        }
+        None => {
+            let mut resp: Response = Redirect::see_other("/admin/subscribers/data").into();
+            attach_flashed_message(
+                &mut resp,
+                &req.state().hmac_secret,
+                format!("No subscriber found for {}.", parameters.email),
+            );
+            Ok(resp)
+        }
+    }
+}
+
+pub async fn erase_subscriber(mut req: Request) -> Result {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let form_data: Parameters = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let pool = &req.state().connection;
+    let erased = match get_subscriber_id_from_email(pool, &form_data.email).await? {
+        Some(subscriber_id) => {
+            erase_subscriber_data(pool, subscriber_id, Requester::Admin(user_id)).await?
+        }
+        None => false,
+    };
+    let message = if erased {
+        format!("The data of {} has been erased.", form_data.email)
+    } else {
+        format!("No subscriber found for {}.", form_data.email)
+    };
+    let mut resp: Response = Redirect::see_other("/admin/subscribers/data").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, message);
+    Ok(resp)
+}
@@ -0,0 +1,197 @@
+use crate::Request;
+use async_std::channel::{bounded, Sender};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::PgPool;
+use std::borrow::Cow;
+use tide::{Body, Response, Result, StatusCode};
+use uuid::Uuid;
+
+// How many encoded rows may be buffered before the database cursor waits for the client.
+const EXPORT_BUFFER_SIZE: usize = 64;
+const CSV_HEADER: &str =
+    "id,email,name,status,subscribed_at,consent_text_version,consent_confirmed_at\n";
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(serde::Deserialize)]
+struct Parameters {
+    #[serde(default)]
+    format: ExportFormat,
+    // Comma separated list of statuses, e.g. `confirmed,pending_confirmation`.
+    status: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    // Taken from the latest consent confirmation, if any.
+    consent_text_version: Option<String>,
+    consent_confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl SubscriberRecord {
+    fn encode(&self, format: ExportFormat) -> std::result::Result<Vec<u8>, serde_json::Error> {
+        match format {
+            ExportFormat::Csv => Ok(format!(
+                "{},{},{},{},{},{},{}\n",
+                self.id,
+                csv_field(&self.email),
+                csv_field(&self.name),
+                csv_field(&self.status),
+                self.subscribed_at.to_rfc3339(),
+                csv_field(self.consent_text_version.as_deref().unwrap_or_default()),
+                self.consent_confirmed_at
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default()
+            )
+            .into_bytes()),
+            ExportFormat::Json => {
+                let mut line = serde_json::to_vec(self)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+/// Stream every subscriber as CSV or JSON lines.
+///
+/// Rows are pulled from a database cursor by a background task and pushed through a
+/// bounded channel, so the whole table is never held in memory.
+pub async fn export_subscribers(req: Request) -> Result {
+    let parameters: Parameters = req.query().map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let statuses = parameters.status.map(|s| {
+        s.split(',')
+            .map(|status| status.trim().to_string())
+            .filter(|status| !status.is_empty())
+            .collect::<Vec<_>>()
+    });
+    let format = parameters.format;
+    let pool = req.state().connection.clone();
+
+    let (sender, receiver) = bounded(EXPORT_BUFFER_SIZE);
+    async_std::task::spawn(write_subscribers(pool, statuses, format, sender));
+
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_reader(receiver.into_async_read(), None));
+    let (content_type, filename) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "subscribers.csv"),
+        ExportFormat::Json => ("application/x-ndjson", "subscribers.jsonl"),
+    };
+    resp.set_content_type(content_type);
+    resp.insert_header(
+        "Content-Disposition",
+        format!(r#"attachment; filename="{filename}""#),
+    );
+    Ok(resp)
+}
+
+#[tracing::instrument(name = "Export subscribers", skip(pool, sender))]
+async fn write_subscribers(
+    pool: PgPool,
+    statuses: Option<Vec<String>>,
+    format: ExportFormat,
+    sender: Sender<std::io::Result<Vec<u8>>>,
+) {
+    if let Err(e) = try_write_subscribers(&pool, statuses, format, &sender).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to export subscribers."
+        );
+        // Surface the failure to the reader so the response is aborted instead of
+        // looking like a complete (but truncated) export.
+        let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+    }
+}
+
+async fn try_write_subscribers(
+    pool: &PgPool,
+    statuses: Option<Vec<String>>,
+    format: ExportFormat,
+    sender: &Sender<std::io::Result<Vec<u8>>>,
+) -> std::result::Result<(), anyhow::Error> {
+    if let ExportFormat::Csv = format {
+        if sender.send(Ok(CSV_HEADER.into())).await.is_err() {
+            return Ok(());
+        }
+    }
+    let mut rows = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.status,
+            s.subscribed_at,
+            c.consent_text_version as "consent_text_version?",
+            c.occurred_at as "consent_confirmed_at?"
+        FROM subscriptions s
+        LEFT JOIN LATERAL (
+            SELECT consent_text_version, occurred_at
+            FROM consent_events
+            WHERE subscriber_id = s.id AND kind = 'confirmation'
+            ORDER BY occurred_at DESC
+            LIMIT 1
+        ) c ON true
+        WHERE $1::text[] IS NULL OR s.status = ANY($1)
+        ORDER BY s.subscribed_at, s.id
+        "#,
+        statuses.as_deref()
+    )
+    .fetch(pool);
+    while let Some(record) = rows.try_next().await? {
+        // The receiver is gone once the client disconnects, stop reading rows then.
+        if sender.send(Ok(record.encode(format)?)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn plain_values_are_not_quoted() {
+        assert_eq!(
+            csv_field("ursula_le_guin@gmail.com"),
+            "ursula_le_guin@gmail.com"
+        );
+    }
+
+    #[test]
+    fn values_with_separators_are_quoted() {
+        assert_eq!(csv_field("le guin, ursula"), "\"le guin, ursula\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn quotes_are_doubled() {
+        assert_eq!(csv_field("the \"best\""), "\"the \"\"best\"\"\"");
+    }
+}
@@ -0,0 +1,7 @@
+mod consent;
+mod data;
+mod export;
+
+pub use consent::*;
+pub use data::*;
+pub use export::*;
@@ -0,0 +1,87 @@
+use crate::authentication::{get_two_factor_status, TwoFactorStatus};
+use crate::csrf_middleware::csrf_field;
+use crate::login_middleware::UserId;
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use anyhow::Context;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use tide::http::Cookie;
+use tide::{Response, Result};
+
+pub async fn two_factor_settings(req: Request) -> Result {
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let state = req.state();
+    let csrf_field = csrf_field(&req)?;
+    let status =
+        get_two_factor_status(user_id, &state.two_factor_settings, &state.connection).await?;
+    let content = match status {
+        TwoFactorStatus::Disabled => format!(
+            r#"<p>Two-factor authentication is off.</p>
+        <form action="/admin/two_factor/enroll" method="post">
+            {csrf_field}
+            <button type="submit">Set up two-factor authentication</button>
+        </form>"#
+        ),
+        TwoFactorStatus::Pending {
+            otpauth_uri,
+            secret,
+        } => {
+            let qr_code = QrCode::new(otpauth_uri.as_bytes())
+                .context("Failed to encode the otpauth uri as a QR code.")?
+                .render::<svg::Color>()
+                .min_dimensions(200, 200)
+                .build();
+            let qr_code = base64::encode(qr_code);
+            let otpauth_uri = escape_html(&otpauth_uri);
+            format!(
+                r#"<p>Scan this QR code with your authenticator app, or enter the key by hand.</p>
+        <p><img alt="QR code" src="data:image/svg+xml;base64,{qr_code}"></p>
+        <p>Key: <code>{secret}</code></p>
+        <p><a href="{otpauth_uri}">{otpauth_uri}</a></p>
+        <form action="/admin/two_factor/confirm" method="post">
+            {csrf_field}
+            <label>Code from the app
+                <input type="text" autocomplete="one-time-code" placeholder="123456" name="code">
+            </label>
+            <button type="submit">Turn on</button>
+        </form>"#
+            )
+        }
+        TwoFactorStatus::Enabled {
+            recovery_codes_left,
+        } => format!(
+            r#"<p>Two-factor authentication is on, {recovery_codes_left} recovery codes left.</p>
+        <form action="/admin/two_factor/disable" method="post">
+            {csrf_field}
+            <label>Current password
+                <input type="password" placeholder="Enter current password" name="current_password">
+            </label>
+            <button type="submit">Turn off</button>
+        </form>"#
+        ),
+    };
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {content}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,122 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::login_middleware::UserId;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use secrecy::Secret;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+struct ConfirmFormData {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct DisableFormData {
+    current_password: Secret<String>,
+}
+
+fn user_id(req: &Request) -> uuid::Uuid {
+    req.ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0
+}
+
+fn back_to_settings(req: &Request, msg: &str) -> Response {
+    let mut resp: Response = Redirect::see_other("/admin/two_factor").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg.to_string());
+    resp
+}
+
+#[tracing::instrument(name = "Enroll in two-factor authentication", skip(req))]
+pub async fn enroll_two_factor(req: Request) -> Result {
+    let state = req.state();
+    crate::authentication::start_two_factor_enrollment(
+        user_id(&req),
+        &state.two_factor_settings,
+        &state.connection,
+    )
+    .await?;
+    Ok(Redirect::see_other("/admin/two_factor").into())
+}
+
+/// Turn two-factor authentication on and show the recovery codes, only this once.
+#[tracing::instrument(name = "Confirm two-factor authentication", skip(req))]
+pub async fn confirm_two_factor(mut req: Request) -> Result {
+    let form_data: ConfirmFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let state = req.state();
+    let recovery_codes = match crate::authentication::confirm_two_factor(
+        user_id(&req),
+        &form_data.code,
+        &state.two_factor_settings,
+        &state.connection,
+    )
+    .await?
+    {
+        Some(recovery_codes) => recovery_codes,
+        None => {
+            return Ok(back_to_settings(
+                &req,
+                "The code is invalid, please try again.",
+            ))
+        }
+    };
+    let recovery_codes: String = recovery_codes
+        .iter()
+        .map(|code| format!("\n        <li><code>{code}</code></li>"))
+        .collect();
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Recovery codes</title>
+</head>
+<body>
+    <p>Two-factor authentication is on.</p>
+    <p>Keep these recovery codes somewhere safe. Each of them lets you log in once without your authenticator app, they won't be shown again.</p>
+    <ul>{recovery_codes}
+    </ul>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(req))]
+pub async fn disable_two_factor(mut req: Request) -> Result {
+    let form_data: DisableFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let user_id = user_id(&req);
+    let pool = &req.state().connection;
+    let credentials = Credentials {
+        username: get_username(user_id, pool).await?,
+        password: form_data.current_password,
+    };
+    if let Err(e) = validate_credentials(
+        credentials,
+        &req.state().password_hashing,
+        &req.state().dummy_password_hash,
+        pool,
+    )
+    .await
+    {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                Ok(back_to_settings(&req, "The current password is incorrect"))
+            }
+            _ => Err(e.into()),
+        };
+    }
+    crate::authentication::disable_two_factor(user_id, pool).await?;
+    Ok(back_to_settings(&req, "Two-factor authentication is off."))
+}
@@ -0,0 +1,177 @@
+use crate::authorization::Role;
+use crate::csrf_middleware::csrf_field;
+use crate::login_middleware::UserId;
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tide::http::Cookie;
+use tide::{Response, Result};
+use uuid::Uuid;
+
+struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+    active: bool,
+}
+
+struct PendingInvitation {
+    email: String,
+    role: String,
+    created_at: DateTime<Utc>,
+}
+
+pub async fn users_page(req: Request) -> Result {
+    let current_user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let pool = &req.state().connection;
+    let csrf_field = csrf_field(&req)?;
+    let msg_html = get_flashed_message(&req);
+    let users: String = get_users(pool)
+        .await?
+        .iter()
+        .map(|user| {
+            let user_id = user.user_id;
+            let (status, toggle_action, toggle_label) = if user.active {
+                ("active", "deactivate", "Deactivate")
+            } else {
+                ("deactivated", "activate", "Activate")
+            };
+            let role_options = role_options(&user.role);
+            let you = if user_id == current_user_id {
+                " (you)"
+            } else {
+                ""
+            };
+            format!(
+                r#"<tr>
+                    <td>{}{you}</td>
+                    <td>{}</td>
+                    <td>{status}</td>
+                    <td>
+                        <form action="/admin/users/role" method="post">
+                            {csrf_field}
+                            <input hidden type="text" name="user_id" value="{user_id}">
+                            <select name="role">{role_options}</select>
+                            <button type="submit">Change role</button>
+                        </form>
+                    </td>
+                    <td>
+                        <form action="/admin/users/{toggle_action}" method="post">
+                            {csrf_field}
+                            <input hidden type="text" name="user_id" value="{user_id}">
+                            <button type="submit">{toggle_label}</button>
+                        </form>
+                        <form action="/admin/users/delete" method="post">
+                            {csrf_field}
+                            <input hidden type="text" name="user_id" value="{user_id}">
+                            <button type="submit">Delete</button>
+                        </form>
+                    </td>
+                </tr>"#,
+                escape_html(&user.username),
+                escape_html(user.email.as_deref().unwrap_or_default()),
+            )
+        })
+        .collect();
+    let invitations: String = get_pending_invitations(pool)
+        .await?
+        .iter()
+        .map(|invitation| {
+            format!(
+                "<li>{} as {} (invited {})</li>",
+                escape_html(&invitation.email),
+                escape_html(&invitation.role),
+                invitation.created_at.to_rfc3339()
+            )
+        })
+        .collect();
+    let invite_role_options = role_options(Role::Viewer.as_str());
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Users</title>
+        </head>
+        <body>
+            {msg_html}
+            <table>
+                <tr>
+                    <th>Username</th>
+                    <th>Email</th>
+                    <th>Status</th>
+                    <th>Role</th>
+                    <th>Actions</th>
+                </tr>
+                {users}
+            </table>
+            <p>Pending invitations</p>
+            <ul>{invitations}</ul>
+            <form action="/admin/users/invite" method="post">
+                {csrf_field}
+                <label>Email
+                    <input type="text" placeholder="Enter the email to invite" name="email">
+                </label>
+                <label>Role
+                    <select name="role">{invite_role_options}</select>
+                </label>
+                <button type="submit">Invite</button>
+            </form>
+            <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+fn role_options(selected: &str) -> String {
+    [Role::Viewer, Role::Editor, Role::Owner]
+        .iter()
+        .map(|role| {
+            let role = role.as_str();
+            let selected = if role == selected { " selected" } else { "" };
+            format!(r#"<option value="{role}"{selected}>{role}</option>"#)
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Get users", skip(pool))]
+async fn get_users(pool: &PgPool) -> std::result::Result<Vec<User>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        User,
+        r#"SELECT user_id, username, email, role, active FROM users ORDER BY username"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch users.")?;
+    Ok(users)
+}
+
+#[tracing::instrument(name = "Get pending invitations", skip(pool))]
+async fn get_pending_invitations(
+    pool: &PgPool,
+) -> std::result::Result<Vec<PendingInvitation>, anyhow::Error> {
+    let invitations = sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        SELECT email, role, created_at
+        FROM user_invitations
+        WHERE created_at > now() - interval '7 days'
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch pending invitations.")?;
+    Ok(invitations)
+}
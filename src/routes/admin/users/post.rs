@@ -0,0 +1,261 @@
+use crate::authorization::Role;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::login_middleware::UserId;
+use crate::routes::subscriptions::generate_subscription_token;
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use tide::{Redirect, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct InviteFormData {
+    email: String,
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct RoleFormData {
+    user_id: Uuid,
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct UserFormData {
+    user_id: Uuid,
+}
+
+fn back_to_users(req: &Request, msg: String) -> Response {
+    let mut resp: Response = Redirect::see_other("/admin/users").into();
+    attach_flashed_message(&mut resp, &req.state().hmac_secret, msg);
+    resp
+}
+
+/// Email a single-use link letting someone create their own account.
+#[tracing::instrument(name = "Invite a user", skip(req))]
+pub async fn invite_user(mut req: Request) -> Result {
+    let invited_by = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    let form_data: InviteFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let email = match SubscriberEmail::parse(form_data.email) {
+        Ok(email) => email,
+        Err(e) => return Ok(back_to_users(&req, e)),
+    };
+    let role = Role::try_from(form_data.role)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+    let state = req.state();
+    let invitation_token = generate_subscription_token();
+    store_invitation(
+        &state.connection,
+        &invitation_token,
+        &email,
+        role,
+        invited_by,
+    )
+    .await?;
+    send_invitation_email(
+        &state.email_client,
+        &email,
+        &state.base_url,
+        &invitation_token,
+    )
+    .await
+    .map_err(|e| e.into_inner())
+    .context("Failed to send the invitation email.")?;
+    Ok(back_to_users(
+        &req,
+        format!("An invitation has been sent to {}.", email.as_ref()),
+    ))
+}
+
+#[tracing::instrument(name = "Deactivate a user", skip(req))]
+pub async fn deactivate_user(mut req: Request) -> Result {
+    let form_data: UserFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let pool = &req.state().connection;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if is_last_active_owner(&mut transaction, form_data.user_id).await? {
+        return Ok(back_to_users(
+            &req,
+            "The last active owner can't be deactivated.".into(),
+        ));
+    }
+    sqlx::query!(
+        r#"UPDATE users SET active = false WHERE user_id = $1"#,
+        form_data.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to deactivate a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to deactivate a user.")?;
+    Ok(back_to_users(&req, "The user has been deactivated.".into()))
+}
+
+#[tracing::instrument(name = "Activate a user", skip(req))]
+pub async fn activate_user(mut req: Request) -> Result {
+    let form_data: UserFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    sqlx::query!(
+        r#"UPDATE users SET active = true WHERE user_id = $1"#,
+        form_data.user_id
+    )
+    .execute(&req.state().connection)
+    .await
+    .context("Failed to activate a user.")?;
+    Ok(back_to_users(&req, "The user has been activated.".into()))
+}
+
+#[tracing::instrument(name = "Delete a user", skip(req))]
+pub async fn delete_user(mut req: Request) -> Result {
+    let form_data: UserFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let pool = &req.state().connection;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if is_last_active_owner(&mut transaction, form_data.user_id).await? {
+        return Ok(back_to_users(
+            &req,
+            "The last active owner can't be deleted.".into(),
+        ));
+    }
+    sqlx::query!(r#"DELETE FROM users WHERE user_id = $1"#, form_data.user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+    Ok(back_to_users(&req, "The user has been deleted.".into()))
+}
+
+#[tracing::instrument(name = "Change the role of a user", skip(req))]
+pub async fn change_user_role(mut req: Request) -> Result {
+    let form_data: RoleFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let role = Role::try_from(form_data.role)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+    let pool = &req.state().connection;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if role != Role::Owner && is_last_active_owner(&mut transaction, form_data.user_id).await? {
+        return Ok(back_to_users(
+            &req,
+            "The last active owner must stay an owner.".into(),
+        ));
+    }
+    sqlx::query!(
+        r#"UPDATE users SET role = $2 WHERE user_id = $1"#,
+        form_data.user_id,
+        role.as_str()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to change the role of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user.")?;
+    Ok(back_to_users(
+        &req,
+        format!("The user is now {}.", role.as_str()),
+    ))
+}
+
+/// Whether `user_id` is the only active owner left, who alone can manage users.
+///
+/// Active owners stay locked until the transaction ends, so two owners can't remove
+/// each other at the same time.
+async fn is_last_active_owner(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> std::result::Result<bool, anyhow::Error> {
+    let active_owners: Vec<Uuid> =
+        sqlx::query!(r#"SELECT user_id FROM users WHERE active AND role = 'owner' FOR UPDATE"#)
+            .fetch_all(transaction)
+            .await
+            .context("Failed to fetch active owners.")?
+            .into_iter()
+            .map(|r| r.user_id)
+            .collect();
+    Ok(active_owners == [user_id])
+}
+
+#[tracing::instrument(name = "Store invitation", skip(pool, invitation_token))]
+async fn store_invitation(
+    pool: &PgPool,
+    invitation_token: &str,
+    email: &SubscriberEmail,
+    role: Role,
+    invited_by: Uuid,
+) -> std::result::Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (invitation_token, email, role, invited_by, created_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        invitation_token,
+        email.as_ref(),
+        role.as_str(),
+        invited_by
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the invitation.")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an invitation email",
+    skip(email_client, base_url, invitation_token)
+)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    invitation_token: &str,
+) -> std::result::Result<(), surf::Error> {
+    let invitation_link =
+        format!("{base_url}/invitations/accept?invitation_token={invitation_token}");
+    email_client
+        .send_email(
+            recipient,
+            "You have been invited to manage the newsletter",
+            &format!(
+                "You have been invited to manage the newsletter.<br />\
+                Click <a href=\"{invitation_link}\">here</a> to create your account. \
+                The link is valid for 7 days."
+            ),
+            &format!(
+                "You have been invited to manage the newsletter.\n\
+                Visit {invitation_link} to create your account. The link is valid for 7 days."
+            ),
+        )
+        .await
+}
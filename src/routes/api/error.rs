@@ -0,0 +1,78 @@
+use crate::routes::subscriptions::SubscribeError;
+use crate::routes::{LoginError, PublishError};
+use crate::State;
+use tide::{Body, Middleware, Next, Response, Result, StatusCode};
+
+/// Give the failures of the JSON API a JSON body.
+///
+/// The body is `{"error": {"code": ..., "message": ...}}`, where the code is meant for
+/// programs and the message for people. Both come from the error of the response when
+/// it is one of ours, from its status otherwise.
+#[derive(Default)]
+pub struct ApiErrorMiddleware;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for ApiErrorMiddleware {
+    async fn handle(&self, req: tide::Request<State>, next: Next<'_, State>) -> Result {
+        let is_api = req.url().path().starts_with("/api/");
+        let mut res = next.run(req).await;
+        if is_api && (res.status().is_client_error() || res.status().is_server_error()) {
+            let (code, message) = describe(&res);
+            res.set_body(Body::from_json(&serde_json::json!({
+                "error": { "code": code, "message": message }
+            }))?);
+        }
+        Ok(res)
+    }
+}
+
+fn describe(res: &Response) -> (&'static str, String) {
+    if let Some(e) = res.downcast_error::<SubscribeError>() {
+        return match e {
+            SubscribeError::ValidationError(_) | SubscribeError::SuspectedBot(_) => {
+                ("validation_error", e.to_string())
+            }
+            SubscribeError::TooManyAttempts => ("too_many_requests", e.to_string()),
+            SubscribeError::AlreadySubscribed(_) => ("already_subscribed", e.to_string()),
+            SubscribeError::UnexpectedError(_) => unexpected(),
+        };
+    }
+    if let Some(e) = res.downcast_error::<PublishError>() {
+        return match e {
+            // Says what was wrong with the token.
+            PublishError::AuthError(cause) => ("unauthorized", cause.to_string()),
+            PublishError::UnexpectedError(_) => unexpected(),
+        };
+    }
+    if let Some(e) = res.downcast_error::<LoginError>() {
+        return match e {
+            LoginError::AuthError(_) => ("unauthorized", e.to_string()),
+            LoginError::UnexpectedError(_) => unexpected(),
+        };
+    }
+    let status = res.status();
+    if status.is_server_error() {
+        // Don't leak the internals.
+        return unexpected();
+    }
+    let code = match status {
+        StatusCode::BadRequest => "bad_request",
+        StatusCode::Unauthorized => "unauthorized",
+        StatusCode::Forbidden => "forbidden",
+        StatusCode::NotFound => "not_found",
+        StatusCode::MethodNotAllowed => "method_not_allowed",
+        StatusCode::Conflict => "conflict",
+        StatusCode::UnprocessableEntity => "unprocessable_entity",
+        StatusCode::TooManyRequests => "too_many_requests",
+        _ => "client_error",
+    };
+    let message = match res.error() {
+        Some(e) => e.to_string(),
+        None => status.canonical_reason().to_string(),
+    };
+    (code, message)
+}
+
+fn unexpected() -> (&'static str, String) {
+    ("internal_error", "Something went wrong.".to_string())
+}
@@ -0,0 +1,91 @@
+use super::{id_param, json, page};
+use crate::idempotency::{begin_transaction, commit_transaction};
+use crate::newsletter_issues::{self, insert_draft, PublishOutcome};
+use crate::Request;
+use anyhow::Context;
+use http_types::headers;
+use tide::{Result, StatusCode};
+
+#[derive(serde::Deserialize)]
+struct ListParameters {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+struct DraftBody {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+pub async fn list_issues(req: Request) -> Result {
+    let parameters: ListParameters = req.query()?;
+    let (limit, offset) = page(parameters.limit, parameters.offset)?;
+    let issues = newsletter_issues::get_issues(&req.state().connection, limit, offset).await?;
+    json(StatusCode::Ok, &serde_json::json!({ "issues": issues }))
+}
+
+/// The issue, with how far its delivery went once published.
+pub async fn get_issue(req: Request) -> Result {
+    let issue_id = id_param(&req)?;
+    let issue = newsletter_issues::get_issue(&req.state().connection, issue_id)
+        .await?
+        .ok_or_else(not_found)?;
+    json(StatusCode::Ok, &issue)
+}
+
+/// Store a draft, sent to nobody until it is published.
+pub async fn create_issue(mut req: Request) -> Result {
+    let body: DraftBody = req.body_json().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    if body.title.trim().is_empty() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "The title of an issue can't be empty.",
+        ));
+    }
+    let mut transaction = begin_transaction(&req).await?;
+    let issue_id = insert_draft(
+        &mut transaction,
+        &body.title,
+        &body.text_content,
+        &body.html_content,
+    )
+    .await
+    .context("Failed to store a newsletter issue draft.")?;
+    let issue = newsletter_issues::get_issue(&mut transaction, issue_id)
+        .await?
+        .ok_or_else(not_found)?;
+    commit_transaction(&req, transaction).await?;
+    let mut resp = json(StatusCode::Created, &issue)?;
+    resp.insert_header(headers::LOCATION, format!("/api/v1/issues/{issue_id}"));
+    Ok(resp)
+}
+
+/// Send a draft to every confirmed subscriber.
+pub async fn publish_issue(req: Request) -> Result {
+    let issue_id = id_param(&req)?;
+    let mut transaction = begin_transaction(&req).await?;
+    match newsletter_issues::publish_issue(&mut transaction, issue_id).await? {
+        PublishOutcome::Published => {}
+        PublishOutcome::NotFound => return Err(not_found()),
+        PublishOutcome::AlreadyPublished => {
+            return Err(tide::Error::from_str(
+                StatusCode::Conflict,
+                "The newsletter issue has already been published.",
+            ))
+        }
+    }
+    let issue = newsletter_issues::get_issue(&mut transaction, issue_id)
+        .await?
+        .ok_or_else(not_found)?;
+    commit_transaction(&req, transaction).await?;
+    json(StatusCode::Ok, &issue)
+}
+
+fn not_found() -> tide::Error {
+    tide::Error::from_str(StatusCode::NotFound, "No newsletter issue with this id.")
+}
@@ -0,0 +1,147 @@
+//! The JSON API, reached with API tokens.
+mod error;
+mod issues;
+mod subscribers;
+
+pub use error::ApiErrorMiddleware;
+
+use crate::idempotency::IdempotencyMiddleware;
+use crate::{Request, State};
+use futures::future::BoxFuture;
+use http_types::{mime, Method};
+use tide::{Body, Response, StatusCode};
+use uuid::Uuid;
+
+pub const OPENAPI_PATH: &str = "/api/v1/openapi.json";
+const OPENAPI_DOCUMENT: &str = include_str!("openapi.json");
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+type Handler = fn(Request) -> BoxFuture<'static, tide::Result>;
+
+/// Every route of the API.
+///
+/// Tests check them against the OpenAPI document, so the two can't drift apart.
+pub const ROUTES: &[(Method, &str, Handler)] = &[
+    (Method::Get, OPENAPI_PATH, |req| Box::pin(openapi(req))),
+    (Method::Get, "/api/v1/subscribers", |req| {
+        Box::pin(subscribers::list_subscribers(req))
+    }),
+    (Method::Post, "/api/v1/subscribers", |req| {
+        Box::pin(subscribers::create_subscriber(req))
+    }),
+    (Method::Get, "/api/v1/subscribers/:id", |req| {
+        Box::pin(subscribers::get_subscriber(req))
+    }),
+    (Method::Patch, "/api/v1/subscribers/:id", |req| {
+        Box::pin(subscribers::update_subscriber(req))
+    }),
+    (Method::Delete, "/api/v1/subscribers/:id", |req| {
+        Box::pin(subscribers::delete_subscriber(req))
+    }),
+    (Method::Get, "/api/v1/issues", |req| {
+        Box::pin(issues::list_issues(req))
+    }),
+    (Method::Post, "/api/v1/issues", |req| {
+        Box::pin(issues::create_issue(req))
+    }),
+    (Method::Get, "/api/v1/issues/:id", |req| {
+        Box::pin(issues::get_issue(req))
+    }),
+    (Method::Post, "/api/v1/issues/:id/publish", |req| {
+        Box::pin(issues::publish_issue(req))
+    }),
+];
+
+pub fn register(app: &mut tide::Server<State>) {
+    for &(method, path, handler) in ROUTES {
+        let mut route = app.at(path);
+        if method == Method::Post {
+            route.with(IdempotencyMiddleware::optional());
+        }
+        route.method(method, handler);
+    }
+}
+
+async fn openapi(_req: Request) -> tide::Result {
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(OPENAPI_DOCUMENT);
+    resp.set_content_type(mime::JSON);
+    Ok(resp)
+}
+
+fn json(status: StatusCode, value: &impl serde::Serialize) -> tide::Result {
+    let mut resp = Response::new(status);
+    resp.set_body(Body::from_json(value)?);
+    Ok(resp)
+}
+
+/// The `:id` of the path, which has to be a UUID.
+fn id_param(req: &Request) -> tide::Result<Uuid> {
+    let id = req.param("id")?;
+    Uuid::parse_str(id).map_err(|_| {
+        tide::Error::from_str(StatusCode::BadRequest, format!("`{id}` is not a valid id."))
+    })
+}
+
+/// The `limit` and `offset` of a list, checked and defaulted.
+fn page(limit: Option<i64>, offset: Option<i64>) -> tide::Result<(i64, i64)> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = offset.unwrap_or(0);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("`limit` has to be between 1 and {MAX_PAGE_SIZE}."),
+        ));
+    }
+    if offset < 0 {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "`offset` can't be negative.",
+        ));
+    }
+    Ok((limit, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OPENAPI_DOCUMENT, ROUTES};
+
+    #[test]
+    fn the_openapi_document_describes_every_route() {
+        let document: serde_json::Value = serde_json::from_str(OPENAPI_DOCUMENT).unwrap();
+        assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+        let mut documented: Vec<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, operations)| {
+                operations
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .filter(|key| *key != "parameters")
+                    .map(move |method| (method.to_uppercase(), path.clone()))
+            })
+            .collect();
+        // OpenAPI writes path parameters as `{id}`, tide as `:id`.
+        let mut routed: Vec<(String, String)> = ROUTES
+            .iter()
+            .map(|(method, path, _)| {
+                let path = path
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix(':') {
+                        Some(name) => format!("{{{name}}}"),
+                        None => segment.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (method.to_string(), path)
+            })
+            .collect();
+        documented.sort();
+        routed.sort();
+        assert_eq!(documented, routed);
+    }
+}
@@ -0,0 +1,255 @@
+use super::{id_param, json, page};
+use crate::consent::{get_signup_consent, record_consent_event, ConsentContext, ConsentEventKind};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::idempotency::{begin_transaction, commit_transaction};
+use crate::login_middleware::UserId;
+use crate::routes::subscriptions::{add_subscriber, SubscribeError};
+use crate::subscriber_data::{erase_subscriber_data, Requester};
+use crate::Request;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use http_types::headers;
+use tide::{Response, Result, StatusCode};
+use uuid::Uuid;
+
+const STATUSES: [&str; 2] = ["pending_confirmation", "confirmed"];
+
+#[derive(serde::Serialize)]
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListParameters {
+    status: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+struct NewSubscriberBody {
+    email: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct UpdateBody {
+    status: String,
+}
+
+pub async fn list_subscribers(req: Request) -> Result {
+    let parameters: ListParameters = req.query()?;
+    if let Some(status) = &parameters.status {
+        check_status(status)?;
+    }
+    let (limit, offset) = page(parameters.limit, parameters.offset)?;
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY subscribed_at, id
+        LIMIT $2 OFFSET $3
+        "#,
+        parameters.status,
+        limit,
+        offset
+    )
+    .fetch_all(&req.state().connection)
+    .await
+    .context("Failed to fetch subscribers.")?;
+    json(
+        StatusCode::Ok,
+        &serde_json::json!({ "subscribers": subscribers }),
+    )
+}
+
+pub async fn get_subscriber(req: Request) -> Result {
+    let subscriber_id = id_param(&req)?;
+    let subscriber = fetch_subscriber(&req.state().connection, subscriber_id)
+        .await?
+        .ok_or_else(not_found)?;
+    json(StatusCode::Ok, &subscriber)
+}
+
+/// Subscribe someone on their behalf, which still needs them to confirm.
+pub async fn create_subscriber(mut req: Request) -> Result {
+    let body: NewSubscriberBody = req.body_json().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let new_subscriber = parse_subscriber(body).map_err(|e| {
+        tide::Error::new(StatusCode::BadRequest, SubscribeError::ValidationError(e))
+    })?;
+    let state = req.state();
+    let existing = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+        new_subscriber.email.as_ref()
+    )
+    .fetch_optional(&state.connection)
+    .await
+    .context("Failed to look up a subscriber.")?;
+    if existing.is_some() {
+        return Err(tide::Error::new(
+            StatusCode::Conflict,
+            SubscribeError::AlreadySubscribed(new_subscriber.email.as_ref().to_string()),
+        ));
+    }
+    let consent = ConsentContext::from_request(
+        &req,
+        Some("api".to_string()),
+        state.consent_text_version.clone(),
+    );
+    let mut transaction = begin_transaction(&req).await?;
+    let subscriber_id = add_subscriber(
+        new_subscriber,
+        consent,
+        &mut transaction,
+        &state.email_client,
+        &state.base_url,
+    )
+    .await
+    .map_err(|e| {
+        tide::Error::new(
+            StatusCode::InternalServerError,
+            SubscribeError::UnexpectedError(e),
+        )
+    })?;
+    let subscriber = fetch_subscriber(&mut transaction, subscriber_id)
+        .await?
+        .ok_or_else(not_found)?;
+    commit_transaction(&req, transaction).await?;
+    let mut resp = json(StatusCode::Created, &subscriber)?;
+    resp.insert_header(
+        headers::LOCATION,
+        format!("/api/v1/subscribers/{subscriber_id}"),
+    );
+    Ok(resp)
+}
+
+/// Change the status of a subscriber, e.g. to confirm them by hand.
+pub async fn update_subscriber(mut req: Request) -> Result {
+    let subscriber_id = id_param(&req)?;
+    let body: UpdateBody = req.body_json().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    check_status(&body.status)?;
+    let state = req.state();
+    let mut transaction = state
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let previous = sqlx::query!(
+        r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the status of a subscriber.")?
+    .ok_or_else(not_found)?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE id = $1"#,
+        subscriber_id,
+        body.status
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the status of a subscriber.")?;
+    if body.status == "confirmed" && previous.status != "confirmed" {
+        // Confirms what was shown at signup, like the confirmation link does.
+        let consent_text_version = get_signup_consent(&mut transaction, subscriber_id)
+            .await?
+            .map(|(_, version)| version)
+            .unwrap_or_else(|| state.consent_text_version.clone());
+        let consent =
+            ConsentContext::from_request(&req, Some("api".to_string()), consent_text_version);
+        record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventKind::Confirmation,
+            &consent,
+        )
+        .await
+        .context("Failed to record the confirmation of a subscriber.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+    let subscriber = fetch_subscriber(&state.connection, subscriber_id)
+        .await?
+        .ok_or_else(not_found)?;
+    json(StatusCode::Ok, &subscriber)
+}
+
+/// Erase a subscriber and everything we know about them.
+pub async fn delete_subscriber(req: Request) -> Result {
+    let subscriber_id = id_param(&req)?;
+    let user_id = req
+        .ext::<UserId>()
+        .expect("request session not initialized, did you enable crate::login_middleware::RequiredLoginMiddleware?")
+        .0;
+    if !erase_subscriber_data(
+        &req.state().connection,
+        subscriber_id,
+        Requester::Admin(user_id),
+    )
+    .await?
+    {
+        return Err(not_found());
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(executor))]
+async fn fetch_subscriber<'a, E>(
+    executor: E,
+    subscriber_id: Uuid,
+) -> std::result::Result<Option<Subscriber>, anyhow::Error>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch a subscriber.")?;
+    Ok(subscriber)
+}
+
+fn parse_subscriber(body: NewSubscriberBody) -> std::result::Result<NewSubscriber, String> {
+    let name = SubscriberName::parse(body.name)?;
+    let email = SubscriberEmail::parse(body.email)?;
+    Ok(NewSubscriber { email, name })
+}
+
+fn check_status(status: &str) -> Result<()> {
+    if STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(tide::Error::new(
+            StatusCode::BadRequest,
+            SubscribeError::ValidationError(format!(
+                "{status} is not a supported status. Use either `pending_confirmation` or `confirmed`."
+            )),
+        ))
+    }
+}
+
+fn not_found() -> tide::Error {
+    tide::Error::from_str(StatusCode::NotFound, "No subscriber with this id.")
+}
@@ -0,0 +1,97 @@
+use crate::authorization::Role;
+use crate::csrf_middleware::csrf_field;
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use anyhow::Context;
+use serde::Deserialize;
+use tide::http::Cookie;
+use tide::{Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    invitation_token: String,
+}
+
+/// Let an invited user pick a username and a password.
+pub async fn accept_invitation_form(req: Request) -> Result {
+    let parameters: Parameters = req.query()?;
+    let (email, role) =
+        match get_invitation(&req.state().connection, &parameters.invitation_token).await? {
+            None => return Ok(Response::new(StatusCode::Unauthorized)),
+            Some(invitation) => invitation,
+        };
+    let csrf_field = csrf_field(&req)?;
+    let msg_html = get_flashed_message(&req);
+    let email = escape_html(&email);
+    let role = role.as_str();
+    let invitation_token = escape_html(&parameters.invitation_token);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Create your account</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>Create the account of {email}, you have been invited as {role}.</p>
+            <form action="/invitations/accept" method="post">
+                {csrf_field}
+                <input hidden type="text" name="invitation_token" value="{invitation_token}">
+                <label>Username
+                    <input type="text" placeholder="Enter Username" name="username">
+                </label>
+                <br>
+                <label>Password
+                    <input type="password" placeholder="Enter password" name="password">
+                </label>
+                <br>
+                <label>Confirm password
+                    <input type="password" placeholder="Type the password again" name="password_check">
+                </label>
+                <br>
+                <button type="submit">Create account</button>
+            </form>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+/// The email an invitation was sent to and the role it grants, if the invitation is
+/// still valid.
+///
+/// The invitation stays locked until the end of the transaction `executor` belongs to,
+/// so it can't be accepted twice.
+#[tracing::instrument(name = "Get invitation", skip(executor, invitation_token))]
+pub(super) async fn get_invitation<'a, E>(
+    executor: E,
+    invitation_token: &str,
+) -> std::result::Result<Option<(String, Role)>, anyhow::Error>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+        SELECT email, role
+        FROM user_invitations
+        WHERE
+            invitation_token = $1 AND
+            created_at > now() - interval '7 days'
+        FOR UPDATE
+        "#,
+        invitation_token
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve the invitation.")?;
+    row.map(|r| {
+        let role = Role::try_from(r.role).map_err(anyhow::Error::msg)?;
+        Ok((r.email, role))
+    })
+    .transpose()
+}
@@ -0,0 +1,100 @@
+use super::get::get_invitation;
+use crate::authentication::create_user;
+use crate::domain::NewPassword;
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    invitation_token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+/// Create the account of an invited user, consuming the invitation.
+#[tracing::instrument(name = "Accept an invitation", skip(req))]
+pub async fn accept_invitation(mut req: Request) -> Result {
+    let form_data: FormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let hmac_key = &req.state().hmac_secret;
+    let retry = |msg: &str| {
+        let mut resp: Response = Redirect::see_other(format!(
+            "/invitations/accept?invitation_token={}",
+            form_data.invitation_token
+        ))
+        .into();
+        attach_flashed_message(&mut resp, hmac_key, msg.to_string());
+        resp
+    };
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let (email, role) = match get_invitation(&mut transaction, &form_data.invitation_token).await? {
+        None => return Ok(Response::new(StatusCode::Unauthorized)),
+        Some(invitation) => invitation,
+    };
+    // Only tokens we issued make it here, so they are safe to redirect to.
+    let username = form_data.username.trim();
+    if username.is_empty() {
+        return Ok(retry("Please pick a username."));
+    }
+    if form_data.password.expose_secret() != form_data.password_check.expose_secret() {
+        return Ok(retry(
+            "You entered two different passwords - the field values must match.",
+        ));
+    }
+    let password = match NewPassword::parse(
+        form_data.password.clone(),
+        username,
+        &req.state().password_policy,
+    ) {
+        Ok(password) => password,
+        Err(e) => return Ok(retry(&e)),
+    };
+    let username_taken = sqlx::query!(r#"SELECT user_id FROM users WHERE username = $1"#, username)
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to look up a username.")?
+        .is_some();
+    if username_taken {
+        return Ok(retry("This username is already taken."));
+    }
+    create_user(
+        &mut transaction,
+        username,
+        &email,
+        role,
+        password,
+        &req.state().password_hashing,
+    )
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM user_invitations WHERE invitation_token = $1"#,
+        form_data.invitation_token
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the invitation.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invitation.")?;
+
+    let mut resp: Response = Redirect::see_other("/login").into();
+    attach_flashed_message(
+        &mut resp,
+        hmac_key,
+        "Your account has been created, you can now log in.".into(),
+    );
+    Ok(resp)
+}
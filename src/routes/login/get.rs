@@ -1,9 +1,11 @@
+use crate::csrf_middleware::csrf_field;
 use crate::routes::utils::get_flashed_message;
 use crate::Request;
 use http_types::Cookie;
 use tide::{Response, Result};
 
 pub async fn login_form(req: Request) -> Result {
+    let csrf_field = csrf_field(&req)?;
     let error_html = get_flashed_message(&req);
     let body = format!(
         r#"<!DOCTYPE html>
@@ -17,10 +19,12 @@ pub async fn login_form(req: Request) -> Result {
         <body>
             {error_html}
             <form action="/login" method="post">
+                {csrf_field}
                 <label>Username <input type="text" placeholder="Enter Username" name="username"> </label>
                 <label>Password <input type="password" placeholder="Enter Password" name="password"> </label>
                 <button type="submit">Login</button>
             </form>
+            <p><a href="/login/forgot">Forgot your password?</a></p>
         </body>
 
         </html>"#,
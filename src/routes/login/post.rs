@@ -1,4 +1,7 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::authentication::{
+    attempt_login, is_two_factor_enabled, reset_failed_logins, start_session, AuthError,
+    Credentials,
+};
 use crate::session_state::TypedSession;
 use crate::Request;
 use http_types::headers;
@@ -6,7 +9,7 @@ use secrecy::Secret;
 use serde::Deserialize;
 use tide::{Redirect, Response, Result, StatusCode};
 
-use crate::routes::utils::attach_flashed_message;
+use crate::routes::utils::{attach_flashed_message, client_ip};
 
 #[derive(Deserialize)]
 pub struct FormData {
@@ -19,31 +22,59 @@ pub async fn login(mut req: Request) -> Result {
         e.set_status(StatusCode::BadRequest);
         e
     })?;
-    let pool = &req.state().connection;
+    let state = req.state();
     let credentials = Credentials {
         username: form_data.username,
         password: form_data.password,
     };
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let validate_result = validate_credentials(credentials, pool).await;
+    let validate_result = attempt_login(
+        credentials,
+        client_ip(&req, req.state().behind_proxy),
+        &state.lockout_settings,
+        &state.password_hashing,
+        &state.dummy_password_hash,
+        &state.connection,
+        &state.email_client,
+    )
+    .await;
     let user_id = match validate_result {
         Ok(user_id) => user_id,
-        Err(e) => match e {
-            AuthError::InvalidCredentials(_) => {
-                let err = LoginError::AuthError(e.into());
-                let error_msg = err.to_string();
-                let mut response = Response::new(StatusCode::SeeOther);
-                attach_flashed_message(&mut response, &req.state().hmac_secret, error_msg);
-                response.append_header(headers::LOCATION, "/login");
-                return Ok(response);
-            }
-            AuthError::UnexpectedError(_) => {
-                return Err(LoginError::UnexpectedError(e.into()).into())
-            }
-        },
+        Err(e) => {
+            let err = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => {
+                    return Err(LoginError::UnexpectedError(e.into()).into())
+                }
+            };
+            let error_msg = err.to_string();
+            let mut response = Response::new(StatusCode::SeeOther);
+            attach_flashed_message(&mut response, &req.state().hmac_secret, error_msg);
+            response.append_header(headers::LOCATION, "/login");
+            return Ok(response);
+        }
     };
     let mut session = TypedSession::from_req(&req);
-    if let Err(e) = session.insert_user_id(user_id) {
+    // The session only gets the user id once the second factor checks out too.
+    if is_two_factor_enabled(user_id, &req.state().connection).await? {
+        if let Err(e) = session.insert_pending_user_id(user_id) {
+            let error = LoginError::UnexpectedError(e.into());
+            let mut response: Response = Redirect::see_other("/login").into();
+            attach_flashed_message(&mut response, &req.state().hmac_secret, error.to_string());
+            return Ok(response);
+        }
+        session.regenerate();
+        return Ok(Redirect::see_other("/login/two_factor").into());
+    }
+    let session_id = start_session(
+        user_id,
+        client_ip(&req, req.state().behind_proxy).as_deref(),
+        req.header(headers::USER_AGENT).map(|ua| ua.as_str()),
+        &req.state().connection,
+    )
+    .await
+    .map_err(LoginError::UnexpectedError)?;
+    if let Err(e) = session.insert_user_id(user_id, session_id) {
         let error = LoginError::UnexpectedError(e.into());
         let error_msg = error.to_string();
         let mut response = Response::new(StatusCode::SeeOther);
@@ -52,6 +83,9 @@ pub async fn login(mut req: Request) -> Result {
         return Ok(response);
     }
     session.regenerate();
+    reset_failed_logins(user_id, &req.state().connection)
+        .await
+        .map_err(LoginError::UnexpectedError)?;
 
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
     Ok(Redirect::see_other("/admin/dashboard").into())
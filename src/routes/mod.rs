@@ -1,14 +1,23 @@
 mod admin;
+pub(crate) mod api;
 mod health_check;
 mod home;
+mod invitations;
 mod login;
-mod subscriptions;
+mod password_reset;
+pub(crate) mod subscriptions;
 mod subscriptions_confirm;
-mod utils;
+mod subscriptions_data;
+mod two_factor;
+pub(crate) mod utils;
 
 pub use admin::*;
 pub use health_check::health_check;
 pub use home::*;
+pub use invitations::*;
 pub use login::*;
-pub use subscriptions::subscribe;
+pub use password_reset::*;
+pub use subscriptions::{subscribe, subscribe_form};
 pub use subscriptions_confirm::confirm;
+pub use subscriptions_data::*;
+pub use two_factor::*;
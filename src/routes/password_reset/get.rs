@@ -0,0 +1,84 @@
+use crate::authentication::get_password_reset_user;
+use crate::csrf_middleware::csrf_field;
+use crate::routes::utils::{escape_html, get_flashed_message};
+use crate::Request;
+use serde::Deserialize;
+use tide::http::Cookie;
+use tide::{Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+pub async fn forgot_password_form(req: Request) -> Result {
+    let csrf_field = csrf_field(&req)?;
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Forgot your password?</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>Enter your username, we will email you a link to pick a new password.</p>
+            <form action="/login/forgot" method="post">
+                {csrf_field}
+                <label>Username <input type="text" placeholder="Enter Username" name="username"> </label>
+                <button type="submit">Send reset link</button>
+            </form>
+            <p><a href="/login">&lt;- Back to login</a></p>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
+
+pub async fn reset_password_form(req: Request) -> Result {
+    let parameters: Parameters = req.query()?;
+    if get_password_reset_user(&req.state().connection, &parameters.token)
+        .await?
+        .is_none()
+    {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    }
+    let csrf_field = csrf_field(&req)?;
+    let msg_html = get_flashed_message(&req);
+    let token = escape_html(&parameters.token);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Reset your password</title>
+        </head>
+        <body>
+            {msg_html}
+            <form action="/login/reset" method="post">
+                {csrf_field}
+                <input hidden type="text" name="token" value="{token}">
+                <label>New password
+                    <input type="password" placeholder="Enter new password" name="new_password">
+                </label>
+                <br>
+                <label>Confirm new password
+                    <input type="password" placeholder="Type the new password again" name="new_password_check">
+                </label>
+                <br>
+                <button type="submit">Reset password</button>
+            </form>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{forgot_password_form, reset_password_form};
+pub use post::{forgot_password, reset_password};
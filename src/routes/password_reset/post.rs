@@ -0,0 +1,160 @@
+use crate::authentication::{
+    change_password, finish_password_reset, get_password_reset_user, start_password_reset,
+};
+use crate::domain::{NewPassword, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::utils::attach_flashed_message;
+use crate::Request;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+pub struct ForgotFormData {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Email a reset link to the owner of an account.
+///
+/// The response is the same whether the account exists or not, so this can't be used
+/// to find out usernames. The link is sent in the background, otherwise the response
+/// time would give them away instead.
+#[tracing::instrument(name = "Request a password reset", skip(req))]
+pub async fn forgot_password(mut req: Request) -> Result {
+    let form_data: ForgotFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let state = req.state();
+    async_std::task::spawn(send_reset_link(
+        form_data.username.trim().to_string(),
+        state.connection.clone(),
+        state.email_client.clone(),
+        state.base_url.clone(),
+    ));
+    let mut resp: Response = Redirect::see_other("/login").into();
+    attach_flashed_message(
+        &mut resp,
+        &state.hmac_secret,
+        "If the account exists and has an email address, a link to reset its password is on its way."
+            .into(),
+    );
+    Ok(resp)
+}
+
+#[tracing::instrument(
+    name = "Send a password reset link",
+    skip(pool, email_client, base_url)
+)]
+async fn send_reset_link(
+    username: String,
+    pool: sqlx::PgPool,
+    email_client: EmailClient,
+    base_url: String,
+) {
+    let result: std::result::Result<(), anyhow::Error> = async {
+        let reset = match start_password_reset(&username, &pool).await? {
+            Some(reset) => reset,
+            None => return Ok(()),
+        };
+        let recipient = SubscriberEmail::parse(reset.email).map_err(anyhow::Error::msg)?;
+        let reset_link = format!("{base_url}/login/reset?token={}", reset.token);
+        email_client
+            .send_email(
+                &recipient,
+                "Reset your password",
+                &format!(
+                    "Someone asked to reset the password of your account {username}.<br />\
+                    Click <a href=\"{reset_link}\">here</a> to pick a new one. \
+                    The link is valid for 1 hour. If it wasn't you, ignore this email."
+                ),
+                &format!(
+                    "Someone asked to reset the password of your account {username}.\n\
+                    Visit {reset_link} to pick a new one. \
+                    The link is valid for 1 hour. If it wasn't you, ignore this email."
+                ),
+            )
+            .await
+            .map_err(|e| e.into_inner())
+            .context("Failed to send the password reset email.")?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a password reset link."
+        );
+    }
+}
+
+/// Set a new password with a reset token, logging the user out everywhere.
+#[tracing::instrument(name = "Reset a password", skip(req))]
+pub async fn reset_password(mut req: Request) -> Result {
+    let form_data: ResetFormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let state = req.state();
+    let mut transaction = state
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let user_id = match get_password_reset_user(&mut transaction, &form_data.token).await? {
+        None => return Ok(Response::new(StatusCode::Unauthorized)),
+        Some(user_id) => user_id,
+    };
+    // Only tokens we issued make it here, so they are safe to redirect to.
+    if form_data.new_password.expose_secret() != form_data.new_password_check.expose_secret() {
+        let mut resp: Response =
+            Redirect::see_other(format!("/login/reset?token={}", form_data.token)).into();
+        attach_flashed_message(
+            &mut resp,
+            &state.hmac_secret,
+            "You entered two different new passwords - the field values must match.".into(),
+        );
+        return Ok(resp);
+    }
+    let username = get_username(user_id, &state.connection).await?;
+    let new_password =
+        match NewPassword::parse(form_data.new_password, &username, &state.password_policy) {
+            Ok(new_password) => new_password,
+            Err(e) => {
+                let mut resp: Response =
+                    Redirect::see_other(format!("/login/reset?token={}", form_data.token)).into();
+                attach_flashed_message(&mut resp, &state.hmac_secret, e);
+                return Ok(resp);
+            }
+        };
+    change_password(
+        user_id,
+        new_password,
+        &state.password_hashing,
+        &mut transaction,
+    )
+    .await?;
+    finish_password_reset(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+
+    let mut resp: Response = Redirect::see_other("/login").into();
+    attach_flashed_message(
+        &mut resp,
+        &state.hmac_secret,
+        "Your password has been reset, you can now log in.".into(),
+    );
+    Ok(resp)
+}
@@ -0,0 +1,114 @@
+use crate::routes::utils::{gen_hmac_tag, verify_hmac_tag};
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use std::time::Duration;
+
+/// A signed timestamp embedded in the subscribe form when it is rendered.
+///
+/// Humans need a few seconds to fill the form in, bots posting straight away
+/// (or without loading the form at all) don't.
+pub struct FormToken {
+    pub issued_at: i64,
+    pub signature: String,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum FormTokenError {
+    #[error("The form signature is invalid.")]
+    InvalidSignature,
+    #[error("The form was submitted too quickly.")]
+    TooFast,
+    #[error("The form has expired, please reload the page.")]
+    Expired,
+}
+
+impl FormToken {
+    pub fn issue(hmac_key: &Secret<String>, now: DateTime<Utc>) -> Self {
+        let issued_at = now.timestamp_millis();
+        Self {
+            issued_at,
+            signature: gen_hmac_tag(hmac_key, Self::message(issued_at)),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        hmac_key: &Secret<String>,
+        now: DateTime<Utc>,
+        min_fill_time: Duration,
+        max_age: Duration,
+    ) -> Result<(), FormTokenError> {
+        let signature =
+            hex::decode(&self.signature).map_err(|_| FormTokenError::InvalidSignature)?;
+        if !verify_hmac_tag(hmac_key, Self::message(self.issued_at), &signature) {
+            return Err(FormTokenError::InvalidSignature);
+        }
+        let elapsed = now.timestamp_millis() - self.issued_at;
+        if elapsed < min_fill_time.as_millis() as i64 {
+            return Err(FormTokenError::TooFast);
+        }
+        if elapsed > max_age.as_millis() as i64 {
+            return Err(FormTokenError::Expired);
+        }
+        Ok(())
+    }
+
+    fn message(issued_at: i64) -> String {
+        format!("subscribe_form={issued_at}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormToken, FormTokenError};
+    use chrono::Utc;
+    use secrecy::Secret;
+    use std::time::Duration;
+
+    fn key() -> Secret<String> {
+        Secret::new("super-long-key-that-expected-larger-or-equal-to-thirty-two-bytes".into())
+    }
+
+    const MIN_FILL_TIME: Duration = Duration::from_secs(3);
+    const MAX_AGE: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn a_token_submitted_after_the_minimum_fill_time_is_accepted() {
+        let issued_at = Utc::now() - chrono::Duration::seconds(5);
+        let token = FormToken::issue(&key(), issued_at);
+        assert_eq!(
+            token.verify(&key(), Utc::now(), MIN_FILL_TIME, MAX_AGE),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_token_submitted_too_quickly_is_rejected() {
+        let token = FormToken::issue(&key(), Utc::now());
+        assert_eq!(
+            token.verify(&key(), Utc::now(), MIN_FILL_TIME, MAX_AGE),
+            Err(FormTokenError::TooFast)
+        );
+    }
+
+    #[test]
+    fn an_old_token_is_rejected() {
+        let issued_at = Utc::now() - chrono::Duration::hours(2);
+        let token = FormToken::issue(&key(), issued_at);
+        assert_eq!(
+            token.verify(&key(), Utc::now(), MIN_FILL_TIME, MAX_AGE),
+            Err(FormTokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn a_tampered_timestamp_is_rejected() {
+        let issued_at = Utc::now();
+        let mut token = FormToken::issue(&key(), issued_at);
+        token.issued_at -= 60_000;
+        assert_eq!(
+            token.verify(&key(), Utc::now(), MIN_FILL_TIME, MAX_AGE),
+            Err(FormTokenError::InvalidSignature)
+        );
+    }
+}
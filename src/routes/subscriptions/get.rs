@@ -0,0 +1,40 @@
+use super::form_token::FormToken;
+use crate::Request;
+use chrono::Utc;
+use tide::{Response, Result};
+
+pub async fn subscribe_form(req: Request) -> Result {
+    let token = FormToken::issue(&req.state().hmac_secret, Utc::now());
+    let issued_at = token.issued_at;
+    let signature = token.signature;
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Subscribe</title>
+        </head>
+        <body>
+            <form action="/subscriptions" method="post">
+                <label>Name <input type="text" placeholder="Enter your name" name="name"></label>
+                <br>
+                <label>Email <input type="email" placeholder="Enter your email" name="email"></label>
+                <br>
+                <!-- Left empty by humans, bots tend to fill every field in. -->
+                <div style="display:none" aria-hidden="true">
+                    <label>Website <input type="text" name="website" tabindex="-1" autocomplete="off"></label>
+                </div>
+                <input hidden type="text" name="form_issued_at" value="{issued_at}">
+                <input hidden type="text" name="form_signature" value="{signature}">
+                <input hidden type="text" name="source" value="subscribe-page">
+                <p>By subscribing you agree to receive our newsletter by email.
+                You can ask us to erase your data at any time.</p>
+                <button type="submit">Subscribe</button>
+            </form>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
@@ -0,0 +1,6 @@
+mod form_token;
+mod get;
+mod post;
+
+pub use get::subscribe_form;
+pub use post::*;
@@ -0,0 +1,360 @@
+use std::fmt::Debug;
+
+use super::form_token::FormToken;
+use crate::configuration::SubscriptionSettings;
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventKind};
+use crate::{EmailClient, Request};
+
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::{Postgres, Transaction};
+use tide::StatusCode;
+use tide::{Response, Result};
+use uuid::Uuid;
+
+#[derive(Clone, Deserialize, Serialize)]
+struct SubscribeBody {
+    email: String,
+    name: String,
+    // Identifies the form the subscriber used.
+    source: Option<String>,
+    // Honeypot, hidden from humans by the form.
+    #[serde(default)]
+    website: String,
+    // Signed by `subscribe_form` when the form was rendered.
+    form_issued_at: i64,
+    form_signature: String,
+}
+
+pub async fn subscribe(mut req: Request) -> Result {
+    let subscribe_body: SubscribeBody = req.body_form().await.map_err(|mut e| {
+        e.set_status(400);
+        e
+    })?;
+    // All the bot checks happen before anything is stored or any email is sent.
+    if !subscribe_body.website.is_empty() {
+        return Err(tide::Error::new(
+            StatusCode::BadRequest,
+            SubscribeError::SuspectedBot("The form contains an unexpected field.".into()),
+        ));
+    }
+    let settings = &req.state().subscription_settings;
+    let form_token = FormToken {
+        issued_at: subscribe_body.form_issued_at,
+        signature: subscribe_body.form_signature.clone(),
+    };
+    form_token
+        .verify(
+            &req.state().hmac_secret,
+            Utc::now(),
+            settings.min_form_fill_time(),
+            settings.max_form_age(),
+        )
+        .map_err(|e| {
+            tide::Error::new(
+                StatusCode::BadRequest,
+                SubscribeError::SuspectedBot(e.to_string()),
+            )
+        })?;
+    // The consent text is the one we serve, whatever the client claims it was shown.
+    let consent = ConsentContext::from_request(
+        &req,
+        subscribe_body.source.clone(),
+        req.state().consent_text_version.clone(),
+    );
+    let new_subscriber: NewSubscriber = subscribe_body.try_into().map_err(|e| {
+        tide::Error::new(StatusCode::BadRequest, SubscribeError::ValidationError(e))
+    })?;
+    if !register_subscription_attempt(
+        &req.state().connection,
+        consent.client_ip.as_deref(),
+        new_subscriber.email.as_ref(),
+        settings,
+    )
+    .await?
+    {
+        let mut resp = Response::new(StatusCode::TooManyRequests);
+        resp.insert_header("Retry-After", settings.attempts_window_seconds.to_string());
+        resp.set_error(SubscribeError::TooManyAttempts);
+        return Ok(resp);
+    }
+
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    add_subscriber(
+        new_subscriber,
+        consent,
+        &mut transaction,
+        &req.state().email_client,
+        &req.state().base_url,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    Ok("".into())
+}
+
+impl TryFrom<SubscribeBody> for NewSubscriber {
+    type Error = String;
+
+    fn try_from(value: SubscribeBody) -> std::result::Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name)?;
+        let email = SubscriberEmail::parse(value.email)?;
+        Ok(NewSubscriber { email, name })
+    }
+}
+
+// WARN: can't use `name` as argument name for `add_subscriber`, or tracing will not show that argument.
+// Because it already have a `name` field for Layer.
+/// Store a new subscriber in `transaction` and send them a confirmation email.
+///
+/// The caller commits: the email goes out before, so a failure to send it leaves nothing behind.
+#[tracing::instrument(
+    name = "Adding a new subscriber",
+    skip(transaction, email_client),
+    fields(request_id = %Uuid::new_v4())
+)]
+pub(crate) async fn add_subscriber(
+    new_subscriber: NewSubscriber,
+    consent: ConsentContext,
+    transaction: &mut Transaction<'_, Postgres>,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> std::result::Result<Uuid, anyhow::Error> {
+    let subscriber_id = insert_subscriber(&new_subscriber, transaction)
+        .await
+        .context("Failed to insert new subscriber in the database.")?;
+    record_consent_event(
+        &mut *transaction,
+        subscriber_id,
+        ConsentEventKind::Signup,
+        &consent,
+    )
+    .await
+    .context("Failed to record the consent of a new subscriber.")?;
+    let subscription_token = generate_subscription_token();
+    store_token(transaction, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber.")?;
+    send_confirmation_email(email_client, new_subscriber, base_url, &subscription_token)
+        .await
+        .map_err(|e| e.into_inner())
+        .context("Failed to send a confirmation email.")?;
+    Ok(subscriber_id)
+}
+
+/// Count an attempt to subscribe `email` from `client_ip`.
+///
+/// Returns `false`, without counting it, once either of them went over its limit
+/// within the window: that's how the endpoint avoids being used to mail-bomb someone.
+#[tracing::instrument(name = "Register a subscription attempt", skip(pool, email, settings))]
+async fn register_subscription_attempt(
+    pool: &PgPool,
+    client_ip: Option<&str>,
+    email: &str,
+    settings: &SubscriptionSettings,
+) -> std::result::Result<bool, anyhow::Error> {
+    let window_seconds = settings.attempts_window().as_secs_f64();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Concurrent attempts for the same email or IP wait for each other, otherwise they
+    // could all be counted before any of them is recorded. Always the email first, then
+    // the IP, so two attempts can't wait on each other forever.
+    sqlx::query!(
+        r#"
+        SELECT
+            pg_advisory_xact_lock(1, hashtext($1)),
+            pg_advisory_xact_lock(2, hashtext(COALESCE($2, '')))
+        "#,
+        email,
+        client_ip
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to lock the subscription attempts.")?;
+    // Attempts without a client IP share a bucket, rather than never being limited.
+    let attempts = sqlx::query!(
+        r#"
+        SELECT
+            count(*) FILTER (WHERE client_ip IS NOT DISTINCT FROM $1) as "by_ip!",
+            count(*) FILTER (WHERE email = $2) as "by_email!"
+        FROM subscription_attempts
+        WHERE attempted_at > now() - make_interval(secs => $3)
+        "#,
+        client_ip,
+        email,
+        window_seconds
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to count recent subscription attempts.")?;
+    if attempts.by_ip >= settings.max_attempts_per_ip
+        || attempts.by_email >= settings.max_attempts_per_email
+    {
+        tracing::warn!(
+            attempts_by_ip = attempts.by_ip,
+            attempts_by_email = attempts.by_email,
+            "Too many subscription attempts."
+        );
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_attempts (email, client_ip, attempted_at)
+        VALUES ($1, $2, now())
+        "#,
+        email,
+        client_ip
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record a subscription attempt.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a subscription attempt.")?;
+    // Attempts only matter within the window, don't let them pile up.
+    sqlx::query!(
+        r#"DELETE FROM subscription_attempts WHERE attempted_at < now() - make_interval(secs => $1)"#,
+        window_seconds
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired subscription attempts.")?;
+    Ok(true)
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> std::result::Result<(), StoreTokenError> {
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ($1, $2)"#,
+        subscription_token,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a confirmation email to a new subscriber",
+    skip(email_client, new_subscriber, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+) -> std::result::Result<(), surf::Error> {
+    // Send a (useless) email to the new subscriber.
+    // We are ignoring email delivery errors for now.
+    let confirmation_link =
+        format!("{base_url}/subscriptions/confirm?subscription_token={subscription_token}");
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            "Welcome",
+            &format!(
+                "Welcome to our newsletter!<br />\
+            Clink <a href=\"{confirmation_link}\">here</a> to confirm your subscription.",
+            ),
+            &format!(
+                "Welcome to our newsletter!\nVisit {confirmation_link} to confirm your subscription.",
+            )
+
+        )
+        .await
+}
+
+#[tracing::instrument(name = "Savning new subscriber details in the database")]
+async fn insert_subscriber(
+    new_subscriber: &NewSubscriber,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> std::result::Result<Uuid, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        "#,
+        subscriber_id,
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(subscriber_id)
+}
+
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct StoreTokenError(sqlx::Error);
+
+impl std::fmt::Display for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "database error occured")
+    }
+}
+
+impl std::error::Error for StoreTokenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<sqlx::Error> for StoreTokenError {
+    fn from(e: sqlx::Error) -> Self {
+        Self(e)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SubscribeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    SuspectedBot(String),
+    #[error("Too many subscription attempts, try again later.")]
+    TooManyAttempts,
+    #[error("{0} is already subscribed.")]
+    AlreadySubscribed(String),
+    // Transparent delegates both `Display`'s and `source`'s implementation
+    // to the type wrapped by `UnexpectedError`.
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<String> for SubscribeError {
+    fn from(e: String) -> Self {
+        Self::ValidationError(e)
+    }
+}
@@ -1,6 +1,8 @@
+use crate::consent::{get_signup_consent, record_consent_event, ConsentContext, ConsentEventKind};
 use crate::Request;
+use anyhow::Context;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use tide::StatusCode;
 use tide::{Response, Result};
 use uuid::Uuid;
@@ -22,7 +24,12 @@ pub async fn confirm(req: Request) -> Result {
         // Non-exists token!
         None => Ok(Response::builder(StatusCode::Unauthorized).build()),
         Some(subscriber_id) => {
-            if confirm_subscriber(pool, subscriber_id).await.is_err() {
+            if let Err(e) = confirm_and_record_consent(&req, subscriber_id).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to confirm a subscriber."
+                );
                 return Ok(Response::builder(StatusCode::InternalServerError).build());
             }
             Ok("".into())
@@ -30,22 +37,61 @@ pub async fn confirm(req: Request) -> Result {
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
+async fn confirm_and_record_consent(
+    req: &Request,
+    subscriber_id: Uuid,
+) -> std::result::Result<(), anyhow::Error> {
+    let mut transaction = req
+        .state()
+        .connection
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Following the link again doesn't consent again.
+    if !confirm_subscriber(&mut transaction, subscriber_id).await? {
+        return Ok(());
+    }
+    // A confirmation agrees to what was shown at signup. Subscribers who signed up before
+    // we recorded consent fall back to the current text.
+    let (source, consent_text_version) = get_signup_consent(&mut transaction, subscriber_id)
+        .await?
+        .unwrap_or_else(|| (None, req.state().consent_text_version.clone()));
+    let consent = ConsentContext::from_request(req, source, consent_text_version);
+    record_consent_event(
+        &mut transaction,
+        subscriber_id,
+        ConsentEventKind::Confirmation,
+        &consent,
+    )
+    .await
+    .context("Failed to record the consent confirmation.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(())
+}
+
+/// Returns `false` if the subscriber was already confirmed.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
 pub async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
-) -> std::result::Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+) -> std::result::Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1 AND status <> 'confirmed'"#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {e:?}");
         e
     })?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
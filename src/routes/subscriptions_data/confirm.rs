@@ -0,0 +1,138 @@
+use crate::subscriber_data::{
+    erase_subscriber_data, export_subscriber_data, DataRequestKind, Requester,
+};
+use crate::Request;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tide::{Body, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct Parameters {
+    data_request_token: String,
+}
+
+/// Follow-up of the link we emailed.
+///
+/// Exports are returned straight away, erasures need one more explicit click so that
+/// link scanners opening the email can't wipe a subscriber.
+#[tracing::instrument(name = "Confirm a data request", skip(req))]
+pub async fn confirm_data_request(req: Request) -> Result {
+    let parameters: Parameters = req.query()?;
+    let pool = &req.state().connection;
+    let (subscriber_id, kind) =
+        match get_data_request_from_token(pool, &parameters.data_request_token).await? {
+            None => return Ok(Response::new(StatusCode::Unauthorized)),
+            Some(r) => r,
+        };
+    match kind {
+        DataRequestKind::Export => {
+            delete_data_request_token(pool, &parameters.data_request_token).await?;
+            let export =
+                match export_subscriber_data(pool, subscriber_id, Requester::Subscriber).await? {
+                    None => return Ok(Response::new(StatusCode::Unauthorized)),
+                    Some(export) => export,
+                };
+            let mut resp = Response::new(StatusCode::Ok);
+            resp.set_body(Body::from_json(&export)?);
+            resp.insert_header(
+                "Content-Disposition",
+                r#"attachment; filename="subscriber-data.json""#,
+            );
+            Ok(resp)
+        }
+        DataRequestKind::Erasure => {
+            let data_request_token = parameters.data_request_token;
+            let body = format!(
+                r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Erase your data</title>
+        </head>
+        <body>
+            <p>This will unsubscribe you and permanently erase the data we hold about you.</p>
+            <form action="/subscriptions/data/erase" method="post">
+                <input hidden type="text" name="data_request_token" value="{data_request_token}">
+                <button type="submit">Erase my data</button>
+            </form>
+        </body>
+        </html>"#
+            );
+            let mut resp: Response = body.into();
+            resp.set_content_type("text/html; charset=utf-8");
+            Ok(resp)
+        }
+    }
+}
+
+pub async fn erase_data(mut req: Request) -> Result {
+    let form_data: Parameters = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let pool = &req.state().connection;
+    let subscriber_id =
+        match get_data_request_from_token(pool, &form_data.data_request_token).await? {
+            Some((subscriber_id, DataRequestKind::Erasure)) => subscriber_id,
+            _ => return Ok(Response::new(StatusCode::Unauthorized)),
+        };
+    // The erasure removes the token as well, so the link can't be replayed.
+    erase_subscriber_data(pool, subscriber_id, Requester::Subscriber).await?;
+    let mut resp: Response = r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Erase your data</title>
+        </head>
+        <body>
+            <p>Your data has been erased.</p>
+        </body>
+        </html>"#
+        .into();
+    resp.set_content_type("text/html; charset=utf-8");
+    Ok(resp)
+}
+
+#[tracing::instrument(name = "Get data request from token", skip(data_request_token, pool))]
+async fn get_data_request_from_token(
+    pool: &PgPool,
+    data_request_token: &str,
+) -> std::result::Result<Option<(Uuid, DataRequestKind)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriber_id, kind
+        FROM data_request_tokens
+        WHERE
+            data_request_token = $1 AND
+            created_at > now() - interval '1 day'
+        "#,
+        data_request_token
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the data request token.")?;
+    match row {
+        None => Ok(None),
+        Some(r) => {
+            let kind = DataRequestKind::try_from(r.kind).map_err(anyhow::Error::msg)?;
+            Ok(Some((r.subscriber_id, kind)))
+        }
+    }
+}
+
+#[tracing::instrument(name = "Delete data request token", skip(data_request_token, pool))]
+async fn delete_data_request_token(
+    pool: &PgPool,
+    data_request_token: &str,
+) -> std::result::Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM data_request_tokens WHERE data_request_token = $1"#,
+        data_request_token
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete the data request token.")?;
+    Ok(())
+}
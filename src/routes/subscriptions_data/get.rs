@@ -0,0 +1,36 @@
+use crate::routes::utils::get_flashed_message;
+use crate::Request;
+use tide::http::Cookie;
+use tide::{Response, Result};
+
+pub async fn data_request_form(req: Request) -> Result {
+    let message = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Your data</title>
+        </head>
+        <body>
+            {message}
+            <p>Ask for a copy of the data we hold about you, or for it to be erased.
+            We will email you a link to confirm the request.</p>
+            <form action="/subscriptions/data" method="post">
+                <label>Email <input type="email" placeholder="Enter your email" name="email"></label>
+                <br>
+                <label><input type="radio" name="kind" value="export" checked> Send me a copy of my data</label>
+                <br>
+                <label><input type="radio" name="kind" value="erasure"> Erase my data</label>
+                <br>
+                <button type="submit">Submit</button>
+            </form>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,7 @@
+mod confirm;
+mod get;
+mod post;
+
+pub use confirm::{confirm_data_request, erase_data};
+pub use get::data_request_form;
+pub use post::request_data;
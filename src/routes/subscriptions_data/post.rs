@@ -0,0 +1,146 @@
+use crate::domain::SubscriberEmail;
+use crate::routes::subscriptions::generate_subscription_token;
+use crate::routes::utils::attach_flashed_message;
+use crate::subscriber_data::{get_subscriber_id_from_email, DataRequestKind};
+use crate::{EmailClient, Request};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tide::{Redirect, Response, Result, StatusCode};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct FormData {
+    email: String,
+    kind: String,
+}
+
+/// Email a confirmation link to the subscriber before doing anything with their data.
+///
+/// The response is the same whether or not we know the address, so the endpoint
+/// can't be used to find out who is subscribed. The link is sent in the background,
+/// otherwise the response time would give it away instead.
+pub async fn request_data(mut req: Request) -> Result {
+    let form_data: FormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let email = SubscriberEmail::parse(form_data.email)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+    let kind = DataRequestKind::try_from(form_data.kind)
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+
+    let state = req.state();
+    async_std::task::spawn(send_data_request_link(
+        email,
+        kind,
+        state.connection.clone(),
+        state.email_client.clone(),
+        state.base_url.clone(),
+    ));
+
+    let mut resp: Response = Redirect::see_other("/subscriptions/data").into();
+    attach_flashed_message(
+        &mut resp,
+        &state.hmac_secret,
+        "If we hold any data for this address, we have emailed it a link to confirm your request."
+            .into(),
+    );
+    Ok(resp)
+}
+
+#[tracing::instrument(
+    name = "Send a data request link",
+    skip(email, pool, email_client, base_url)
+)]
+async fn send_data_request_link(
+    email: SubscriberEmail,
+    kind: DataRequestKind,
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+) {
+    let result: std::result::Result<(), anyhow::Error> = async {
+        let subscriber_id = match get_subscriber_id_from_email(&pool, email.as_ref()).await? {
+            Some(subscriber_id) => subscriber_id,
+            None => return Ok(()),
+        };
+        let data_request_token = generate_subscription_token();
+        store_data_request_token(&pool, subscriber_id, kind, &data_request_token)
+            .await
+            .context("Failed to store the data request token.")?;
+        send_data_request_email(&email_client, &email, kind, &base_url, &data_request_token)
+            .await
+            .map_err(|e| e.into_inner())
+            .context("Failed to send a data request confirmation email.")?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a data request link."
+        );
+    }
+}
+
+#[tracing::instrument(
+    name = "Store data request token in the database",
+    skip(pool, data_request_token)
+)]
+async fn store_data_request_token(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    kind: DataRequestKind,
+    data_request_token: &str,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO data_request_tokens (data_request_token, subscriber_id, kind, created_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        data_request_token,
+        subscriber_id,
+        kind.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a data request confirmation email",
+    skip(email_client, recipient, data_request_token)
+)]
+async fn send_data_request_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    kind: DataRequestKind,
+    base_url: &str,
+    data_request_token: &str,
+) -> std::result::Result<(), surf::Error> {
+    let confirmation_link =
+        format!("{base_url}/subscriptions/data/confirm?data_request_token={data_request_token}");
+    let action = match kind {
+        DataRequestKind::Export => "receive a copy of the data we hold about you",
+        DataRequestKind::Erasure => "erase the data we hold about you",
+    };
+    email_client
+        .send_email(
+            recipient,
+            "Confirm your data request",
+            &format!(
+                "We received a request to {action}.<br />\
+                Click <a href=\"{confirmation_link}\">here</a> to confirm it. \
+                The link is valid for 24 hours.<br />\
+                If you didn't ask for this, you can ignore this email."
+            ),
+            &format!(
+                "We received a request to {action}.\n\
+                Visit {confirmation_link} to confirm it. The link is valid for 24 hours.\n\
+                If you didn't ask for this, you can ignore this email."
+            ),
+        )
+        .await
+}
@@ -0,0 +1,38 @@
+use crate::csrf_middleware::csrf_field;
+use crate::routes::utils::get_flashed_message;
+use crate::session_state::TypedSession;
+use crate::Request;
+use tide::http::Cookie;
+use tide::{Redirect, Response, Result};
+
+/// Second step of a login, for users who enabled two-factor authentication.
+pub async fn two_factor_form(req: Request) -> Result {
+    if TypedSession::from_req(&req).get_pending_user_id().is_none() {
+        return Ok(Redirect::see_other("/login").into());
+    }
+    let csrf_field = csrf_field(&req)?;
+    let msg_html = get_flashed_message(&req);
+    let body = format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>Two-factor authentication</title>
+        </head>
+        <body>
+            {msg_html}
+            <p>Enter the code from your authenticator app, or one of your recovery codes.</p>
+            <form action="/login/two_factor" method="post">
+                {csrf_field}
+                <label>Code <input type="text" autocomplete="one-time-code" placeholder="123456" name="code"> </label>
+                <button type="submit">Verify</button>
+            </form>
+        </body>
+        </html>"#,
+    );
+    let mut resp: Response = body.into();
+    resp.set_content_type("text/html; charset=utf-8");
+    resp.remove_cookie(Cookie::named("_flash"));
+    resp.remove_cookie(Cookie::named("tag"));
+    Ok(resp)
+}
@@ -0,0 +1,78 @@
+use crate::authentication::{reset_failed_logins, start_session, verify_second_factor};
+use crate::routes::utils::{attach_flashed_message, client_ip};
+use crate::session_state::TypedSession;
+use crate::Request;
+use chrono::{Duration, Utc};
+use http_types::headers;
+use serde::Deserialize;
+use tide::{Redirect, Response, Result, StatusCode};
+
+// How long the second factor can be entered after the password.
+const PENDING_LOGIN_MINUTES: i64 = 5;
+// Wrong codes allowed before the password has to be entered again.
+const MAX_CODE_ATTEMPTS: u32 = 5;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+#[tracing::instrument(name = "Verify the second factor of a login", skip(req), fields(user_id=tracing::field::Empty))]
+pub async fn verify_two_factor(mut req: Request) -> Result {
+    let form_data: FormData = req.body_form().await.map_err(|mut e| {
+        e.set_status(StatusCode::BadRequest);
+        e
+    })?;
+    let state = req.state();
+    let redirect_with = |location: &str, msg: &str| {
+        let mut resp: Response = Redirect::see_other(location).into();
+        attach_flashed_message(&mut resp, &state.hmac_secret, msg.to_string());
+        resp
+    };
+    let mut session = TypedSession::from_req(&req);
+    let user_id = match session.get_pending_user_id() {
+        Some((user_id, since)) if since + Duration::minutes(PENDING_LOGIN_MINUTES) > Utc::now() => {
+            user_id
+        }
+        Some(_) => {
+            session.remove_pending_user_id();
+            return Ok(redirect_with(
+                "/login",
+                "Your login expired, please log in again.",
+            ));
+        }
+        None => return Ok(Redirect::see_other("/login").into()),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    if !verify_second_factor(
+        user_id,
+        &form_data.code,
+        &state.two_factor_settings,
+        &state.connection,
+    )
+    .await?
+    {
+        if session.count_pending_failure()? >= MAX_CODE_ATTEMPTS {
+            session.remove_pending_user_id();
+            return Ok(redirect_with(
+                "/login",
+                "Too many invalid codes, please log in again.",
+            ));
+        }
+        return Ok(redirect_with("/login/two_factor", "The code is invalid."));
+    }
+
+    session.remove_pending_user_id();
+    let session_id = start_session(
+        user_id,
+        client_ip(&req, req.state().behind_proxy).as_deref(),
+        req.header(headers::USER_AGENT).map(|ua| ua.as_str()),
+        &state.connection,
+    )
+    .await?;
+    session.insert_user_id(user_id, session_id)?;
+    session.regenerate();
+    reset_failed_logins(user_id, &state.connection).await?;
+    Ok(Redirect::see_other("/admin/dashboard").into())
+}
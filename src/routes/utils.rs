@@ -19,10 +19,11 @@ pub fn attach_flashed_message(
     response.insert_cookie(tag_cookie);
 }
 
+/// The flashed message as HTML, escaped since it often echoes user input.
 pub fn get_flashed_message(req: &Request) -> String {
     if verify_cookie(req) {
         match req.cookie("_flash") {
-            Some(cookie) => format!("<p><i>{}</i></p>", cookie.value()),
+            Some(cookie) => format!("<p><i>{}</i></p>", escape_html(cookie.value())),
             None => "".into(),
         }
     } else {
@@ -50,7 +51,7 @@ pub fn verify_cookie(req: &Request) -> bool {
     }
 }
 
-fn gen_hmac_tag(hmac_key: &Secret<String>, msg: String) -> String {
+pub fn gen_hmac_tag(hmac_key: &Secret<String>, msg: String) -> String {
     let mut mac =
         Hmac::<sha2::Sha256>::new_from_slice(hmac_key.expose_secret().as_bytes()).unwrap();
     mac.update(msg.as_bytes());
@@ -58,9 +59,51 @@ fn gen_hmac_tag(hmac_key: &Secret<String>, msg: String) -> String {
     format!("{mac_bytes:x}")
 }
 
-fn verify_hmac_tag(hmac_key: &Secret<String>, msg: String, input_tag: &[u8]) -> bool {
+pub fn verify_hmac_tag(hmac_key: &Secret<String>, msg: String, input_tag: &[u8]) -> bool {
     let mut mac =
         Hmac::<sha2::Sha256>::new_from_slice(hmac_key.expose_secret().as_bytes()).unwrap();
     mac.update(msg.as_bytes());
     mac.verify_slice(input_tag).map(|_| true).unwrap_or(false)
 }
+
+/// Escape text before interpolating it in an HTML page.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The address of the client that sent `req`, without the port.
+///
+/// Forwarding headers can be set by anyone, so they are only honoured when the
+/// application sits `behind_proxy`. Even then only the last `X-Forwarded-For` entry
+/// is used: it is the one our proxy appended.
+pub fn client_ip<S: Clone + Send + Sync + 'static>(
+    req: &tide::Request<S>,
+    behind_proxy: bool,
+) -> Option<String> {
+    if behind_proxy {
+        let forwarded = req
+            .header("X-Forwarded-For")
+            .and_then(|values| values.as_str().rsplit(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    req.peer_addr().map(|peer| {
+        peer.parse::<std::net::SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| peer.to_string())
+    })
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Duration, Utc};
 use tide::sessions::Session;
 use uuid::Uuid;
 
@@ -5,6 +6,15 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    // Row of `user_sessions` backing this session.
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const LOGGED_IN_AT_KEY: &'static str = "logged_in_at";
+    const LAST_SEEN_AT_KEY: &'static str = "last_seen_at";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+    // Set between a correct password and a correct second factor.
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    const PENDING_SINCE_KEY: &'static str = "pending_since";
+    const PENDING_FAILURES_KEY: &'static str = "pending_failures";
 
     pub fn from_req<S: Clone + Send + Sync + 'static>(req: &tide::Request<S>) -> Self {
         Self(req.session().clone())
@@ -13,7 +23,15 @@ impl TypedSession {
         self.0.regenerate()
     }
 
-    pub fn insert_user_id(&mut self, user_id: Uuid) -> std::result::Result<(), serde_json::Error> {
+    pub fn insert_user_id(
+        &mut self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> std::result::Result<(), serde_json::Error> {
+        let now = Utc::now();
+        self.0.insert(Self::LOGGED_IN_AT_KEY, now)?;
+        self.0.insert(Self::LAST_SEEN_AT_KEY, now)?;
+        self.0.insert(Self::SESSION_ID_KEY, session_id)?;
         self.0.insert(Self::USER_ID_KEY, user_id)
     }
 
@@ -21,7 +39,131 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn get_session_id(&self) -> Option<Uuid> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// Record activity on the session, pushing back its idle timeout.
+    pub fn touch(&mut self) -> std::result::Result<(), serde_json::Error> {
+        self.0.insert(Self::LAST_SEEN_AT_KEY, Utc::now())
+    }
+
+    /// Whether the session has been idle for longer than `idle_timeout`, or logged in
+    /// for longer than `absolute_timeout`.
+    ///
+    /// Sessions missing their timestamps count as expired.
+    pub fn is_expired(&self, idle_timeout: Duration, absolute_timeout: Duration) -> bool {
+        let now = Utc::now();
+        let logged_in_at: Option<DateTime<Utc>> = self.0.get(Self::LOGGED_IN_AT_KEY);
+        let last_seen_at: Option<DateTime<Utc>> = self.0.get(Self::LAST_SEEN_AT_KEY);
+        match (logged_in_at, last_seen_at) {
+            (Some(logged_in_at), Some(last_seen_at)) => {
+                now - last_seen_at > idle_timeout || now - logged_in_at > absolute_timeout
+            }
+            _ => true,
+        }
+    }
+
+    pub fn get_csrf_token(&self) -> Option<String> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// The CSRF token of the session, creating one with `generate` if there is none yet.
+    pub fn get_or_insert_csrf_token(
+        &mut self,
+        generate: impl FnOnce() -> String,
+    ) -> std::result::Result<String, serde_json::Error> {
+        if let Some(token) = self.get_csrf_token() {
+            return Ok(token);
+        }
+        let token = generate();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn insert_pending_user_id(
+        &mut self,
+        user_id: Uuid,
+    ) -> std::result::Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_SINCE_KEY, Utc::now())?;
+        self.0.insert(Self::PENDING_FAILURES_KEY, 0u32)?;
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    /// The user who still has to pass the second factor, and since when.
+    pub fn get_pending_user_id(&self) -> Option<(Uuid, DateTime<Utc>)> {
+        let user_id = self.0.get(Self::PENDING_USER_ID_KEY)?;
+        let since = self.0.get(Self::PENDING_SINCE_KEY)?;
+        Some((user_id, since))
+    }
+
+    /// Count a wrong second factor, returning how many there were so far.
+    pub fn count_pending_failure(&mut self) -> std::result::Result<u32, serde_json::Error> {
+        let failures = self
+            .0
+            .get::<u32>(Self::PENDING_FAILURES_KEY)
+            .unwrap_or_default()
+            + 1;
+        self.0.insert(Self::PENDING_FAILURES_KEY, failures)?;
+        Ok(failures)
+    }
+
+    pub fn remove_pending_user_id(&mut self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+        self.0.remove(Self::PENDING_SINCE_KEY);
+        self.0.remove(Self::PENDING_FAILURES_KEY);
+    }
+
     pub fn log_out(mut self) {
         self.0.destroy()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TypedSession;
+    use chrono::{Duration, Utc};
+    use tide::sessions::Session;
+    use uuid::Uuid;
+
+    fn logged_in(logged_in_ago: Duration, last_seen_ago: Duration) -> TypedSession {
+        let mut session = TypedSession(Session::new());
+        session
+            .insert_user_id(Uuid::new_v4(), Uuid::new_v4())
+            .unwrap();
+        let now = Utc::now();
+        session
+            .0
+            .insert(TypedSession::LOGGED_IN_AT_KEY, now - logged_in_ago)
+            .unwrap();
+        session
+            .0
+            .insert(TypedSession::LAST_SEEN_AT_KEY, now - last_seen_ago)
+            .unwrap();
+        session
+    }
+
+    #[test]
+    fn active_sessions_within_their_lifetime_are_not_expired() {
+        let session = logged_in(Duration::hours(1), Duration::minutes(5));
+        assert!(!session.is_expired(Duration::minutes(30), Duration::hours(12)));
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let session = logged_in(Duration::hours(1), Duration::minutes(31));
+        assert!(session.is_expired(Duration::minutes(30), Duration::hours(12)));
+    }
+
+    #[test]
+    fn sessions_expire_after_their_absolute_lifetime_even_if_active() {
+        let session = logged_in(Duration::hours(13), Duration::seconds(1));
+        assert!(session.is_expired(Duration::minutes(30), Duration::hours(12)));
+    }
+
+    #[test]
+    fn sessions_without_timestamps_are_expired() {
+        let session = TypedSession(Session::new());
+        assert!(session.is_expired(Duration::minutes(30), Duration::hours(12)));
+    }
+}
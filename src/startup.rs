@@ -1,14 +1,27 @@
-use http_types::headers;
-use secrecy::Secret;
+use http_types::{headers, Cookie};
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use tide::StatusCode;
+use tide::sessions::SessionMiddleware;
+use tide::{Middleware, Next, StatusCode};
 
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::configuration::{DatabaseSettings, SessionSettings, Settings};
+use crate::csrf_middleware::CsrfMiddleware;
 use crate::email_client::EmailClient;
+use crate::idempotency::IdempotencyMiddleware;
 use crate::login_middleware::RequiredLoginMiddleware;
+use crate::rate_limit_middleware::RateLimitMiddleware;
+use crate::routes::api::{self, ApiErrorMiddleware};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, newsletter_form, publish_newsletter, subscribe, PublishError,
+    accept_invitation, accept_invitation_form, activate_user, admin_dashboard, api_tokens_page,
+    cancel_delivery, change_password, change_password_form, change_user_role, clone_issue, confirm,
+    confirm_data_request, confirm_two_factor, create_api_token, data_request_form, deactivate_user,
+    delete_user, disable_two_factor, enroll_two_factor, erase_data, erase_subscriber,
+    export_subscriber, export_subscribers, flash_published, forgot_password, forgot_password_form,
+    health_check, home, invite_user, issue_page, issues_page, log_out, login, login_activity,
+    login_form, newsletter_form, pause_delivery, publish_draft, publish_newsletter, request_data,
+    resend_issue, reset_password, reset_password_form, resume_delivery, revoke_api_token,
+    revoke_other_sessions, revoke_session, sessions_page, subscribe, subscribe_form,
+    subscriber_consent, subscriber_data_form, two_factor_form, two_factor_settings, users_page,
+    verify_two_factor, PublishError,
 };
 use crate::State;
 use async_redis_session::RedisSessionStore;
@@ -26,18 +39,16 @@ pub struct Application {
 
 impl Application {
     pub fn build(configuration: Settings) -> std::result::Result<Self, std::io::Error> {
-        let email_client = configuration.email_client.client();
-        let server = get_server(
-            get_connection_pool(&configuration.database),
-            email_client,
-            configuration.application.base_url.clone(),
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
-        );
         let listener = TcpListener::bind(format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         ))?;
+        let email_client = configuration.email_client.clone().client();
+        let server = get_server(
+            get_connection_pool(&configuration.database),
+            email_client,
+            configuration,
+        );
         let port = listener.local_addr().unwrap().port();
 
         Ok(Self {
@@ -68,39 +79,157 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 fn get_server(
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    configuration: Settings,
 ) -> tide::Server<State> {
-    let state = State::new(db_pool, email_client, base_url, hmac_secret.clone());
+    let state = State::new(db_pool, email_client, &configuration);
+    let hmac_secret = configuration.application.hmac_secret;
+    let redis_uri = configuration.redis_uri;
+    let cookie_secure = configuration.session.cookie_secure;
     let mut app = tide::with_state(state);
     app.with(After(|mut res: tide::Response| async {
         if let Some(PublishError::AuthError(_)) = res.downcast_error::<PublishError>() {
             res.set_status(StatusCode::Unauthorized);
-            res.append_header(headers::WWW_AUTHENTICATE, r#"Basic realm="publish""#);
+            res.append_header(headers::WWW_AUTHENTICATE, r#"Bearer realm="publish""#);
         }
         Ok(res)
     }));
-    app.with(tide::sessions::SessionMiddleware::new(
-        RedisSessionStore::new(redis_uri.expose_secret().as_str()).unwrap(),
+    app.with(ApiErrorMiddleware);
+    // Rejects floods before they cost a session lookup.
+    app.with(RateLimitMiddleware::new(
+        redis_uri.expose_secret(),
+        configuration.rate_limits,
+        configuration.application.behind_proxy,
+    ));
+    app.with(session_middleware(
+        &configuration.session,
+        redis_uri.expose_secret(),
         hmac_secret.expose_secret().as_bytes(),
     ));
     // TODO: RequiredLoginMiddleware only want to handles for specific url
     // But it seems that it doesn't serve with nested app well, so keep the usage currently for now.
     app.with(RequiredLoginMiddleware);
+    // After the login check, so logged out users are still sent to the login page.
+    app.with(CsrfMiddleware);
     app.with(TraceMiddleware::new());
     app.at("/health_check").get(health_check);
-    app.at("/subscriptions").post(subscribe);
+    app.at("/subscriptions").get(subscribe_form).post(subscribe);
     app.at("/subscriptions/confirm").get(confirm);
+    app.at("/subscriptions/data")
+        .get(data_request_form)
+        .post(request_data);
+    app.at("/subscriptions/data/confirm")
+        .get(confirm_data_request);
+    app.at("/subscriptions/data/erase").post(erase_data);
     app.at("/").get(home);
     app.at("/login").get(login_form).post(login);
+    app.at("/login/two_factor")
+        .get(two_factor_form)
+        .post(verify_two_factor);
+    app.at("/login/forgot")
+        .get(forgot_password_form)
+        .post(forgot_password);
+    app.at("/login/reset")
+        .get(reset_password_form)
+        .post(reset_password);
+    app.at("/admin/newsletters").get(newsletter_form);
     app.at("/admin/newsletters")
-        .get(newsletter_form)
+        .with(IdempotencyMiddleware::required().on_replay(flash_published))
         .post(publish_newsletter);
+    app.at("/admin/issues").get(issues_page);
+    app.at("/admin/issues/view").get(issue_page);
+    app.at("/admin/issues/clone").post(clone_issue);
+    app.at("/admin/issues/publish").post(publish_draft);
+    app.at("/admin/issues/resend").post(resend_issue);
+    app.at("/admin/issues/pause").post(pause_delivery);
+    app.at("/admin/issues/resume").post(resume_delivery);
+    app.at("/admin/issues/cancel").post(cancel_delivery);
     app.at("/admin/dashboard").get(admin_dashboard);
     app.at("/admin/password")
         .get(change_password_form)
         .post(change_password);
     app.at("/admin/logout").post(log_out);
-    app
+    app.at("/admin/login_activity").get(login_activity);
+    app.at("/admin/two_factor").get(two_factor_settings);
+    app.at("/admin/two_factor/enroll").post(enroll_two_factor);
+    app.at("/admin/two_factor/confirm").post(confirm_two_factor);
+    app.at("/admin/two_factor/disable").post(disable_two_factor);
+    app.at("/admin/sessions").get(sessions_page);
+    app.at("/admin/sessions/revoke").post(revoke_session);
+    app.at("/admin/sessions/revoke_others")
+        .post(revoke_other_sessions);
+    app.at("/admin/api_tokens").get(api_tokens_page);
+    app.at("/admin/api_tokens/create").post(create_api_token);
+    app.at("/admin/api_tokens/revoke").post(revoke_api_token);
+    app.at("/admin/users").get(users_page);
+    app.at("/admin/users/invite").post(invite_user);
+    app.at("/admin/users/deactivate").post(deactivate_user);
+    app.at("/admin/users/activate").post(activate_user);
+    app.at("/admin/users/delete").post(delete_user);
+    app.at("/admin/users/role").post(change_user_role);
+    app.at("/invitations/accept")
+        .get(accept_invitation_form)
+        .post(accept_invitation);
+    app.at("/admin/subscribers/export").get(export_subscribers);
+    app.at("/admin/subscribers/data").get(subscriber_data_form);
+    app.at("/admin/subscribers/consent").get(subscriber_consent);
+    app.at("/admin/subscribers/data/export")
+        .get(export_subscriber);
+    app.at("/admin/subscribers/data/erase")
+        .post(erase_subscriber);
+    api::register(&mut app);
+    if !cookie_secure {
+        return app;
+    }
+    // Cookies only become `Set-Cookie` headers on their way out of a server, so they
+    // are marked `Secure` by a server wrapping the application.
+    let mut server = tide::with_state(app.state().clone());
+    server.with(SecureCookies);
+    server.at("/").all(app.clone());
+    server.at("*").all(app);
+    server
+}
+
+fn session_middleware(
+    settings: &SessionSettings,
+    redis_uri: &str,
+    secret: &[u8],
+) -> SessionMiddleware<RedisSessionStore> {
+    let middleware = SessionMiddleware::new(RedisSessionStore::new(redis_uri).unwrap(), secret)
+        .with_cookie_name(&settings.cookie_name)
+        .with_same_site_policy(settings.cookie_same_site.into())
+        // Expired sessions are turned away by `RequiredLoginMiddleware`, which tells
+        // the user why, the store only has to clean them up eventually.
+        .with_session_ttl(Some(
+            settings.idle_timeout().max(settings.absolute_timeout()),
+        ));
+    match &settings.cookie_domain {
+        Some(domain) => middleware.with_cookie_domain(domain),
+        None => middleware,
+    }
+}
+
+/// Mark every cookie we set `Secure`, for requests that reached a TLS-terminating proxy.
+///
+/// tide only marks the session cookie `Secure` for https requests, and those only
+/// ever reach us as plain http.
+struct SecureCookies;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for SecureCookies {
+    async fn handle(&self, req: tide::Request<State>, next: Next<'_, State>) -> tide::Result {
+        let mut res = next.run(req).await;
+        if let Some(cookies) = res.remove_header(headers::SET_COOKIE) {
+            for cookie in cookies.iter() {
+                let cookie = match Cookie::parse_encoded(cookie.as_str()) {
+                    Ok(mut cookie) => {
+                        cookie.set_secure(true);
+                        cookie.encoded().to_string()
+                    }
+                    Err(_) => cookie.as_str().to_string(),
+                };
+                res.append_header(headers::SET_COOKIE, cookie);
+            }
+        }
+        Ok(res)
+    }
 }
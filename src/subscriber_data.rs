@@ -0,0 +1,331 @@
+use crate::consent::{get_consent_events, ConsentEvent};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// What a subscriber (or an admin on their behalf) asked us to do with their data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataRequestKind {
+    Export,
+    Erasure,
+}
+
+impl DataRequestKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestKind::Export => "export",
+            DataRequestKind::Erasure => "erasure",
+        }
+    }
+}
+
+impl TryFrom<String> for DataRequestKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "export" => Ok(Self::Export),
+            "erasure" => Ok(Self::Erasure),
+            other => Err(format!(
+                "{other} is not a supported data request. Use either `export` or `erasure`."
+            )),
+        }
+    }
+}
+
+/// Who asked for an export or an erasure, recorded in the audit log.
+#[derive(Debug, Clone, Copy)]
+pub enum Requester {
+    Subscriber,
+    Admin(Uuid),
+}
+
+impl Requester {
+    fn user_id(&self) -> Option<Uuid> {
+        match self {
+            Requester::Subscriber => None,
+            Requester::Admin(user_id) => Some(*user_id),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberDataExport {
+    pub subscriber: SubscriberRecord,
+    pub subscription_tokens: Vec<String>,
+    pub pending_deliveries: Vec<PendingDelivery>,
+    pub failed_deliveries: Vec<FailedDelivery>,
+    pub consent_events: Vec<ConsentEvent>,
+    pub subscription_attempts: Vec<SubscriptionAttempt>,
+    pub data_requests: Vec<DataRequestRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PendingDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct FailedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionAttempt {
+    pub client_ip: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DataRequestRecord {
+    pub kind: String,
+    pub performed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get subscriber id from email", skip(email, pool))]
+pub async fn get_subscriber_id_from_email(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT id FROM subscriptions WHERE email = $1"#, email)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up a subscriber by email.")?;
+    Ok(row.map(|r| r.id))
+}
+
+/// Collect everything tied to a subscriber.
+///
+/// Returns `None` if the subscriber doesn't exist (anymore).
+#[tracing::instrument(name = "Export subscriber data", skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    requester: Requester,
+) -> Result<Option<SubscriberDataExport>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the subscriber.")?;
+    let subscriber = match subscriber {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch subscription tokens.")?
+    .into_iter()
+    .map(|r| r.subscription_token)
+    .collect();
+    let pending_deliveries = sqlx::query_as!(
+        PendingDelivery,
+        r#"
+        SELECT q.newsletter_issue_id, i.title
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch pending deliveries.")?;
+    let failed_deliveries = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.error, d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch failed deliveries.")?;
+    let consent_events = get_consent_events(&mut transaction, subscriber_id).await?;
+    let subscription_attempts = sqlx::query_as!(
+        SubscriptionAttempt,
+        r#"
+        SELECT client_ip, attempted_at
+        FROM subscription_attempts
+        WHERE email = $1
+        ORDER BY attempted_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch subscription attempts.")?;
+    let data_requests = sqlx::query_as!(
+        DataRequestRecord,
+        r#"
+        SELECT kind, performed_at
+        FROM data_request_audit_log
+        WHERE subscriber_id = $1
+        ORDER BY performed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch data requests.")?;
+    record_data_request(
+        &mut transaction,
+        subscriber_id,
+        DataRequestKind::Export,
+        requester,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to export subscriber data.")?;
+
+    Ok(Some(SubscriberDataExport {
+        subscriber,
+        subscription_tokens,
+        pending_deliveries,
+        failed_deliveries,
+        consent_events,
+        subscription_attempts,
+        data_requests,
+    }))
+}
+
+/// Delete every row tied to a subscriber, in a single transaction.
+///
+/// Returns `false` if there was nothing to erase.
+#[tracing::instrument(name = "Erase subscriber data", skip(pool))]
+pub async fn erase_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    requester: Requester,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the subscriber.")?;
+    let email = match subscriber {
+        Some(s) => s.email,
+        None => return Ok(false),
+    };
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete pending deliveries.")?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_dead_letters WHERE subscriber_email = $1"#,
+        email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete failed deliveries.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_attempts WHERE email = $1"#,
+        email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete subscription attempts.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete subscription tokens.")?;
+    sqlx::query!(
+        r#"DELETE FROM data_request_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete data request tokens.")?;
+    sqlx::query!(
+        r#"DELETE FROM consent_events WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete consent events.")?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the subscriber.")?;
+    record_data_request(
+        &mut transaction,
+        subscriber_id,
+        DataRequestKind::Erasure,
+        requester,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase subscriber data.")?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Record data request in the audit log", skip(transaction))]
+async fn record_data_request(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: DataRequestKind,
+    requester: Requester,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO data_request_audit_log (id, subscriber_id, kind, requested_by, performed_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        kind.as_str(),
+        requester.user_id()
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record the data request in the audit log.")?;
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn login(app: &TestApp, user: &TestUser) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// Invite `email` as the logged in user and return the link we emailed.
+async fn invite(app: &TestApp, email: &str, role: &str) -> surf::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_users(
+            "invite",
+            &serde_json::json!({ "email": email, "role": role }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+fn invitation_token(link: &surf::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "invitation_token")
+        .unwrap()
+        .1
+        .to_string()
+}
+
+#[async_std::test]
+async fn you_must_be_logged_in_to_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_users().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn users_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+
+    // Act
+    let html_page = app.get_admin_users_html().await;
+
+    // Assert
+    assert!(html_page.contains(&app.test_user.username));
+    assert!(html_page.contains("(you)"));
+}
+
+#[async_std::test]
+async fn an_invited_user_can_create_an_account_and_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let link = invite(&app, "new_admin@example.com", "viewer").await;
+    assert!(app
+        .get_admin_users_html()
+        .await
+        .contains("new_admin@example.com"));
+    app.post_logout().await;
+
+    // Act - Part 1 - Follow the link
+    let form = surf::get(link.clone()).recv_string().await.unwrap();
+    assert!(form.contains("new_admin@example.com"));
+    assert!(form.contains("viewer"));
+
+    // Act - Part 2 - Create the account
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "invitation_token": invitation_token(&link),
+            "username": "new_admin",
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Log in
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new_admin",
+            "password": "a-brand-new-password"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let email = sqlx::query!("SELECT email FROM users WHERE username = 'new_admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    assert_eq!(email.as_deref(), Some("new_admin@example.com"));
+    let role = sqlx::query!("SELECT role FROM users WHERE username = 'new_admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .role;
+    assert_eq!(role, "viewer");
+}
+
+#[async_std::test]
+async fn invitations_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let link = invite(&app, "new_admin@example.com", "viewer").await;
+    let body = serde_json::json!({
+        "invitation_token": invitation_token(&link),
+        "username": "new_admin",
+        "password": "a-brand-new-password",
+        "password_check": "a-brand-new-password"
+    });
+    app.post_accept_invitation(&body).await;
+
+    // Act
+    let response = app.post_accept_invitation(&body).await;
+
+    // Assert
+    assert_eq!(response.status(), 401);
+    let response = surf::get(link).await.unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[async_std::test]
+async fn expired_invitations_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let link = invite(&app, "new_admin@example.com", "viewer").await;
+    sqlx::query!("UPDATE user_invitations SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "invitation_token": invitation_token(&link),
+            "username": "new_admin",
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 401);
+}
+
+#[async_std::test]
+async fn accepting_an_invitation_with_a_taken_username_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let link = invite(&app, "new_admin@example.com", "viewer").await;
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "invitation_token": invitation_token(&link),
+            "username": app.test_user.username,
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password"
+        }))
+        .await;
+
+    // Assert
+    let expected_location = format!(
+        "/invitations/accept?invitation_token={}",
+        invitation_token(&link)
+    );
+    assert_is_redirect_to(&response, &expected_location);
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", app.address, expected_location))
+        .recv_string()
+        .await
+        .unwrap();
+    assert!(html_page.contains("This username is already taken."));
+}
+
+#[async_std::test]
+async fn inviting_an_invalid_email_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "invite",
+            &serde_json::json!({ "email": "not-an-email", "role": "viewer" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("not-an-email if not a valid subscriber email."));
+}
+
+#[async_std::test]
+async fn deactivated_users_cannot_log_in_and_lose_their_session() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    login(&app, &other_user).await;
+
+    // Act - Part 1 - Deactivate the logged in user behind their back
+    sqlx::query!(
+        "UPDATE users SET active = false WHERE user_id = $1",
+        other_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Assert - Part 1
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Log in again
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": other_user.username,
+            "password": other_user.password
+        }))
+        .await;
+
+    // Assert - Part 2
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Authentication failed"));
+}
+
+#[async_std::test]
+async fn users_can_be_deactivated_activated_and_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    login(&app, &app.test_user).await;
+    let body = serde_json::json!({ "user_id": other_user.user_id });
+    let active = || async {
+        sqlx::query!(
+            "SELECT active FROM users WHERE user_id = $1",
+            other_user.user_id
+        )
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap()
+        .map(|r| r.active)
+    };
+
+    // Act & Assert
+    let response = app.post_admin_users("deactivate", &body).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    assert_eq!(active().await, Some(false));
+
+    app.post_admin_users("activate", &body).await;
+    assert_eq!(active().await, Some(true));
+
+    app.post_admin_users("delete", &body).await;
+    assert_eq!(active().await, None);
+}
+
+#[async_std::test]
+async fn the_last_active_owner_cannot_be_deactivated_deleted_or_demoted() {
+    // Arrange
+    let app = spawn_app().await;
+    // The seed user is the other active user, take it out of the picture.
+    sqlx::query!(
+        "UPDATE users SET active = false WHERE user_id <> $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    login(&app, &app.test_user).await;
+    let body = serde_json::json!({ "user_id": app.test_user.user_id });
+
+    // Act - Part 1 - Deactivate
+    let response = app.post_admin_users("deactivate", &body).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The last active owner can&#x27;t be deactivated."));
+
+    // Act - Part 2 - Delete
+    let response = app.post_admin_users("delete", &body).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The last active owner can&#x27;t be deleted."));
+
+    // Act - Part 3 - Demote
+    let response = app
+        .post_admin_users(
+            "role",
+            &serde_json::json!({ "user_id": app.test_user.user_id, "role": "editor" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The last active owner must stay an owner."));
+
+    // Assert
+    let user = sqlx::query!(
+        "SELECT active, role FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(user.active);
+    assert_eq!(user.role, "owner");
+}
+
+#[async_std::test]
+async fn invited_users_must_pick_a_password_following_the_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let link = invite(&app, "new_admin@example.com", "viewer").await;
+
+    // Act
+    let response = app
+        .post_accept_invitation(&serde_json::json!({
+            "invitation_token": invitation_token(&link),
+            "username": "new_admin",
+            "password": "short",
+            "password_check": "short"
+        }))
+        .await;
+
+    // Assert
+    let expected_location = format!(
+        "/invitations/accept?invitation_token={}",
+        invitation_token(&link)
+    );
+    assert_is_redirect_to(&response, &expected_location);
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", app.address, expected_location))
+        .recv_string()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The password must be at least 12 characters long."));
+}
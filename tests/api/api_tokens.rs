@@ -0,0 +1,197 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use surf::StatusCode;
+
+async fn login(app: &TestApp, user: &TestUser) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// Create a token from the admin UI, returning it in clear.
+async fn create_token(app: &TestApp, scopes: &str) -> String {
+    let mut response = app
+        .post_admin_api_tokens(
+            "create",
+            &serde_json::json!({ "name": "CMS", "scopes": scopes }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::Ok);
+    let html_page = response.body_string().await.unwrap();
+    let (_, rest) = html_page.split_once("<code>z2p_").unwrap();
+    format!("z2p_{}", rest.split('<').next().unwrap())
+}
+
+async fn publish_with_token(app: &TestApp, token: &str) -> surf::Response {
+    let mut response = surf::post(format!("{}/admin/newsletters", app.address))
+        .header("Authorization", format!("Bearer {token}"))
+        .body_json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .unwrap()
+        .await
+        .expect("Failed to execute request.");
+    let body = response.body_string().await.unwrap();
+    response.set_body(body);
+    response
+}
+
+#[async_std::test]
+async fn tokens_are_listed_and_stored_hashed() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+
+    // Act
+    let token = create_token(&app, "newsletters:publish").await;
+
+    // Assert
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("CMS"));
+    assert!(html_page.contains("newsletters:publish"));
+    assert!(!html_page.contains(&token));
+    let stored = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+}
+
+#[async_std::test]
+async fn tokens_need_a_name_and_a_known_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+
+    for (body, message) in [
+        (
+            serde_json::json!({ "name": "", "scopes": "newsletters:publish" }),
+            "Give the token a name.",
+        ),
+        (
+            serde_json::json!({ "name": "CMS" }),
+            "Pick at least one scope.",
+        ),
+        (
+            serde_json::json!({ "name": "CMS", "scopes": "everything" }),
+            "everything is not a supported scope.",
+        ),
+    ] {
+        // Act
+        let response = app.post_admin_api_tokens("create", &body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/api_tokens");
+        let html_page = app.get_admin_api_tokens_html().await;
+        assert!(html_page.contains(message));
+    }
+}
+
+#[async_std::test]
+async fn a_token_can_publish_a_newsletter_from_json() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let token = create_token(&app, "newsletters:publish").await;
+
+    // Act
+    let mut response = publish_with_token(&app, &token).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Created);
+    let body: serde_json::Value = response.body_json().await.unwrap();
+    let issue_id: uuid::Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let issue = sqlx::query!(
+        "SELECT title FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Newsletter title");
+}
+
+#[async_std::test]
+async fn invalid_tokens_are_challenged() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = publish_with_token(&app, "z2p_not-a-token").await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Unauthorized);
+    assert_eq!(
+        response.header("WWW-Authenticate").unwrap().as_str(),
+        r#"Bearer realm="publish""#
+    );
+}
+
+#[async_std::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let token = create_token(&app, "newsletters:publish").await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    // Act
+    let response = app
+        .post_admin_api_tokens("revoke", &serde_json::json!({ "token_id": token_id }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("The token has been revoked."));
+    let response = publish_with_token(&app, &token).await;
+    assert_eq!(response.status(), StatusCode::Unauthorized);
+}
+
+#[async_std::test]
+async fn tokens_only_reach_the_endpoints_of_their_scopes() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user).await;
+    let token = create_token(&app, "newsletters:publish").await;
+
+    // Act
+    let mut response = surf::get(format!("{}/admin/users", app.address))
+        .header("Authorization", format!("Bearer {token}"))
+        .await
+        .unwrap();
+    response.body_bytes().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+}
+
+#[async_std::test]
+async fn tokens_do_not_exceed_the_role_of_their_owner() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    login(&app, &viewer).await;
+    let token = create_token(&app, "newsletters:publish").await;
+
+    // Act
+    let response = publish_with_token(&app, &token).await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+}
@@ -0,0 +1,472 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, spawn_app_with, PostmarkBatch,
+    TestApp,
+};
+use async_std::prelude::FutureExt;
+use std::time::Duration;
+use surf::http::Method;
+use surf::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const ALL_SCOPES: [&str; 4] = [
+    "newsletters:read",
+    "newsletters:publish",
+    "subscribers:read",
+    "subscribers:write",
+];
+
+/// Log the test user in and create a token with `scopes` from the admin UI.
+async fn create_token(app: &TestApp, scopes: &[&str]) -> String {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let mut fields = vec![("name", "Integration")];
+    fields.extend(scopes.iter().map(|scope| ("scopes", *scope)));
+    let mut response = app.post_admin_api_tokens("create", &fields).await;
+    assert_eq!(response.status(), StatusCode::Ok);
+    let html_page = response.body_string().await.unwrap();
+    let (_, rest) = html_page.split_once("<code>z2p_").unwrap();
+    format!("z2p_{}", rest.split('<').next().unwrap())
+}
+
+/// Call the API, returning the status, the `Location` header and the JSON body.
+async fn call(
+    app: &TestApp,
+    method: Method,
+    path: &str,
+    token: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, Option<String>, serde_json::Value) {
+    let url = surf::Url::parse(&format!("{}{}", app.address, path)).unwrap();
+    let mut request = surf::RequestBuilder::new(method, url);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {token}"));
+    }
+    if let Some(body) = body {
+        request = request.body_json(&body).unwrap();
+    }
+    let mut response = request.await.expect("Failed to execute request.");
+    let text = response.body_string().await.unwrap();
+    let json = if text.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_str(&text).unwrap()
+    };
+    let location = response.header("Location").map(|h| h.as_str().to_string());
+    (response.status(), location, json)
+}
+
+#[async_std::test]
+async fn the_openapi_document_is_public() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let (status, _, document) = call(&app, Method::Get, "/api/v1/openapi.json", None, None).await;
+
+    // Assert
+    assert_eq!(status, StatusCode::Ok);
+    assert_eq!(document["openapi"], "3.0.3");
+    assert!(document["paths"]["/api/v1/subscribers/{id}"]["patch"].is_object());
+}
+
+#[async_std::test]
+async fn requests_without_a_valid_token_get_a_json_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for (token, message) in [
+        (None, "Missing API token."),
+        (Some("z2p_not-a-token"), "Invalid API token."),
+    ] {
+        // Act
+        let (status, _, body) = call(&app, Method::Get, "/api/v1/subscribers", token, None).await;
+
+        // Assert
+        assert_eq!(status, StatusCode::Unauthorized);
+        assert_eq!(body["error"]["code"], "unauthorized");
+        assert_eq!(body["error"]["message"], message);
+    }
+}
+
+#[async_std::test]
+async fn tokens_only_reach_the_operations_of_their_scopes() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &["newsletters:read"]).await;
+
+    // Act
+    let (listing_issues, _, _) =
+        call(&app, Method::Get, "/api/v1/issues", Some(&token), None).await;
+    let (listing_subscribers, _, body) =
+        call(&app, Method::Get, "/api/v1/subscribers", Some(&token), None).await;
+
+    // Assert
+    assert_eq!(listing_issues, StatusCode::Ok);
+    assert_eq!(listing_subscribers, StatusCode::Forbidden);
+    assert_eq!(body["error"]["code"], "forbidden");
+}
+
+#[async_std::test]
+async fn unknown_subscribers_get_a_json_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+
+    // Act
+    let (status, _, body) = call(
+        &app,
+        Method::Get,
+        &format!("/api/v1/subscribers/{}", uuid::Uuid::new_v4()),
+        Some(&token),
+        None,
+    )
+    .await;
+
+    // Assert
+    assert_eq!(status, StatusCode::NotFound);
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[async_std::test]
+async fn subscribers_can_be_created_confirmed_and_erased() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Create
+    let (status, location, created) = call(
+        &app,
+        Method::Post,
+        "/api/v1/subscribers",
+        Some(&token),
+        Some(serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::Created);
+    assert_eq!(created["status"], "pending_confirmation");
+    let location = location.unwrap();
+    assert_eq!(
+        location,
+        format!("/api/v1/subscribers/{}", created["id"].as_str().unwrap())
+    );
+
+    // Act - Part 2 - List
+    let (_, _, pending) = call(
+        &app,
+        Method::Get,
+        "/api/v1/subscribers?status=pending_confirmation",
+        Some(&token),
+        None,
+    )
+    .await;
+    assert_eq!(
+        pending["subscribers"][0]["email"],
+        "ursula_le_guin@gmail.com"
+    );
+
+    // Act - Part 3 - Confirm
+    let (status, _, updated) = call(
+        &app,
+        Method::Patch,
+        &location,
+        Some(&token),
+        Some(serde_json::json!({ "status": "confirmed" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::Ok);
+    assert_eq!(updated["status"], "confirmed");
+    let events = sqlx::query!("SELECT kind, source FROM consent_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let events: Vec<_> = events.into_iter().map(|e| (e.kind, e.source)).collect();
+    assert_eq!(
+        events,
+        vec![
+            ("signup".to_string(), Some("api".to_string())),
+            ("confirmation".to_string(), Some("api".to_string()))
+        ]
+    );
+
+    // Act - Part 4 - Erase
+    let (status, _, _) = call(&app, Method::Delete, &location, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NoContent);
+    let (status, _, _) = call(&app, Method::Get, &location, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NotFound);
+    let (status, _, _) = call(&app, Method::Delete, &location, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NotFound);
+}
+
+#[async_std::test]
+async fn invalid_subscriber_changes_get_a_json_error() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    for (method, path, body, expected_status, code) in [
+        (
+            Method::Post,
+            "/api/v1/subscribers".to_string(),
+            serde_json::json!({ "name": "le guin", "email": "definitely-not-an-email" }),
+            StatusCode::BadRequest,
+            "validation_error",
+        ),
+        (
+            Method::Post,
+            "/api/v1/subscribers".to_string(),
+            serde_json::json!({ "name": "le guin" }),
+            StatusCode::BadRequest,
+            "bad_request",
+        ),
+        (
+            Method::Post,
+            "/api/v1/subscribers".to_string(),
+            serde_json::json!({ "name": "le guin", "email": subscriber.email }),
+            StatusCode::Conflict,
+            "already_subscribed",
+        ),
+        (
+            Method::Patch,
+            format!("/api/v1/subscribers/{}", subscriber.id),
+            serde_json::json!({ "status": "vip" }),
+            StatusCode::BadRequest,
+            "validation_error",
+        ),
+        (
+            Method::Patch,
+            "/api/v1/subscribers/not-an-id".to_string(),
+            serde_json::json!({ "status": "confirmed" }),
+            StatusCode::BadRequest,
+            "bad_request",
+        ),
+    ] {
+        // Act
+        let (status, _, response) = call(&app, method, &path, Some(&token), Some(body)).await;
+
+        // Assert
+        assert_eq!(status, expected_status);
+        assert_eq!(response["error"]["code"], code);
+        assert!(response["error"]["message"].is_string());
+    }
+}
+
+#[async_std::test]
+async fn drafts_are_only_sent_once_published() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act - Part 1 - Create a draft
+    let (status, location, draft) = call(
+        &app,
+        Method::Post,
+        "/api/v1/issues",
+        Some(&token),
+        Some(serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>"
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::Created);
+    assert!(draft["published_at"].is_null());
+    assert!(draft["delivery"].is_null());
+    let queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+    let (_, _, issues) = call(&app, Method::Get, "/api/v1/issues", Some(&token), None).await;
+    assert_eq!(issues["issues"][0]["title"], "Newsletter title");
+
+    // Act - Part 2 - Publish it
+    let location = location.unwrap();
+    let publish_path = format!("{location}/publish");
+    let (status, _, published) = call(&app, Method::Post, &publish_path, Some(&token), None).await;
+    assert_eq!(status, StatusCode::Ok);
+    assert!(published["published_at"].is_string());
+    assert_eq!(published["delivery"]["recipients"], 1);
+    assert_eq!(published["delivery"]["pending"], 1);
+
+    // Act - Part 3 - Publish it again
+    let (status, _, body) = call(&app, Method::Post, &publish_path, Some(&token), None).await;
+    assert_eq!(status, StatusCode::Conflict);
+    assert_eq!(body["error"]["code"], "conflict");
+
+    // Act - Part 4 - Deliver it
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let (_, _, delivered) = call(&app, Method::Get, &location, Some(&token), None).await;
+    assert_eq!(delivered["delivery"]["pending"], 0);
+}
+
+#[async_std::test]
+async fn issues_need_a_title_and_an_existing_draft() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+
+    // Act
+    let (empty_title, _, body) = call(
+        &app,
+        Method::Post,
+        "/api/v1/issues",
+        Some(&token),
+        Some(serde_json::json!({ "title": " ", "text_content": "", "html_content": "" })),
+    )
+    .await;
+    let (unknown_issue, _, _) = call(
+        &app,
+        Method::Post,
+        &format!("/api/v1/issues/{}/publish", uuid::Uuid::new_v4()),
+        Some(&token),
+        None,
+    )
+    .await;
+
+    // Assert
+    assert_eq!(empty_title, StatusCode::BadRequest);
+    assert_eq!(
+        body["error"]["message"],
+        "The title of an issue can't be empty."
+    );
+    assert_eq!(unknown_issue, StatusCode::NotFound);
+}
+
+#[async_std::test]
+async fn retried_requests_with_the_same_idempotency_key_are_processed_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    let create_draft = |key: &str| {
+        surf::post(format!("{}/api/v1/issues", app.address))
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Idempotency-Key", key)
+            .body_json(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>"
+            }))
+            .unwrap()
+            .recv_json::<serde_json::Value>()
+    };
+
+    // Act
+    let first = create_draft("first-attempt").await.unwrap();
+    let retry = create_draft("first-attempt").await.unwrap();
+    let other = create_draft("second-attempt").await.unwrap();
+
+    // Assert
+    assert_eq!(first["newsletter_issue_id"], retry["newsletter_issue_id"]);
+    assert_ne!(first["newsletter_issue_id"], other["newsletter_issue_id"]);
+    let stored = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 2);
+}
+
+#[async_std::test]
+async fn retried_subscriber_creations_send_a_single_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let create_subscriber = || async {
+        let mut response = surf::post(format!("{}/api/v1/subscribers", app.address))
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Idempotency-Key", "first-attempt")
+            .body_json(&serde_json::json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com"
+            }))
+            .unwrap()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.body_json().await.unwrap();
+        (response.status(), body)
+    };
+
+    // Act
+    let (first_status, first) = create_subscriber().await;
+    let (retry_status, retry) = create_subscriber().await;
+
+    // Assert
+    assert_eq!(first_status, StatusCode::Created);
+    assert_eq!(retry_status, StatusCode::Created);
+    assert_eq!(first["id"], retry["id"]);
+    let stored = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 1);
+}
+
+#[async_std::test]
+async fn requests_whose_key_was_taken_over_answer_with_the_saved_response() {
+    // Arrange - every claim counts as abandoned, so the second request takes the key over
+    let app = spawn_app_with(|c| c.idempotency.abandoned_after_seconds = 0).await;
+    let token = create_token(&app, &ALL_SCOPES).await;
+    // Slow enough for both requests to be processed at the same time.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&app.email_server)
+        .await;
+    let create_subscriber = || {
+        surf::post(format!("{}/api/v1/subscribers", app.address))
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Idempotency-Key", "first-attempt")
+            .body_json(&serde_json::json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com"
+            }))
+            .unwrap()
+            .recv_json::<serde_json::Value>()
+    };
+
+    // Act
+    let (first, second) = create_subscriber()
+        .join(async {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            create_subscriber().await
+        })
+        .await;
+
+    // Assert
+    assert_eq!(first.unwrap()["id"], second.unwrap()["id"]);
+    let stored = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, 1);
+}
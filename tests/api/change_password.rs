@@ -136,3 +136,55 @@ async fn changing_password_works() {
     let response = app.post_login(&login_body).await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[async_std::test]
+async fn new_passwords_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let test_cases = vec![
+        (
+            "Sh0rt!pass".to_string(),
+            "The password must be at least 12 characters long.",
+        ),
+        (
+            "xY7-".repeat(33),
+            "The password must be at most 128 characters long.",
+        ),
+        (
+            format!("{}-2024", app.test_user.username),
+            "The password must not contain your username.",
+        ),
+        (
+            "Password123456".to_string(),
+            "This password appeared in a data breach, please pick another one.",
+        ),
+        (
+            "aaaaaaaaaaaaaaaa".to_string(),
+            "This password is too easy to guess, make it longer or mix in other kinds of characters.",
+        ),
+    ];
+
+    for (new_password, error_message) in test_cases {
+        // Act
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/password");
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(error_message),
+            "Expected `{error_message}` for `{new_password}`."
+        );
+    }
+}
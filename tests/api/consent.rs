@@ -0,0 +1,142 @@
+use crate::helpers::{spawn_app, TestApp};
+use surf::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Subscribe through a specific form, the way a browser would.
+async fn subscribe_from_form(app: &TestApp, mut body: serde_json::Value) -> Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let form_fields = app.get_subscribe_form_fields().await;
+    for (name, value) in form_fields.as_object().unwrap() {
+        body[name] = value.clone();
+    }
+    let mut request = surf::post(format!("{}/subscriptions", app.address))
+        .header("User-Agent", "Mozilla/5.0 <script>")
+        .build();
+    request.body_form(&body).unwrap();
+    let response = surf::client().send(request).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+#[async_std::test]
+async fn subscribing_records_a_signup_consent_event() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    subscribe_from_form(
+        &app,
+        serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "source": "footer-form"
+        }),
+    )
+    .await;
+
+    // Assert
+    let event = sqlx::query!(
+        "SELECT kind, client_ip, user_agent, source, consent_text_version FROM consent_events"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the consent event.");
+    assert_eq!(event.kind, "signup");
+    assert_eq!(event.client_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(event.user_agent.as_deref(), Some("Mozilla/5.0 <script>"));
+    assert_eq!(event.source.as_deref(), Some("footer-form"));
+    assert_eq!(event.consent_text_version, "1");
+}
+
+#[async_std::test]
+async fn confirming_records_a_single_confirmation_of_the_signup_consent() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link = subscribe_from_form(
+        &app,
+        serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "source": "footer-form",
+            "consent_text_version": "2"
+        }),
+    )
+    .await;
+
+    // Act
+    for _ in 0..2 {
+        let response = surf::get(confirmation_link.clone()).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    // Assert
+    let events = sqlx::query!(
+        "SELECT kind, source, consent_text_version FROM consent_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].kind, "confirmation");
+    assert_eq!(events[1].source.as_deref(), Some("footer-form"));
+    // The client can't pick the consent text it agreed to.
+    assert_eq!(events[1].consent_text_version, "1");
+}
+
+#[async_std::test]
+async fn consent_events_cannot_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_from_form(
+        &app,
+        serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"}),
+    )
+    .await;
+
+    // Act
+    let outcome = sqlx::query!("UPDATE consent_events SET consent_text_version = 'forged'")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[async_std::test]
+async fn consent_history_is_visible_to_admins_and_exported() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link = subscribe_from_form(
+        &app,
+        serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"}),
+    )
+    .await;
+    surf::get(confirmation_link).await.unwrap();
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act - Part 1 - Consent page
+    let html_page = app
+        .get_subscriber_consent_html("ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert!(html_page.contains("<td>signup</td>"));
+    assert!(html_page.contains("<td>confirmation</td>"));
+    // User agents are attacker controlled, they must not be rendered as markup.
+    assert!(html_page.contains("Mozilla/5.0 &lt;script&gt;"));
+
+    // Act - Part 2 - Data export
+    let mut response = app
+        .get_admin_subscriber_export("ursula_le_guin@gmail.com")
+        .await;
+    let export: serde_json::Value = response.body_json().await.unwrap();
+    assert_eq!(export["consent_events"].as_array().unwrap().len(), 2);
+}
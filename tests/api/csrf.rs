@@ -0,0 +1,183 @@
+use crate::helpers::{assert_is_redirect_to, extract_csrf_token, spawn_app, TestApp};
+use surf::StatusCode;
+
+async fn post_login_form(app: &TestApp, body: &serde_json::Value) -> surf::Response {
+    let mut request = surf::post(format!("{}/login", app.address)).build();
+    request.body_form(body).unwrap();
+    let mut response = app
+        .api_client
+        .send(request)
+        .await
+        .expect("Failed to execute request.");
+    response.body_bytes().await.unwrap();
+    response
+}
+
+#[async_std::test]
+async fn forms_carry_the_csrf_token_of_the_session() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let login_page = app.get_login_html().await;
+    let token = extract_csrf_token(&login_page);
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let dashboard = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert_eq!(extract_csrf_token(&dashboard), token);
+}
+
+#[async_std::test]
+async fn the_token_can_be_sent_as_a_form_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = extract_csrf_token(&app.get_login_html().await);
+
+    // Act
+    let response = post_login_form(
+        &app,
+        &serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+            "csrf_token": token
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[async_std::test]
+async fn requests_without_a_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.get_login_html().await;
+
+    // Act
+    let response = post_login_form(
+        &app,
+        &serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+}
+
+#[async_std::test]
+async fn tokens_of_other_sessions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.get_login_html().await;
+    let other_client = surf::client().with(surf_cookie_middleware::CookieMiddleware::new());
+    let other_login_page = other_client
+        .get(format!("{}/login", app.address))
+        .recv_string()
+        .await
+        .unwrap();
+
+    // Act
+    let response = post_login_form(
+        &app,
+        &serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+            "csrf_token": extract_csrf_token(&other_login_page)
+        }),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+}
+
+#[async_std::test]
+async fn admin_forms_need_a_csrf_token_too() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let mut request = surf::post(format!("{}/admin/logout", app.address)).build();
+    request.body_form(&serde_json::json!({})).unwrap();
+    let mut response = app.api_client.send(request).await.unwrap();
+    response.body_bytes().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status(), StatusCode::Ok);
+}
+
+#[async_std::test]
+async fn requests_from_other_origins_are_rejected_even_with_a_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = extract_csrf_token(&app.get_login_html().await);
+    let body = serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password,
+        "csrf_token": token
+    });
+
+    for (header, value) in [
+        ("Origin", "https://evil.example.com"),
+        ("Origin", "null"),
+        ("Referer", "https://evil.example.com/page"),
+    ] {
+        // Act
+        let mut request = surf::post(format!("{}/login", app.address))
+            .header(header, value)
+            .build();
+        request.body_form(&body).unwrap();
+        let mut response = app.api_client.send(request).await.unwrap();
+        response.body_bytes().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status(),
+            StatusCode::Forbidden,
+            "A request with {header}: {value} was accepted."
+        );
+    }
+}
+
+#[async_std::test]
+async fn requests_from_the_same_origin_are_accepted() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = extract_csrf_token(&app.get_login_html().await);
+
+    // Act
+    let mut request = surf::post(format!("{}/login", app.address))
+        .header("Origin", app.address.as_str())
+        .build();
+    request
+        .body_form(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+            "csrf_token": token
+        }))
+        .unwrap();
+    let response = app.api_client.send(request).await.unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -1,13 +1,17 @@
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use http_types::StatusCode;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use surf::Url;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Respond, ResponseTemplate};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, DeliverySettings, Settings};
 use zero2prod::email_client::EmailClient;
 use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
@@ -20,35 +24,43 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: surf::Client,
     pub email_client: EmailClient,
+    pub delivery_settings: DeliverySettings,
 }
 
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role("owner")
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-            ValueS ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role)
+            ValueS ($1, $2, $3, $4)",
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.role
         )
         .execute(pool)
         .await
@@ -69,21 +81,56 @@ pub struct ConfirmationLinks {
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: &Subscription) -> surf::Response {
+        let mut form = self.get_subscribe_form_fields().await;
+        let form_fields = form.as_object_mut().unwrap();
+        if let Some(name) = &body.name {
+            form_fields.insert("name".into(), name.clone().into());
+        }
+        if let Some(email) = &body.email {
+            form_fields.insert("email".into(), email.clone().into());
+        }
+        self.post_subscribe_form(&form).await
+    }
+
+    /// Post the subscribe form as is, without the fields `get_subscribe_form_fields` adds.
+    pub async fn post_subscribe_form<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
         let url = Url::parse(&format!("{}/subscriptions", self.address))
             .expect("failed to parse url address");
 
         let mut request = surf::post(url).build();
-        request.body_form(&body).unwrap();
+        request.body_form(body).unwrap();
         self.api_client
             .send(request)
             .await
             .expect("Failed to execute request.")
     }
 
+    /// The signed timestamp the subscribe form page embeds, as form fields.
+    pub async fn get_subscribe_form_fields(&self) -> serde_json::Value {
+        let html = surf::get(format!("{}/subscriptions", self.address))
+            .recv_string()
+            .await
+            .expect("Failed to fetch the subscribe form.");
+        let extract = |name: &str| {
+            let marker = format!(r#"name="{name}" value=""#);
+            let start = html.find(&marker).unwrap() + marker.len();
+            let end = start + html[start..].find('"').unwrap();
+            html[start..end].to_string()
+        };
+        serde_json::json!({
+            "form_issued_at": extract("form_issued_at"),
+            "form_signature": extract("form_signature"),
+        })
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> surf::Response {
         let url = Url::parse(&format!("{}/admin/newsletters", self.address))
             .expect("failed to parse url address");
         let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
         request.body_form(&body).unwrap();
         self.api_client
             .send(request)
@@ -99,6 +146,7 @@ impl TestApp {
             Url::parse(&format!("{}/login", &self.address)).expect("failed to parse url address");
 
         let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
         request.body_form(body).unwrap();
         self.api_client
             .send(request)
@@ -106,6 +154,12 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// The CSRF token of the client's session, taken from a form like a browser would.
+    pub async fn csrf_token(&self) -> String {
+        let html_page = self.get_login_html().await;
+        extract_csrf_token(&html_page)
+    }
+
     pub async fn get_login_html(&self) -> String {
         let url =
             Url::parse(&format!("{}/login", &self.address)).expect("failed to parse url address");
@@ -119,6 +173,142 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn get_login_activity(&self) -> surf::Response {
+        let url = Url::parse(&format!("{}/admin/login_activity", &self.address))
+            .expect("failed to parse url address");
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_login_activity_html(&self) -> String {
+        self.get_login_activity().await.body_string().await.unwrap()
+    }
+
+    pub async fn get_admin_users(&self) -> surf::Response {
+        let url = Url::parse(&format!("{}/admin/users", &self.address))
+            .expect("failed to parse url address");
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        self.get_admin_users().await.body_string().await.unwrap()
+    }
+
+    /// Post to one of the `/admin/users/{action}` endpoints.
+    pub async fn post_admin_users<Body>(&self, action: &str, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/users/{}", &self.address, action))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_accept_invitation<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/invitations/accept", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_forgot_password<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/login/forgot", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/login/reset", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two_factor", &self.address))
+            .recv_string()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Post to one of the `/admin/two_factor/{action}` endpoints.
+    pub async fn post_admin_two_factor<Body>(&self, action: &str, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/two_factor/{}", &self.address, action))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_two_factor<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/login/two_factor", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login/two_factor", &self.address))
+            .recv_string()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_admin_dashboard_html(&self) -> String {
         let mut resp = self.get_admin_dashboard().await;
         resp.body_string().await.unwrap()
@@ -148,6 +338,19 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    pub async fn get_subscriptions_confirm(&self, query: &str) -> surf::Response {
+        let url = Url::parse(&format!(
+            "{}/subscriptions/confirm?{}",
+            &self.address, query
+        ))
+        .expect("failed to parse url address");
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn get_admin_dashboard(&self) -> surf::Response {
         let url = Url::parse(&format!("{}/admin/dashboard", &self.address))
             .expect("failed to parse url address");
@@ -171,13 +374,86 @@ impl TestApp {
     pub async fn post_logout(&self) -> surf::Response {
         let url = Url::parse(&format!("{}/admin/logout", &self.address))
             .expect("failed to parse url address");
-        let request = surf::post(url).build();
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
         self.api_client
             .send(request)
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_admin_sessions(&self) -> surf::Response {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_sessions_html(&self) -> String {
+        self.get_admin_sessions().await.body_string().await.unwrap()
+    }
+
+    pub async fn post_admin_sessions<Body>(&self, action: &str, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/sessions/{}", &self.address, action))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issues_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/issues{}", &self.address, path))
+            .recv_string()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_issues<Body>(&self, action: &str, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/issues/{}", &self.address, action))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .recv_string()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_api_tokens<Body>(&self, action: &str, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/api_tokens/{}", &self.address, action))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_change_password_html(&self) -> String {
         self.get_change_password()
             .await
@@ -211,6 +487,7 @@ impl TestApp {
         let url = Url::parse(&format!("{}/admin/password", &self.address))
             .expect("failed to parse url address");
         let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
         request.body_form(&body).unwrap();
         self.api_client
             .send(request)
@@ -218,10 +495,104 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_subscribers_export(&self, query: &str) -> surf::Response {
+        let url = Url::parse(&format!(
+            "{}/admin/subscribers/export?{}",
+            &self.address, query
+        ))
+        .expect("failed to parse url address");
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_data_request<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/subscriptions/data", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_erase_data<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/subscriptions/data/erase", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscriber_export(&self, email: &str) -> surf::Response {
+        let mut url = Url::parse(&format!("{}/admin/subscribers/data/export", &self.address))
+            .expect("failed to parse url address");
+        url.query_pairs_mut().append_pair("email", email);
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_admin_erase_subscriber<Body>(&self, body: &Body) -> surf::Response
+    where
+        Body: serde::Serialize,
+    {
+        let url = Url::parse(&format!("{}/admin/subscribers/data/erase", &self.address))
+            .expect("failed to parse url address");
+        let mut request = surf::post(url).build();
+        request.set_header(CSRF_TOKEN_HEADER, self.csrf_token().await);
+        request.body_form(body).unwrap();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscriber_data_html(&self) -> String {
+        let url = Url::parse(&format!("{}/admin/subscribers/data", &self.address))
+            .expect("failed to parse url address");
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+            .body_string()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_subscriber_consent_html(&self, email: &str) -> String {
+        let mut url = Url::parse(&format!("{}/admin/subscribers/consent", &self.address))
+            .expect("failed to parse url address");
+        url.query_pairs_mut().append_pair("email", email);
+        let request = surf::get(url).build();
+        self.api_client
+            .send(request)
+            .await
+            .expect("Failed to execute request")
+            .body_string()
+            .await
+            .unwrap()
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
+                try_execute_task(&self.db_pool, &self.email_client, &self.delivery_settings)
                     .await
                     .unwrap()
             {
@@ -229,6 +600,19 @@ impl TestApp {
             }
         }
     }
+
+    /// Wait until the email server received `count` requests, for emails sent in the
+    /// background.
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<wiremock::Request> {
+        for _ in 0..50 {
+            let requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= count {
+                return requests;
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("Expected {count} emails to be sent.");
+    }
 }
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
@@ -246,6 +630,14 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 
 // Launch our application in the background ~somehow~
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, letting the test adjust the configuration first.
+pub async fn spawn_app_with<F>(customise: F) -> TestApp
+where
+    F: FnOnce(&mut Settings),
+{
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -258,6 +650,11 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         c.database.database_name = Uuid::new_v4().to_string();
         c.email_client.base_url = email_server.uri();
+        // Tests submit the subscribe form as soon as they have loaded it.
+        c.subscriptions.min_form_fill_milliseconds = 0;
+        // Tests share a redis instance, keep their rate limit buckets apart.
+        c.rate_limits.key_prefix = format!("rate_limit:{}", c.database.database_name);
+        customise(&mut c);
         c
     };
     let connection_pool = configure_database(&configuration.database).await;
@@ -276,6 +673,7 @@ pub async fn spawn_app() -> TestApp {
         test_user: TestUser::generate(),
         api_client: client,
         email_client: configuration.email_client.client(),
+        delivery_settings: configuration.delivery,
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -302,7 +700,99 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
     connection_pool
 }
 
+/// Header the CSRF middleware accepts the token in, besides the `csrf_token` form field.
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+
+/// The value of the first hidden `csrf_token` field in `html_page`.
+pub fn extract_csrf_token(html_page: &str) -> String {
+    let (_, rest) = html_page
+        .split_once(r#"name="csrf_token" value=""#)
+        .expect("No CSRF token in the page.");
+    rest.split('"').next().unwrap().to_string()
+}
+
 pub fn assert_is_redirect_to(response: &surf::Response, location: &str) {
     assert_eq!(response.status(), StatusCode::SeeOther);
     assert_eq!(response.header("Location").unwrap().as_str(), location)
 }
+
+/// Use the public API of the application under test to create an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = Subscription {
+        name: Some(name),
+        email: Some(email),
+    };
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let resp = app.post_subscriptions(&body).await;
+    if resp.status().is_client_error() || resp.status().is_server_error() {
+        panic!("post subscripitons during create_unconfirmed_subscriber shouldn't failed");
+    }
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    let resp = surf::get(confirmation_link.html).await.unwrap();
+    if resp.status().is_client_error() || resp.status().is_server_error() {
+        panic!("post subscripitons during create_unconfirmed_subscriber shouldn't failed");
+    }
+}
+
+/// Answers Postmark's batch endpoint, refusing the emails sent to `rejected`.
+pub struct PostmarkBatch {
+    pub rejected: Vec<String>,
+    // What Postmark answers for the rejected emails.
+    pub error_code: i64,
+}
+
+impl PostmarkBatch {
+    pub fn accept_all() -> Self {
+        Self {
+            rejected: vec![],
+            error_code: 406,
+        }
+    }
+}
+
+impl Respond for PostmarkBatch {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = emails
+            .iter()
+            .map(|email| {
+                if self
+                    .rejected
+                    .iter()
+                    .any(|rejected| email["To"] == *rejected)
+                {
+                    let message = match self.error_code {
+                        300 => "Invalid email request",
+                        406 => "Inactive recipient",
+                        429 => "Rate limit exceeded",
+                        _ => "Rejected",
+                    };
+                    serde_json::json!({"ErrorCode": self.error_code, "Message": message})
+                } else {
+                    serde_json::json!({"ErrorCode": 0, "Message": "OK"})
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
@@ -0,0 +1,243 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, spawn_app_with, PostmarkBatch,
+    TestApp,
+};
+use wiremock::matchers::{method, path};
+use wiremock::Mock;
+use zero2prod::issue_delivery_worker::try_execute_task;
+
+async fn login_and_publish(app: &TestApp) -> uuid::Uuid {
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter <title>",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn queued_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscriber_email)
+        .collect()
+}
+
+#[async_std::test]
+async fn published_issues_are_listed_with_their_delivery_state() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = login_and_publish(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Before delivery
+    let html_page = app.get_admin_issues_html("").await;
+    assert!(html_page.contains("Newsletter &lt;title&gt;"));
+    assert!(html_page.contains("Delivering, 1 of 1 left"));
+
+    // Act - Part 2 - After delivery
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_admin_issues_html("").await;
+    assert!(html_page.contains("Delivered to 1"));
+
+    // Act - Part 3 - Details
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("Newsletter body as plain text"));
+    assert!(html_page.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+}
+
+#[async_std::test]
+async fn issues_can_be_copied_into_a_draft_and_published() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = login_and_publish(&app).await;
+
+    // Act - Part 1 - Copy
+    let response = app
+        .post_admin_issues("clone", &serde_json::json!({ "issue_id": issue_id }))
+        .await;
+    let draft = sqlx::query!(
+        "SELECT newsletter_issue_id, title FROM newsletter_issues WHERE published_at IS NULL"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/issues/view?issue_id={}", draft.newsletter_issue_id),
+    );
+    assert_eq!(draft.title, "Newsletter <title>");
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={}", draft.newsletter_issue_id))
+        .await;
+    assert!(html_page.contains("This is a new draft, copied from the issue."));
+    assert!(html_page.contains("Draft"));
+
+    // Act - Part 2 - Publish
+    create_confirmed_subscriber(&app).await;
+    let response = app
+        .post_admin_issues(
+            "publish",
+            &serde_json::json!({ "issue_id": draft.newsletter_issue_id }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/issues/view?issue_id={}", draft.newsletter_issue_id),
+    );
+    assert_eq!(queued_emails(&app).await.len(), 1);
+}
+
+#[async_std::test]
+async fn resending_reaches_only_subscribers_who_joined_since() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = login_and_publish(&app).await;
+    let delivery_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(delivery_guard);
+    create_confirmed_subscriber(&app).await;
+    let newcomer = sqlx::query!("SELECT email FROM subscriptions ORDER BY subscribed_at DESC")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    // Act - Part 1 - Resend
+    let _ = app
+        .post_admin_issues("resend", &serde_json::json!({ "issue_id": issue_id }))
+        .await;
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The issue is being sent to 1 more subscribers."));
+    assert_eq!(queued_emails(&app).await, vec![newcomer]);
+
+    // Act - Part 2 - Resend again
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let _ = app
+        .post_admin_issues("resend", &serde_json::json!({ "issue_id": issue_id }))
+        .await;
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("Nobody joined since the issue was last sent."));
+    assert!(queued_emails(&app).await.is_empty());
+}
+
+#[async_std::test]
+async fn paused_deliveries_wait_until_resumed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = login_and_publish(&app).await;
+    let body = serde_json::json!({ "issue_id": issue_id });
+
+    // Act - Part 1 - Pause
+    let response = app.post_admin_issues("pause", &body).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/issues/view?issue_id={issue_id}"),
+    );
+    let paused_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(paused_guard);
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The delivery has been paused."));
+    assert!(html_page.contains("Paused, 1 of 1 left"));
+
+    // Act - Part 2 - Resume
+    let _ = app.post_admin_issues("resume", &body).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The delivery has been resumed."));
+    assert!(html_page.contains("Delivered to 1"));
+}
+
+#[async_std::test]
+async fn cancelling_drops_the_remaining_deliveries() {
+    // Arrange
+    let app = spawn_app_with(|c| c.delivery.batch_size = 1).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = login_and_publish(&app).await;
+    let body = serde_json::json!({ "issue_id": issue_id });
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    try_execute_task(&app.db_pool, &app.email_client, &app.delivery_settings)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Cancel
+    let _ = app.post_admin_issues("cancel", &body).await;
+    assert!(queued_emails(&app).await.is_empty());
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The delivery has been cancelled."));
+    assert!(html_page.contains("Cancelled after 1 of 2 sent"));
+
+    // Act - Part 2 - There is no way back
+    let _ = app.post_admin_issues("resume", &body).await;
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The delivery was already cancelled."));
+    let _ = app.post_admin_issues("resend", &body).await;
+    let html_page = app
+        .get_admin_issues_html(&format!("/view?issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("The delivery of the issue was cancelled."));
+    assert!(queued_emails(&app).await.is_empty());
+}
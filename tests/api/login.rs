@@ -1,5 +1,7 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
 use http_types::headers;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
 
 #[async_std::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -40,3 +42,219 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[async_std::test]
+async fn login_attempts_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+    app.post_login(&serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password
+    }))
+    .await;
+
+    // Assert
+    let outcomes: Vec<String> = sqlx::query!(
+        "SELECT outcome FROM login_attempts WHERE user_id = $1 ORDER BY attempted_at",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.outcome)
+    .collect();
+    assert_eq!(outcomes, vec!["invalid_credentials", "success"]);
+}
+
+#[async_std::test]
+async fn an_account_is_locked_after_too_many_failed_logins() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_lockout.max_failed_attempts = 3).await;
+    sqlx::query!(
+        "UPDATE users SET email = 'owner@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..3 {
+        app.post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": "wrong-password"
+        }))
+        .await;
+    }
+
+    // Act - Part 1 - The right password doesn't help while locked
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Authentication failed"));
+
+    // Assert - only the attempt log knows the account was locked
+    let outcome =
+        sqlx::query!("SELECT outcome FROM login_attempts ORDER BY attempted_at DESC LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .outcome;
+    assert_eq!(outcome, "locked_out");
+
+    // Assert - the owner was warned
+    let email_request = &app.wait_for_emails(1).await[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "owner@example.com");
+}
+
+#[async_std::test]
+async fn lockouts_last_longer_every_time() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_lockout.max_failed_attempts = 1).await;
+    let lock = || async {
+        app.post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": "wrong-password"
+        }))
+        .await;
+        let locked_for = sqlx::query!(
+            "SELECT locked_until - now() as \"locked_for!\" FROM users WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .locked_for;
+        // Let the lock expire right away.
+        sqlx::query!(
+            "UPDATE users SET locked_until = now() WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        locked_for.microseconds
+    };
+
+    // Act
+    let first = lock().await;
+    let second = lock().await;
+
+    // Assert
+    assert!(second > first * 3 / 2);
+}
+
+#[async_std::test]
+async fn a_successful_login_clears_failed_attempts() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_lockout.max_failed_attempts = 2).await;
+    let wrong_login = serde_json::json!({
+        "username": app.test_user.username,
+        "password": "wrong-password"
+    });
+    let right_login = serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password
+    });
+    app.post_login(&wrong_login).await;
+    app.post_login(&right_login).await;
+    app.post_logout().await;
+
+    // Act
+    app.post_login(&wrong_login).await;
+    let response = app.post_login(&right_login).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[async_std::test]
+async fn you_must_be_logged_in_to_see_login_activity() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_login_activity().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn login_activity_lists_recent_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": "<b>mallory</b>",
+        "password": "random-password"
+    }))
+    .await;
+    app.post_login(&serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password
+    }))
+    .await;
+
+    // Act
+    let html_page = app.get_login_activity_html().await;
+
+    // Assert
+    assert!(html_page.contains("&lt;b&gt;mallory&lt;/b&gt;"));
+    assert!(html_page.contains("invalid_credentials"));
+    assert!(html_page.contains(&app.test_user.username));
+    assert!(html_page.contains("success"));
+}
+
+#[async_std::test]
+async fn weaker_password_hashes_are_upgraded_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let credentials = serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password
+    });
+
+    // Act
+    let response = app.post_login(&credentials).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert - the hash is replaced in the background.
+    let mut password_hash = String::new();
+    for _ in 0..50 {
+        password_hash = sqlx::query!(
+            "SELECT password_hash FROM users WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash;
+        if password_hash.contains("m=15000,t=2,p=1") {
+            break;
+        }
+        async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(password_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+    let response = app.post_login(&credentials).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
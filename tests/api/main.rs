@@ -1,8 +1,21 @@
 mod admin_dashboard;
+mod admin_users;
+mod api_tokens;
+mod api_v1;
 mod change_password;
+mod consent;
+mod csrf;
 mod health_check;
 mod helpers;
+mod issues;
 mod login;
 mod newsletter;
+mod password_reset;
+mod rate_limit;
+mod roles;
+mod sessions;
+mod subscriber_data;
+mod subscribers_export;
 mod subscriptions;
 mod subscriptions_confirm;
+mod two_factor;
@@ -1,12 +1,16 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, Subscription, TestApp};
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    spawn_app_with, PostmarkBatch, TestApp,
+};
 use async_std::prelude::FutureExt;
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
 use std::time::Duration;
 use surf::StatusCode;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::idempotency::{
+    delete_expired_keys, release_key, try_processing, IdempotencyKey, NextAction,
+};
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 
 #[async_std::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -47,9 +51,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatch::accept_all())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -84,9 +88,17 @@ async fn newsletters_returns_400_for_invalid_data() {
             "missing title",
         ),
         (
-            serde_json::json!({"title": "Newsletter!"}),
+            serde_json::json!({"title": "Newsletter!", "idempotency_key": uuid::Uuid::new_v4().to_string()}),
             "missing content",
         ),
+        (
+            serde_json::json!({"title": "Newsletter!", "text_content": "Newsletter body as plain text", "html_content": "<p>Newsletter body as HTML</p>"}),
+            "missing idempotency key",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!", "text_content": "Newsletter body as plain text", "html_content": "<p>Newsletter body as HTML</p>", "idempotency_key": ""}),
+            "empty idempotency key",
+        ),
     ];
     // Act - Part 1 - Login
     let login_body =
@@ -135,9 +147,9 @@ async fn basic_newsletter_published() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatch::accept_all())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -170,9 +182,9 @@ async fn newsletter_creation_is_idempotent() {
         serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
     let _ = app.post_login(&login_body).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatch::accept_all())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -212,11 +224,15 @@ async fn concurrent_form_submission_is_handled_gracefully() {
         serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
     let _ = app.post_login(&login_body).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         // Setting a long delay to ensure that the second request
         // arrives before the first one completes.
-        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!([{"ErrorCode": 0, "Message": "OK"}]))
+                .set_delay(Duration::from_secs(2)),
+        )
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -239,40 +255,426 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     app.dispatch_all_pending_emails().await;
 }
 
-/// Use the public API of the application under test to create an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = Subscription {
-        name: Some(name),
-        email: Some(email),
+#[async_std::test]
+async fn reusing_an_idempotency_key_for_another_issue_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Another newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::UnprocessableEntity);
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 1);
+}
+
+#[async_std::test]
+async fn expired_idempotency_keys_are_forgotten() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let _ = app.post_newsletters(newsletter_request_body.clone()).await;
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let deleted = delete_expired_keys(&app.db_pool, Duration::from_secs(86400))
+        .await
+        .unwrap();
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(deleted, 1);
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 2);
+}
+
+/// Claim `idempotency_key` for the test user, as a request still in progress would.
+async fn claim_idempotency_key(app: &TestApp, idempotency_key: &str, claimed_at: &str) {
+    sqlx::query(&format!(
+        "INSERT INTO idempotency (user_id, idempotency_key, created_at) \
+         VALUES ($1, $2, now() - interval '{claimed_at}')"
+    ))
+    .bind(app.test_user.user_id)
+    .bind(idempotency_key)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn newsletter_body(idempotency_key: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key
+    })
+}
+
+#[async_std::test]
+async fn duplicates_of_a_request_in_progress_get_a_409() {
+    // Arrange
+    let app = spawn_app_with(|c| c.idempotency.in_flight_wait_milliseconds = 0).await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    claim_idempotency_key(&app, &idempotency_key, "0 seconds").await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Conflict);
+    assert_eq!(response.header("Retry-After").unwrap().as_str(), "1");
+}
+
+#[async_std::test]
+async fn abandoned_requests_can_be_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    claim_idempotency_key(&app, &idempotency_key, "1 hour").await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[async_std::test]
+async fn taken_over_claims_cannot_release_the_key() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key: IdempotencyKey = uuid::Uuid::new_v4().to_string().try_into().unwrap();
+    let claim = |abandoned_after| {
+        try_processing(
+            &app.db_pool,
+            &idempotency_key,
+            app.test_user.user_id,
+            "hash",
+            abandoned_after,
+        )
     };
-    let _mock_guard = Mock::given(path("/email"))
+    let abandoned_claim = match claim(Duration::ZERO).await.unwrap() {
+        NextAction::StartProcessing(_, claim_id) => claim_id,
+        _ => panic!("The key should have been claimed."),
+    };
+    assert!(matches!(
+        claim(Duration::ZERO).await.unwrap(),
+        NextAction::StartProcessing(..)
+    ));
+
+    // Act
+    release_key(
+        &app.db_pool,
+        &idempotency_key,
+        app.test_user.user_id,
+        abandoned_claim,
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert!(matches!(
+        claim(Duration::from_secs(3600)).await.unwrap(),
+        NextAction::WaitInProgress
+    ));
+}
+
+#[async_std::test]
+async fn failed_requests_release_their_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "idempotency_key": idempotency_key
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BadRequest);
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(&idempotency_key))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 1);
+}
+
+#[async_std::test]
+async fn the_worker_delivers_newsletters_as_soon_as_they_are_published() {
+    // Arrange
+    let mut configuration = None;
+    let app = spawn_app_with(|c| {
+        // Only a notification can wake the worker up within the test.
+        c.delivery.fallback_poll_interval_seconds = 3600;
+        configuration = Some(c.clone());
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(PostmarkBatch::accept_all())
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
-    let resp = app.post_subscriptions(&body).await;
-    if resp.status().is_client_error() || resp.status().is_server_error() {
-        panic!("post subscripitons during create_unconfirmed_subscriber shouldn't failed");
+    async_std::task::spawn(run_worker_until_stopped(configuration.unwrap()));
+    // Give the worker the time to find the queue empty.
+    async_std::task::sleep(Duration::from_millis(500)).await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    async_std::future::timeout(Duration::from_secs(5), queue_drained(&app))
+        .await
+        .expect("The worker should deliver without waiting for the next poll.");
+}
+
+#[async_std::test]
+async fn the_worker_delivers_concurrently_within_the_rate_limit() {
+    // Arrange
+    let mut configuration = None;
+    let app = spawn_app_with(|c| {
+        c.delivery.concurrency = 4;
+        c.delivery.max_sends_per_second = Some(20);
+        // One request per email, for the workers to share them out.
+        c.delivery.batch_size = 1;
+        configuration = Some(c.clone());
+    })
+    .await;
+    for _ in 0..6 {
+        create_confirmed_subscriber(&app).await;
     }
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch::accept_all())
+        .expect(6)
+        .mount(&app.email_server)
+        .await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
 
-    let email_request = app
-        .email_server
-        .received_requests()
+    // Act
+    let started = std::time::Instant::now();
+    async_std::task::spawn(run_worker_until_stopped(configuration.unwrap()));
+    async_std::future::timeout(Duration::from_secs(5), queue_drained(&app))
         .await
-        .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
+        .expect("The worker should deliver every issue.");
+
+    // Assert
+    // 6 sends at 20 per second take at least 250ms.
+    assert!(started.elapsed() >= Duration::from_millis(250));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    let resp = surf::get(confirmation_link.html).await.unwrap();
-    if resp.status().is_client_error() || resp.status().is_server_error() {
-        panic!("post subscripitons during create_unconfirmed_subscriber shouldn't failed");
+async fn queue_drained(app: &TestApp) {
+    loop {
+        let pending = sqlx::query!("SELECT count(*) AS \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        if pending == 0 {
+            break;
+        }
+        async_std::task::sleep(Duration::from_millis(50)).await;
     }
 }
+
+#[async_std::test]
+async fn refused_deliveries_are_retried_then_given_up_on() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.delivery.max_attempts = 2;
+        c.delivery.redelivery_delay_seconds = 0;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let refused = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch {
+            rejected: vec![refused.clone()],
+            error_code: 429,
+        })
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT count(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 0);
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, error FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.subscriber_email, refused);
+    assert!(dead_letter.error.contains("Rate limit exceeded"));
+    let html_page = app.get_admin_issues_html("").await;
+    assert!(html_page.contains("Delivered to 1, 1 failed"));
+}
+
+#[async_std::test]
+async fn inactive_recipients_are_given_up_on_right_away() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.delivery.max_attempts = 5;
+        c.delivery.redelivery_delay_seconds = 0;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    let refused = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatch {
+            rejected: vec![refused.clone()],
+            error_code: 406,
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, error FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.subscriber_email, refused);
+    assert!(dead_letter.error.contains("Inactive recipient"));
+}
+
+#[async_std::test]
+async fn deliveries_are_retried_later_when_the_batch_fails() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    let _ = app.post_login(&login_body).await;
+    let response = app
+        .post_newsletters(newsletter_body(&uuid::Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"later!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.later);
+}
@@ -0,0 +1,277 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use std::time::{Duration, Instant};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const FLASH: &str =
+    "If the account exists and has an email address, a link to reset its password is on its way.";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'admin@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Ask for a reset link for the test user and return the link we emailed.
+async fn request_reset_link(app: &TestApp) -> surf::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "username": app.test_user.username }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let email_request = app.wait_for_emails(1).await.pop().unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+fn reset_token(link: &surf::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .to_string()
+}
+
+#[async_std::test]
+async fn a_reset_link_is_emailed_to_the_user() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+
+    // Act
+    let link = request_reset_link(&app).await;
+
+    // Assert
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(FLASH));
+    let response = surf::get(link).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[async_std::test]
+async fn unknown_usernames_get_the_same_response_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "username": "nobody" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(FLASH));
+}
+
+#[async_std::test]
+async fn the_response_does_not_wait_for_the_reset_email() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let started = Instant::now();
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "username": app.test_user.username }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    app.wait_for_emails(1).await;
+}
+
+#[async_std::test]
+async fn reset_tokens_are_stored_hashed() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+
+    // Act
+    let link = request_reset_link(&app).await;
+
+    // Assert
+    let token_hash = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_hash;
+    assert_ne!(token_hash, reset_token(&link));
+}
+
+#[async_std::test]
+async fn a_reset_link_sets_a_new_password_once() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let body = serde_json::json!({
+        "token": reset_token(&link),
+        "new_password": "a-brand-new-password",
+        "new_password_check": "a-brand-new-password"
+    });
+
+    // Act - Part 1 - Reset
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your password has been reset, you can now log in."));
+
+    // Act - Part 2 - Log in with the new password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": "a-brand-new-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 3 - Use the link again
+    let response = app.post_reset_password(&body).await;
+
+    // Assert
+    assert_eq!(response.status(), 401);
+}
+
+#[async_std::test]
+async fn expired_reset_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET created_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": reset_token(&link),
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 401);
+    let response = surf::get(link).await.unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[async_std::test]
+async fn new_passwords_must_match() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let token = reset_token(&link);
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token,
+            "new_password": "a-brand-new-password",
+            "new_password_check": "another-new-password"
+        }))
+        .await;
+
+    // Assert
+    let expected_location = format!("/login/reset?token={token}");
+    assert_is_redirect_to(&response, &expected_location);
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", app.address, expected_location))
+        .recv_string()
+        .await
+        .unwrap();
+    assert!(html_page
+        .contains("You entered two different new passwords - the field values must match."));
+}
+
+#[async_std::test]
+async fn new_passwords_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let token = reset_token(&link);
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token,
+            "new_password": "password1234",
+            "new_password_check": "password1234"
+        }))
+        .await;
+
+    // Assert
+    let expected_location = format!("/login/reset?token={token}");
+    assert_is_redirect_to(&response, &expected_location);
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", app.address, expected_location))
+        .recv_string()
+        .await
+        .unwrap();
+    assert!(html_page.contains("This password appeared in a data breach, please pick another one."));
+}
+
+#[async_std::test]
+async fn resetting_a_password_logs_the_user_out_everywhere() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let link = request_reset_link(&app).await;
+
+    // Act
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": reset_token(&link),
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let revoked_at = sqlx::query!(
+        "SELECT sessions_revoked_at FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .sessions_revoked_at;
+    assert!(revoked_at.is_some());
+}
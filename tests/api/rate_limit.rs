@@ -0,0 +1,200 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app_with};
+use surf::Url;
+use zero2prod::configuration::TokenBucketSettings;
+
+const TWO_PER_MINUTE: TokenBucketSettings = TokenBucketSettings {
+    capacity: 2,
+    refill_interval_milliseconds: 60_000,
+};
+
+#[async_std::test]
+async fn login_is_rate_limited_per_username() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.login_per_username = TWO_PER_MINUTE).await;
+    let login_body = serde_json::json!({
+        "username": app.test_user.username,
+        "password": "wrong-password"
+    });
+    for _ in 0..2 {
+        let response = app.post_login(&login_body).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    // Act - even the right password is rejected once the bucket is empty
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+    let retry_after: u64 = response
+        .header("Retry-After")
+        .unwrap()
+        .as_str()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+}
+
+#[async_std::test]
+async fn login_limits_per_username_do_not_affect_other_usernames() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.login_per_username = TWO_PER_MINUTE).await;
+    let random_login = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    for _ in 0..2 {
+        app.post_login(&random_login).await;
+    }
+    let mut response = app.post_login(&random_login).await;
+    assert_eq!(response.status(), 429);
+    // Drain the body, the client reuses the connection.
+    response.body_string().await.unwrap();
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[async_std::test]
+async fn login_is_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.login_per_ip = TWO_PER_MINUTE).await;
+    for i in 0..2 {
+        app.post_login(&serde_json::json!({
+            "username": format!("username-{i}"),
+            "password": "random-password"
+        }))
+        .await;
+    }
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+    assert!(response.header("Retry-After").is_some());
+}
+
+#[async_std::test]
+async fn subscribe_is_rate_limited_per_ip_before_reaching_the_handler() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.subscribe_per_ip = TWO_PER_MINUTE).await;
+    let body = serde_json::json!({"name": "le guin", "email": "not-an-email"});
+    for _ in 0..2 {
+        let response = app.post_subscribe_form(&body).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    // Act
+    let response = app.post_subscribe_form(&body).await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+    assert!(response.header("Retry-After").is_some());
+}
+
+#[async_std::test]
+async fn forwarding_headers_do_not_dodge_per_ip_limits_without_a_proxy() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.subscribe_per_ip = TWO_PER_MINUTE).await;
+    let body = serde_json::json!({"name": "le guin", "email": "not-an-email"});
+    let mut last_status = 0;
+
+    // Act
+    for i in 0..3 {
+        let url = Url::parse(&format!("{}/subscriptions", app.address)).unwrap();
+        let mut request = surf::post(url)
+            .header("X-Forwarded-For", format!("10.0.0.{i}"))
+            .header("Forwarded", format!("for=10.0.0.{i}"))
+            .build();
+        request.body_form(&body).unwrap();
+        let mut response = app.api_client.send(request).await.unwrap();
+        response.body_string().await.unwrap();
+        last_status = response.status() as u16;
+    }
+
+    // Assert
+    assert_eq!(last_status, 429);
+}
+
+#[async_std::test]
+async fn the_proxy_appended_address_is_used_behind_a_proxy() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.behind_proxy = true;
+        c.rate_limits.subscribe_per_ip = TWO_PER_MINUTE;
+    })
+    .await;
+    let body = serde_json::json!({"name": "le guin", "email": "not-an-email"});
+    let mut statuses = vec![];
+
+    // Act - the client controls the first entry, the proxy appends the last one
+    for i in 0..3 {
+        let url = Url::parse(&format!("{}/subscriptions", app.address)).unwrap();
+        let mut request = surf::post(url)
+            .header("X-Forwarded-For", format!("10.0.0.{i}, 192.0.2.1"))
+            .build();
+        request.body_form(&body).unwrap();
+        let mut response = app.api_client.send(request).await.unwrap();
+        response.body_string().await.unwrap();
+        statuses.push(response.status() as u16);
+    }
+
+    // Assert
+    assert_eq!(statuses, vec![400, 400, 429]);
+}
+
+#[async_std::test]
+async fn confirmations_are_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limits.confirm_per_ip = TWO_PER_MINUTE).await;
+    for _ in 0..2 {
+        let response = app.get_subscriptions_confirm("").await;
+        assert_eq!(response.status(), 400);
+    }
+
+    // Act
+    let response = app.get_subscriptions_confirm("").await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+}
+
+#[async_std::test]
+async fn rate_limits_fall_back_to_memory_when_redis_is_unreachable() {
+    // Arrange - nothing listens on port 1
+    let app = spawn_app_with(|c| {
+        c.redis_uri = secrecy::Secret::new("redis://127.0.0.1:1".to_string());
+        c.rate_limits.confirm_per_ip = TWO_PER_MINUTE;
+    })
+    .await;
+    for _ in 0..2 {
+        // Sessions can't be saved without redis either, only the rate limit matters here.
+        let mut response = app.get_subscriptions_confirm("").await;
+        assert_ne!(response.status(), 429);
+        response.body_string().await.unwrap();
+    }
+
+    // Act
+    let response = app.get_subscriptions_confirm("").await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+}
@@ -0,0 +1,229 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use surf::StatusCode;
+
+/// Admin pages reached with a GET and the least role that may see them.
+const ADMIN_PAGES: &[(&str, &str)] = &[
+    ("/admin/dashboard", "viewer"),
+    ("/admin/password", "viewer"),
+    ("/admin/sessions", "viewer"),
+    ("/admin/api_tokens", "viewer"),
+    ("/admin/newsletters", "editor"),
+    ("/admin/issues", "editor"),
+    ("/admin/subscribers/export?format=csv", "owner"),
+    ("/admin/subscribers/data", "owner"),
+    ("/admin/login_activity", "owner"),
+    ("/admin/users", "owner"),
+];
+
+fn rank(role: &str) -> u8 {
+    match role {
+        "viewer" => 0,
+        "editor" => 1,
+        "owner" => 2,
+        _ => unreachable!(),
+    }
+}
+
+async fn login_as(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::with_role(role);
+    user.store(&app.db_pool).await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    user
+}
+
+async fn get(app: &TestApp, path: &str) -> surf::Response {
+    let mut response = app
+        .api_client
+        .get(format!("{}{}", app.address, path))
+        .await
+        .expect("Failed to execute request.");
+    // Drain the body so the connection can be reused for the next request.
+    response.body_bytes().await.unwrap();
+    response
+}
+
+#[async_std::test]
+async fn admin_pages_are_only_reachable_with_the_required_role() {
+    for role in ["viewer", "editor", "owner"] {
+        // Arrange
+        let app = spawn_app().await;
+        login_as(&app, role).await;
+
+        for (path, required) in ADMIN_PAGES {
+            // Act
+            let response = get(&app, path).await;
+
+            // Assert
+            if rank(role) >= rank(required) {
+                assert_eq!(
+                    response.status(),
+                    StatusCode::Ok,
+                    "A {role} should be able to reach {path}."
+                );
+            } else {
+                assert_eq!(
+                    response.status(),
+                    StatusCode::Forbidden,
+                    "A {role} should not be able to reach {path}."
+                );
+            }
+        }
+    }
+}
+
+#[async_std::test]
+async fn forbidden_pages_explain_why() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, "viewer").await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/users", app.address))
+        .recv_string()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("You don't have permission to access this page."));
+}
+
+#[async_std::test]
+async fn viewers_cannot_publish_a_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, "viewer").await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::Forbidden);
+    let issues = sqlx::query!("SELECT count(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, Some(0));
+}
+
+#[async_std::test]
+async fn editors_can_publish_a_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, "editor").await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[async_std::test]
+async fn only_owners_can_manage_users_and_subscribers() {
+    for role in ["viewer", "editor"] {
+        // Arrange
+        let app = spawn_app().await;
+        login_as(&app, role).await;
+        let user = serde_json::json!({ "user_id": app.test_user.user_id });
+        let requests = [
+            ("deactivate", user.clone()),
+            ("activate", user.clone()),
+            ("delete", user.clone()),
+            (
+                "role",
+                serde_json::json!({ "user_id": app.test_user.user_id, "role": "viewer" }),
+            ),
+            (
+                "invite",
+                serde_json::json!({ "email": "someone@example.com", "role": "owner" }),
+            ),
+        ];
+
+        // Act & Assert
+        for (action, body) in requests {
+            let mut response = app.post_admin_users(action, &body).await;
+            response.body_bytes().await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::Forbidden,
+                "A {role} should not be able to {action} users."
+            );
+        }
+        let mut response = app
+            .post_admin_erase_subscriber(&serde_json::json!({ "email": "someone@example.com" }))
+            .await;
+        response.body_bytes().await.unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+    }
+}
+
+#[async_std::test]
+async fn owners_can_change_the_role_of_a_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "role",
+            &serde_json::json!({ "user_id": viewer.user_id, "role": "editor" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The user is now editor."));
+    let role = sqlx::query!("SELECT role FROM users WHERE user_id = $1", viewer.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .role;
+    assert_eq!(role, "editor");
+}
+
+#[async_std::test]
+async fn the_dashboard_only_links_to_pages_the_role_allows() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, "editor").await;
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(html_page.contains("/admin/newsletters"));
+    assert!(!html_page.contains("/admin/users"));
+    assert!(!html_page.contains("/admin/subscribers"));
+}
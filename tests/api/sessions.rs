@@ -0,0 +1,371 @@
+use crate::helpers::{
+    assert_is_redirect_to, extract_csrf_token, spawn_app, spawn_app_with, TestApp,
+};
+use surf::StatusCode;
+use zero2prod::authentication::delete_expired_sessions;
+use zero2prod::configuration::{get_configuration, SameSitePolicy};
+
+/// Log the test user in from another browser, returning its client.
+async fn login_elsewhere(app: &TestApp, user_agent: &str) -> surf::Client {
+    let client = surf::client().with(surf_cookie_middleware::CookieMiddleware::new());
+    let login_page = client
+        .get(format!("{}/login", app.address))
+        .recv_string()
+        .await
+        .unwrap();
+    let mut request = surf::post(format!("{}/login", app.address))
+        .header("User-Agent", user_agent)
+        .build();
+    request
+        .body_form(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password,
+            "csrf_token": extract_csrf_token(&login_page)
+        }))
+        .unwrap();
+    let mut response = client
+        .send(request)
+        .await
+        .expect("Failed to execute request.");
+    response.body_bytes().await.unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+async fn get_dashboard(app: &TestApp, client: &surf::Client) -> surf::Response {
+    let mut response = client
+        .get(format!("{}/admin/dashboard", app.address))
+        .await
+        .expect("Failed to execute request.");
+    response.body_bytes().await.unwrap();
+    response
+}
+
+async fn login(app: &TestApp) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+async fn session_ids(app: &TestApp) -> Vec<uuid::Uuid> {
+    sqlx::query!(
+        "SELECT session_id FROM user_sessions WHERE user_id = $1 ORDER BY created_at",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.session_id)
+    .collect()
+}
+
+#[async_std::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn the_sessions_page_lists_every_session() {
+    // Arrange
+    let app = spawn_app().await;
+    login_elsewhere(&app, "<b>Other browser</b>").await;
+    login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_sessions_html().await;
+
+    // Assert
+    assert!(html_page.contains("&lt;b&gt;Other browser&lt;/b&gt;"));
+    assert!(html_page.contains("127.0.0.1"));
+    assert!(html_page.contains("This session"));
+    assert_eq!(html_page.matches("name=\"session_id\"").count(), 1);
+}
+
+#[async_std::test]
+async fn expired_sessions_are_not_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    login_elsewhere(&app, "Other browser").await;
+    login(&app).await;
+    sqlx::query!(
+        "UPDATE user_sessions SET last_seen_at = now() - interval '1 day' \
+        WHERE user_agent = 'Other browser'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app.get_admin_sessions_html().await;
+
+    // Assert
+    assert!(!html_page.contains("Other browser"));
+    assert!(html_page.contains("This session"));
+}
+
+#[async_std::test]
+async fn expired_sessions_are_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    login_elsewhere(&app, "Idle browser").await;
+    login_elsewhere(&app, "Old browser").await;
+    login(&app).await;
+    sqlx::query!(
+        "UPDATE user_sessions SET last_seen_at = now() - interval '1 day' \
+        WHERE user_agent = 'Idle browser'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE user_sessions SET created_at = now() - interval '30 days' \
+        WHERE user_agent = 'Old browser'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let settings = get_configuration().unwrap().session;
+
+    // Act
+    let deleted = delete_expired_sessions(&settings, &app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(deleted, 2);
+    assert_eq!(session_ids(&app).await.len(), 1);
+}
+
+#[async_std::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let other = login_elsewhere(&app, "Other browser").await;
+    login(&app).await;
+    let other_session_id = session_ids(&app).await[0];
+
+    // Act
+    let response = app
+        .post_admin_sessions(
+            "revoke",
+            &serde_json::json!({ "session_id": other_session_id }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("The session has been revoked."));
+    assert_is_redirect_to(&get_dashboard(&app, &other).await, "/login");
+    assert_eq!(app.get_admin_dashboard().await.status(), StatusCode::Ok);
+}
+
+#[async_std::test]
+async fn sessions_of_other_users_cannot_be_revoked() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_user = crate::helpers::TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    let other_session_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, created_at, last_seen_at)
+        VALUES ($1, $2, now(), now())
+        "#,
+        other_session_id,
+        other_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_admin_sessions(
+            "revoke",
+            &serde_json::json!({ "session_id": other_session_id }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("The session no longer exists."));
+    let left = sqlx::query!(
+        "SELECT count(*) AS count FROM user_sessions WHERE session_id = $1",
+        other_session_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(left.count, Some(1));
+}
+
+#[async_std::test]
+async fn all_other_sessions_can_be_revoked_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let first = login_elsewhere(&app, "First browser").await;
+    let second = login_elsewhere(&app, "Second browser").await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_sessions("revoke_others", &serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("2 other sessions have been revoked."));
+    assert_is_redirect_to(&get_dashboard(&app, &first).await, "/login");
+    assert_is_redirect_to(&get_dashboard(&app, &second).await, "/login");
+    assert_eq!(session_ids(&app).await.len(), 1);
+}
+
+#[async_std::test]
+async fn changing_password_logs_out_the_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let other = login_elsewhere(&app, "Other browser").await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": app.test_user.password,
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    assert_is_redirect_to(&get_dashboard(&app, &other).await, "/login");
+    assert_eq!(app.get_admin_dashboard().await.status(), StatusCode::Ok);
+}
+
+#[async_std::test]
+async fn logging_out_removes_the_session() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_logout().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(session_ids(&app).await.is_empty());
+}
+
+#[async_std::test]
+async fn idle_sessions_expire() {
+    // Arrange
+    let app = spawn_app_with(|c| c.session.idle_timeout_seconds = 1).await;
+    login(&app).await;
+
+    // Act
+    async_std::task::sleep(std::time::Duration::from_millis(2100)).await;
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has expired, please log in again."));
+    assert!(session_ids(&app).await.is_empty());
+}
+
+#[async_std::test]
+async fn active_sessions_expire_after_their_absolute_lifetime() {
+    // Arrange
+    let app = spawn_app_with(|c| c.session.absolute_timeout_seconds = 2).await;
+    login(&app).await;
+
+    // Act
+    let mut statuses = vec![];
+    for _ in 0..6 {
+        let mut response = app.get_admin_dashboard().await;
+        response.body_bytes().await.unwrap();
+        statuses.push(response.status());
+        async_std::task::sleep(std::time::Duration::from_millis(500)).await;
+    }
+
+    // Assert
+    assert_eq!(statuses[0], StatusCode::Ok);
+    assert_eq!(statuses[5], StatusCode::SeeOther);
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your session has expired, please log in again."));
+}
+
+#[async_std::test]
+async fn the_session_cookie_follows_the_configuration() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.session.cookie_name = "zero2prod.sid".into();
+        c.session.cookie_secure = true;
+        c.session.cookie_same_site = SameSitePolicy::Strict;
+    })
+    .await;
+
+    // Act - the login form gives the visitor a session.
+    let response = app
+        .api_client
+        .get(format!("{}/login", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let cookies: Vec<_> = response
+        .header("Set-Cookie")
+        .unwrap()
+        .iter()
+        .map(|value| value.as_str().to_string())
+        .collect();
+    let session_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.starts_with("zero2prod.sid="))
+        .expect("No session cookie was set.");
+    assert!(session_cookie.contains("Secure"));
+    assert!(session_cookie.contains("SameSite=Strict"));
+    assert!(session_cookie.contains("HttpOnly"));
+}
+
+#[async_std::test]
+async fn secure_cookies_cover_flash_messages_too() {
+    // Arrange
+    let app = spawn_app_with(|c| c.session.cookie_secure = true).await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let cookies: Vec<_> = response
+        .header("Set-Cookie")
+        .unwrap()
+        .iter()
+        .map(|value| value.as_str().to_string())
+        .collect();
+    assert!(cookies.iter().any(|cookie| cookie.starts_with("_flash=")));
+    assert!(cookies.iter().all(|cookie| cookie.contains("Secure")));
+}
@@ -0,0 +1,237 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, ConfirmationLinks, TestApp,
+};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+/// Ask for a data request through the public form and return the link we emailed.
+async fn request_data(app: &TestApp, email: &str, kind: &str) -> ConfirmationLinks {
+    let sent = app.email_server.received_requests().await.unwrap().len();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_data_request(&serde_json::json!({"email": email, "kind": kind}))
+        .await;
+    assert_is_redirect_to(&response, "/subscriptions/data");
+    let email_request = app.wait_for_emails(sent + 1).await.pop().unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+#[async_std::test]
+async fn data_requests_for_unknown_emails_look_the_same_and_send_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_data_request(&serde_json::json!({
+            "email": "ursula_le_guin@gmail.com",
+            "kind": "export"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/subscriptions/data");
+}
+
+#[async_std::test]
+async fn data_requests_with_invalid_kind_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_data_request(&serde_json::json!({
+            "email": "ursula_le_guin@gmail.com",
+            "kind": "sell"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 400);
+}
+
+#[async_std::test]
+async fn confirmed_export_link_returns_the_subscriber_data_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let links = request_data(&app, &email, "export").await;
+
+    // Act
+    let mut response = surf::get(links.html.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let export: serde_json::Value = response.body_json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], email.as_str());
+    assert_eq!(export["subscriber"]["status"], "confirmed");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+
+    let response = surf::get(links.html).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let audit = sqlx::query!("SELECT kind, requested_by FROM data_request_audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.kind, "export");
+    assert!(audit.requested_by.is_none());
+}
+
+#[async_std::test]
+async fn confirmed_erasure_removes_every_row_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let links = request_data(&app, &email, "erasure").await;
+
+    // Act - Part 1 - Opening the link doesn't erase anything yet
+    let mut response = surf::get(links.html.clone()).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response
+        .body_string()
+        .await
+        .unwrap()
+        .contains(r#"action="/subscriptions/data/erase""#));
+    let token = links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "data_request_token")
+        .unwrap()
+        .1
+        .to_string();
+    let remaining = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 1);
+
+    // Act - Part 2 - Confirm the erasure
+    let response = app
+        .post_erase_data(&serde_json::json!({ "data_request_token": token }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let subscriptions = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, 0);
+    let tokens = sqlx::query!("SELECT count(*) as \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+    let audit = sqlx::query!("SELECT kind FROM data_request_audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.kind, "erasure");
+
+    // The token is single-use.
+    let response = app
+        .post_erase_data(&serde_json::json!({ "data_request_token": token }))
+        .await;
+    assert_eq!(response.status(), 401);
+}
+
+#[async_std::test]
+async fn you_must_be_logged_in_to_export_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .get_admin_subscriber_export("ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn the_email_echoed_back_to_admins_is_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act
+    let response = app
+        .get_admin_subscriber_export("<script>alert(1)</script>")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/data");
+    let html_page = app.get_admin_subscriber_data_html().await;
+
+    // Assert
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("No subscriber found for &lt;script&gt;alert(1)&lt;/script&gt;."));
+}
+
+#[async_std::test]
+async fn admins_can_export_and_erase_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act - Part 1 - Export
+    let mut response = app.get_admin_subscriber_export(&email).await;
+    assert_eq!(response.status(), 200);
+    let export: serde_json::Value = response.body_json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], email.as_str());
+
+    // Act - Part 2 - Erase
+    let response = app
+        .post_admin_erase_subscriber(&serde_json::json!({ "email": email }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/data");
+    let html_page = app.get_admin_subscriber_data_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The data of {email} has been erased.</i></p>"
+    )));
+
+    // Assert
+    let subscriptions = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, 0);
+    let audit = sqlx::query!(
+        "SELECT kind, requested_by FROM data_request_audit_log ORDER BY performed_at DESC LIMIT 1"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.kind, "erasure");
+    assert_eq!(audit.requested_by, Some(app.test_user.user_id));
+
+    // Act - Part 3 - The subscriber is gone
+    let response = app.get_admin_subscriber_export(&email).await;
+    assert_is_redirect_to(&response, "/admin/subscribers/data");
+}
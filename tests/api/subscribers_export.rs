@@ -0,0 +1,98 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
+
+#[async_std::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn csv_export_contains_every_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act
+    let mut response = app.get_subscribers_export("format=csv").await;
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    assert!(response
+        .header("Content-Type")
+        .unwrap()
+        .as_str()
+        .starts_with("text/csv"));
+    let body = response.body_string().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(
+        lines[0],
+        "id,email,name,status,subscribed_at,consent_text_version,consent_confirmed_at"
+    );
+    assert_eq!(lines.len(), 3);
+    let saved = sqlx::query!("SELECT id, email, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    for subscriber in saved {
+        assert!(lines.iter().any(|line| line
+            .starts_with(&format!("{},{},", subscriber.id, subscriber.email))
+            && line.contains(&subscriber.status)));
+    }
+}
+
+#[async_std::test]
+async fn json_export_can_be_filtered_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act
+    let mut response = app
+        .get_subscribers_export("format=json&status=confirmed")
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let body = response.body_string().await.unwrap();
+    let records: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record["status"], "confirmed");
+    for field in ["id", "email", "name", "subscribed_at"] {
+        assert!(record.get(field).is_some(), "missing field {field}");
+    }
+}
+
+#[async_std::test]
+async fn export_rejects_unknown_formats() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body =
+        serde_json::json!({"username": app.test_user.username, "password": app.test_user.password});
+    app.post_login(&login_body).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=xml").await;
+
+    // Assert
+    assert_eq!(response.status(), 400);
+}
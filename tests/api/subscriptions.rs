@@ -1,7 +1,5 @@
-use crate::helpers::{spawn_app, Subscription};
-use serde::{Deserialize, Serialize};
-use surf::http::{Method, Url};
-use wiremock::matchers::{method, path};
+use crate::helpers::{spawn_app, spawn_app_with, Subscription};
+use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 #[async_std::test]
@@ -97,11 +95,6 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 
 #[async_std::test]
 async fn subscribe_returns_a_400_when_fields_are_present_but_empty() {
-    #[derive(Clone, Serialize, Deserialize)]
-    struct Subscription {
-        name: Option<String>,
-        email: Option<String>,
-    }
     // Arrange
     let app = spawn_app().await;
     let test_cases = vec![
@@ -127,18 +120,9 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_empty() {
             "invalid email",
         ),
     ];
-    let url =
-        Url::parse(&format!("{}/subscriptions", app.address)).expect("failed to parse url address");
-    let client = surf::client();
-
     for (body, description) in test_cases {
         // Act
-        let mut request = surf::Request::builder(Method::Post, url.clone()).build();
-        request.body_form(&body).unwrap();
-        let response = client
-            .send(request)
-            .await
-            .expect("Failed to execute request.");
+        let response = app.post_subscriptions(&body).await;
 
         // Assert
         assert_eq!(
@@ -219,3 +203,157 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status(), 500);
 }
+
+#[async_std::test]
+async fn subscribe_rejects_submissions_filling_the_honeypot() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let mut body = app.get_subscribe_form_fields().await;
+    body["name"] = "le guin".into();
+    body["email"] = "ursula_le_guin@gmail.com".into();
+    body["website"] = "https://spam.example.com".into();
+
+    // Act
+    let response = app.post_subscribe_form(&body).await;
+
+    // Assert
+    assert_eq!(response.status(), 400);
+}
+
+#[async_std::test]
+async fn subscribe_rejects_submissions_without_a_valid_form_token() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let mut tampered = app.get_subscribe_form_fields().await;
+    tampered["form_issued_at"] = "0".into();
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"}),
+            "missing form token",
+        ),
+        (
+            serde_json::json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com",
+                "form_issued_at": tampered["form_issued_at"],
+                "form_signature": tampered["form_signature"],
+            }),
+            "tampered form token",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscribe_form(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had a {}.",
+            description
+        );
+    }
+}
+
+#[async_std::test]
+async fn subscribe_is_rate_limited_per_target_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    for _ in 0..3 {
+        sqlx::query!(
+            "INSERT INTO subscription_attempts (email, client_ip, attempted_at)
+            VALUES ('ursula_le_guin@gmail.com', '10.0.0.1', now())"
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions(&Subscription {
+            name: Some("le guin".to_string()),
+            email: Some("ursula_le_guin@gmail.com".to_string()),
+        })
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+    assert!(response.header("Retry-After").is_some());
+}
+
+#[async_std::test]
+async fn subscribe_is_rate_limited_per_client_ip() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    for i in 0..10 {
+        sqlx::query!(
+            "INSERT INTO subscription_attempts (email, client_ip, attempted_at)
+            VALUES ($1, '127.0.0.1', now())",
+            format!("someone_{i}@gmail.com")
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions(&Subscription {
+            name: Some("le guin".to_string()),
+            email: Some("ursula_le_guin@gmail.com".to_string()),
+        })
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 429);
+}
+
+#[async_std::test]
+async fn concurrent_attempts_cannot_go_over_the_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscriptions.max_attempts_per_email = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = Subscription {
+        name: Some("le guin".to_string()),
+        email: Some("ursula_le_guin@gmail.com".to_string()),
+    };
+
+    // Act
+    let responses = futures::future::join_all((0..5).map(|_| app.post_subscriptions(&body))).await;
+
+    // Assert
+    let accepted = responses.iter().filter(|r| r.status() == 200).count();
+    assert_eq!(accepted, 1);
+    let attempts = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscription_attempts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts.count, 1);
+}
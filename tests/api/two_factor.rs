@@ -0,0 +1,290 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zero2prod::authentication::totp_code;
+
+async fn login(app: &TestApp) -> surf::Response {
+    app.post_login(&serde_json::json!({
+        "username": app.test_user.username,
+        "password": app.test_user.password
+    }))
+    .await
+}
+
+/// The code for `secret`, `steps` time steps from now.
+fn code(secret: &[u8], steps: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    totp_code(secret, (now + steps * 30) as u64)
+}
+
+fn extract_between<'a>(html: &'a str, start: &str, end: &str) -> Vec<&'a str> {
+    html.split(start)
+        .skip(1)
+        .map(|s| s.split(end).next().unwrap())
+        .collect()
+}
+
+/// Turn two-factor authentication on for the logged in test user.
+///
+/// Returns the secret and the recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (Vec<u8>, Vec<String>) {
+    let response = app
+        .post_admin_two_factor("enroll", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_admin_two_factor_html().await;
+    let key = extract_between(&html_page, "Key: <code>", "</code>")[0];
+    let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, key).unwrap();
+    let mut response = app
+        .post_admin_two_factor("confirm", &serde_json::json!({ "code": code(&secret, 0) }))
+        .await;
+    assert_eq!(response.status(), 200);
+    let html_page = response.body_string().await.unwrap();
+    let recovery_codes = extract_between(&html_page, "<li><code>", "</code></li>")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    (secret, recovery_codes)
+}
+
+#[async_std::test]
+async fn two_factor_is_enabled_once_a_first_code_is_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    assert_is_redirect_to(&login(&app).await, "/admin/dashboard");
+    app.post_admin_two_factor("enroll", &serde_json::json!({}))
+        .await;
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("otpauth://totp/zero2prod"));
+
+    // Act - Part 1 - Wrong code
+    let response = app
+        .post_admin_two_factor("confirm", &serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("The code is invalid, please try again."));
+
+    // Act - Part 2 - Right code
+    let key = extract_between(&html_page, "Key: <code>", "</code>")[0].to_owned();
+    let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &key).unwrap();
+    let mut response = app
+        .post_admin_two_factor("confirm", &serde_json::json!({ "code": code(&secret, 0) }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status(), 200);
+    let html_page = response.body_string().await.unwrap();
+    assert_eq!(
+        extract_between(&html_page, "<li><code>", "</code></li>").len(),
+        10
+    );
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is on, 10 recovery codes left."));
+}
+
+#[async_std::test]
+async fn totp_secrets_are_stored_encrypted() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let (secret, _) = enable_two_factor(&app).await;
+
+    // Assert
+    let stored = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret
+    .unwrap();
+    assert!(!stored.windows(secret.len()).any(|w| w == secret));
+}
+
+#[async_std::test]
+async fn users_with_two_factor_need_a_code_to_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - Password only
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Wrong code
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("The code is invalid."));
+
+    // Act - Part 3 - Right code, the one of enrollment can't be used again
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": code(&secret, 1) }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status(), 200);
+}
+
+#[async_std::test]
+async fn failed_logins_are_only_cleared_once_the_second_factor_checks_out() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_lockout.max_failed_attempts = 3).await;
+    login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    app.post_login(&serde_json::json!({
+        "username": app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+    let failed_attempts = || async {
+        sqlx::query!(
+            "SELECT failed_login_attempts FROM users WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .failed_login_attempts
+    };
+
+    // Act - Part 1 - Password only
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    assert_eq!(failed_attempts().await, 1);
+
+    // Act - Part 2 - Right code
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": code(&secret, 1) }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(failed_attempts().await, 0);
+}
+
+#[async_std::test]
+async fn a_totp_code_cannot_be_used_twice() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    login(&app).await;
+    let body = serde_json::json!({ "code": code(&secret, 1) });
+    let response = app.post_login_two_factor(&body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act
+    login(&app).await;
+    let response = app.post_login_two_factor(&body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[async_std::test]
+async fn recovery_codes_can_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    let body = serde_json::json!({ "code": recovery_codes[0] });
+
+    // Act - Part 1 - Use it
+    login(&app).await;
+    let response = app.post_login_two_factor(&body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("9 recovery codes left"));
+    app.post_logout().await;
+
+    // Act - Part 2 - Use it again
+    login(&app).await;
+    let response = app.post_login_two_factor(&body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[async_std::test]
+async fn too_many_wrong_codes_require_the_password_again() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    login(&app).await;
+
+    // Act
+    for _ in 0..4 {
+        let response = app
+            .post_login_two_factor(&serde_json::json!({ "code": "000000" }))
+            .await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+    }
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": "000000" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Too many invalid codes, please log in again."));
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": code(&secret, 1) }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[async_std::test]
+async fn turning_two_factor_off_takes_the_current_password() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    enable_two_factor(&app).await;
+
+    // Act - Part 1 - Wrong password
+    let response = app
+        .post_admin_two_factor(
+            "disable",
+            &serde_json::json!({ "current_password": "wrong-password" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("The current password is incorrect"));
+
+    // Act - Part 2 - Right password
+    let response = app
+        .post_admin_two_factor(
+            "disable",
+            &serde_json::json!({ "current_password": app.test_user.password }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/two_factor");
+
+    // Assert
+    let html_page = app.get_admin_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is off."));
+    app.post_logout().await;
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}